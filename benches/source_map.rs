@@ -0,0 +1,85 @@
+#![feature(test)]
+
+extern crate test;
+
+use proc_macro0::{SourceMap, Span, TokenStream};
+use std::thread;
+use test::Bencher;
+
+const FILES: usize = 100_000;
+
+/// Parses `FILES` small files into a fresh source map, returning the map and
+/// the span of one token from each of the first, middle and last files.
+fn many_files() -> (SourceMap, [Span; 3]) {
+    let map = SourceMap::new();
+    let mut spans = Vec::new();
+    for i in 0..FILES {
+        let src = format!("fn f{i}() {{\n    g({i});\n}}\n");
+        let tokens: TokenStream = map.parse_token_stream(&src).unwrap();
+        if i == 0 || i == FILES / 2 || i == FILES - 1 {
+            spans.push(tokens.into_iter().nth(1).unwrap().span());
+        }
+    }
+    (map, [spans[0], spans[1], spans[2]])
+}
+
+#[bench]
+fn line_column(b: &mut Bencher) {
+    let (_map, spans) = many_files();
+    b.iter(|| {
+        for span in &spans {
+            test::black_box(span.start());
+            test::black_box(span.end());
+        }
+    });
+}
+
+#[bench]
+fn source_file(b: &mut Bencher) {
+    let (_map, spans) = many_files();
+    b.iter(|| {
+        for span in &spans {
+            test::black_box(span.source_file());
+        }
+    });
+}
+
+#[bench]
+fn join(b: &mut Bencher) {
+    let (_map, spans) = many_files();
+    b.iter(|| {
+        for span in &spans {
+            test::black_box(span.join(*span));
+        }
+    });
+}
+
+const THREADS: usize = 8;
+const PARSES: usize = 1_000;
+
+fn lex_many(count: usize) {
+    for i in 0..count {
+        let src = format!("fn f{i}() {{\n    g({i});\n}}\n");
+        test::black_box(src.parse::<TokenStream>().unwrap());
+    }
+}
+
+/// Lexes `THREADS * PARSES` strings on one thread, for comparison with
+/// `lex_parallel`.
+#[bench]
+fn lex_serial(b: &mut Bencher) {
+    b.iter(|| lex_many(THREADS * PARSES));
+}
+
+/// Lexes `PARSES` strings on each of `THREADS` threads at once, all into the
+/// default source map.
+#[bench]
+fn lex_parallel(b: &mut Bencher) {
+    b.iter(|| {
+        thread::scope(|scope| {
+            for _ in 0..THREADS {
+                scope.spawn(|| lex_many(PARSES));
+            }
+        });
+    });
+}
@@ -0,0 +1,339 @@
+//! Tree-aware differences between token streams, for `TokenStream::diff`.
+//!
+//! Each stream is compared as a sequence of token trees, looking through
+//! `Delimiter::None` groups the same way `PartialEq` does. The sequences are
+//! aligned with the linear space variant of Myers' algorithm, where any two
+//! groups with the same delimiter count as matching so that a change deep
+//! inside a group is reported there instead of as the whole group being
+//! replaced. Matched groups are then compared recursively, and unmatched
+//! tokens between two matches are paired up as changes where they are of the
+//! same kind.
+
+use crate::fallback::{self, Transparent};
+use crate::{Change, ChangeKind, Span, TokenTree};
+use std::fmt::{self, Display};
+
+pub(crate) fn diff(old: &[TokenTree], new: &[TokenTree]) -> Vec<Change> {
+    let mut differ = Differ {
+        old_path: Vec::new(),
+        new_path: Vec::new(),
+        changes: Vec::new(),
+    };
+    differ.streams(old, new);
+    differ.changes
+}
+
+struct Differ {
+    old_path: Vec<usize>,
+    new_path: Vec<usize>,
+    changes: Vec<Change>,
+}
+
+impl Differ {
+    fn streams(&mut self, old: &[TokenTree], new: &[TokenTree]) {
+        let old: Vec<&TokenTree> = Transparent::new(old).collect();
+        let new: Vec<&TokenTree> = Transparent::new(new).collect();
+        let mut deleted = Vec::new();
+        let mut inserted = Vec::new();
+        for op in align(&old, &new, |a, b| matches(a, b)) {
+            match op {
+                Op::Delete(i) => deleted.push(i),
+                Op::Insert(j) => inserted.push(j),
+                Op::Equal(i, j) => {
+                    self.unmatched(&old, &new, &mut deleted, &mut inserted, (i, j));
+                    if let (TokenTree::Group(a), TokenTree::Group(b)) = (old[i], new[j]) {
+                        self.enter(i, j);
+                        self.streams(a.inner.stream_tokens(), b.inner.stream_tokens());
+                        self.leave();
+                    }
+                }
+            }
+        }
+        let end = (old.len(), new.len());
+        self.unmatched(&old, &new, &mut deleted, &mut inserted, end);
+    }
+
+    /// Reports the tokens which were not matched before the tokens at `next`,
+    /// pairing them up where possible.
+    fn unmatched(
+        &mut self,
+        old: &[&TokenTree],
+        new: &[&TokenTree],
+        deleted: &mut Vec<usize>,
+        inserted: &mut Vec<usize>,
+        next: (usize, usize),
+    ) {
+        let mut deleted = deleted.drain(..).peekable();
+        let mut inserted = inserted.drain(..).peekable();
+        loop {
+            match (deleted.peek(), inserted.peek()) {
+                (Some(&i), Some(&j)) if same_kind(old[i], new[j]) => {
+                    deleted.next();
+                    inserted.next();
+                    if let (TokenTree::Group(a), TokenTree::Group(b)) = (old[i], new[j]) {
+                        self.push(
+                            ChangeKind::DelimiterChanged,
+                            (i, Some(old[i])),
+                            (j, Some(new[j])),
+                        );
+                        self.enter(i, j);
+                        self.streams(a.inner.stream_tokens(), b.inner.stream_tokens());
+                        self.leave();
+                    } else {
+                        self.push(ChangeKind::Changed, (i, Some(old[i])), (j, Some(new[j])));
+                    }
+                }
+                (Some(&i), Some(&j)) => {
+                    deleted.next();
+                    inserted.next();
+                    self.push(ChangeKind::Deleted, (i, Some(old[i])), (j, None));
+                    self.push(ChangeKind::Inserted, (i + 1, None), (j, Some(new[j])));
+                }
+                (Some(&i), None) => {
+                    deleted.next();
+                    self.push(ChangeKind::Deleted, (i, Some(old[i])), (next.1, None));
+                }
+                (None, Some(&j)) => {
+                    inserted.next();
+                    self.push(ChangeKind::Inserted, (next.0, None), (j, Some(new[j])));
+                }
+                (None, None) => return,
+            }
+        }
+    }
+
+    fn push(
+        &mut self,
+        kind: ChangeKind,
+        (i, old): (usize, Option<&TokenTree>),
+        (j, new): (usize, Option<&TokenTree>),
+    ) {
+        let mut old_path = self.old_path.clone();
+        old_path.push(i);
+        let mut new_path = self.new_path.clone();
+        new_path.push(j);
+        self.changes.push(Change {
+            kind,
+            old: old.cloned(),
+            new: new.cloned(),
+            old_path,
+            new_path,
+        });
+    }
+
+    fn enter(&mut self, i: usize, j: usize) {
+        self.old_path.push(i);
+        self.new_path.push(j);
+    }
+
+    fn leave(&mut self) {
+        self.old_path.pop();
+        self.new_path.pop();
+    }
+}
+
+/// Whether two tokens line up with each other. Groups with the same delimiter
+/// always line up, and are compared further by their contents.
+fn matches(a: &TokenTree, b: &TokenTree) -> bool {
+    match (a, b) {
+        (TokenTree::Group(a), TokenTree::Group(b)) => a.delimiter() == b.delimiter(),
+        _ => fallback::token_eq(a, b),
+    }
+}
+
+/// Whether an unmatched pair of tokens is reported as one changing into the
+/// other, rather than as a deletion and an insertion.
+fn same_kind(a: &TokenTree, b: &TokenTree) -> bool {
+    matches!(
+        (a, b),
+        (TokenTree::Group(_), TokenTree::Group(_))
+            | (TokenTree::Ident(_), TokenTree::Ident(_))
+            | (TokenTree::Punct(_), TokenTree::Punct(_))
+            | (TokenTree::Literal(_), TokenTree::Literal(_))
+    )
+}
+
+#[derive(Copy, Clone, Debug)]
+enum Op {
+    Equal(usize, usize),
+    Delete(usize),
+    Insert(usize),
+}
+
+/// Finds a shortest edit script turning `a` into `b`, using the linear space
+/// variant of Myers' O(ND) algorithm.
+fn align<T>(a: &[T], b: &[T], eq: impl Fn(&T, &T) -> bool) -> Vec<Op> {
+    let mut ops = Vec::new();
+    conquer(a, b, &eq, (0, 0), &mut ops);
+    ops
+}
+
+/// Appends a shortest edit script turning `a` into `b` to `ops`, where `a` and
+/// `b` start at `start` in the sequences being aligned.
+#[allow(clippy::many_single_char_names)]
+fn conquer<T>(
+    a: &[T],
+    b: &[T],
+    eq: &impl Fn(&T, &T) -> bool,
+    start: (usize, usize),
+    ops: &mut Vec<Op>,
+) {
+    let prefix = a.iter().zip(b).take_while(|(x, y)| eq(x, y)).count();
+    let (a, b) = (&a[prefix..], &b[prefix..]);
+    let suffix = a
+        .iter()
+        .rev()
+        .zip(b.iter().rev())
+        .take_while(|(x, y)| eq(x, y))
+        .count();
+    let (a, b) = (&a[..a.len() - suffix], &b[..b.len() - suffix]);
+
+    let (i, j) = start;
+    ops.extend((0..prefix).map(|p| Op::Equal(i + p, j + p)));
+    let (i, j) = (i + prefix, j + prefix);
+    if a.is_empty() {
+        ops.extend((j..j + b.len()).map(Op::Insert));
+    } else if b.is_empty() {
+        ops.extend((i..i + a.len()).map(Op::Delete));
+    } else {
+        let (x, y) = split(a, b, eq);
+        conquer(&a[..x], &b[..y], eq, (i, j), ops);
+        conquer(&a[x..], &b[y..], eq, (i + x, j + y), ops);
+    }
+    let (i, j) = (i + a.len(), j + b.len());
+    ops.extend((0..suffix).map(|p| Op::Equal(i + p, j + p)));
+}
+
+/// Finds a point half way along a shortest edit script turning `a` into `b`,
+/// which must be non-empty and differ in their first and last elements, by
+/// searching forwards from the start and backwards from the end at once.
+///
+/// Diagonals and their offsets are signed, so positions are kept as `isize`
+/// and only cast back once known to be within `a` and `b`.
+#[allow(
+    clippy::cast_possible_wrap,
+    clippy::cast_sign_loss,
+    clippy::many_single_char_names
+)]
+fn split<T>(a: &[T], b: &[T], eq: &impl Fn(&T, &T) -> bool) -> (usize, usize) {
+    let n = a.len() as isize;
+    let m = b.len() as isize;
+    // Diagonal k holds the points where x - y == k. Seen from the end, the
+    // same diagonal is numbered delta - k.
+    let delta = n - m;
+    let max = (n + m + 1) / 2;
+    let index = |k: isize| (max + 1 + k) as usize;
+    // The furthest x reached on each diagonal, counted from the start going
+    // forwards and from the end going backwards. Paths may run past the
+    // edges, where nothing is equal.
+    let mut forward = vec![0; 2 * max as usize + 3];
+    let mut backward = vec![0; 2 * max as usize + 3];
+    // Where a forward path reaching x and a backward path reaching x_back
+    // (both counted from the start) meet on diagonal k, any point of the
+    // diagonal between the two and inside both sequences is on a shortest
+    // edit script.
+    let meet = |k: isize, x: isize, x_back: isize| {
+        if -m <= k && k <= n && x >= x_back {
+            let x = x.min(n).min(m + k);
+            Some((x as usize, (x - k) as usize))
+        } else {
+            None
+        }
+    };
+    for d in 0..=max {
+        for k in (-d..=d).step_by(2) {
+            let mut x = if k == -d || (k != d && forward[index(k - 1)] < forward[index(k + 1)]) {
+                forward[index(k + 1)]
+            } else {
+                forward[index(k - 1)] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && eq(&a[x as usize], &b[y as usize]) {
+                x += 1;
+                y += 1;
+            }
+            forward[index(k)] = x;
+            if delta % 2 != 0 && (delta - k).abs() < d {
+                if let Some(point) = meet(k, x, n - backward[index(delta - k)]) {
+                    return point;
+                }
+            }
+        }
+        for k in (-d..=d).step_by(2) {
+            let mut x = if k == -d || (k != d && backward[index(k - 1)] < backward[index(k + 1)]) {
+                backward[index(k + 1)]
+            } else {
+                backward[index(k - 1)] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && eq(&a[(n - x - 1) as usize], &b[(m - y - 1) as usize]) {
+                x += 1;
+                y += 1;
+            }
+            backward[index(k)] = x;
+            if delta % 2 == 0 && (delta - k).abs() <= d {
+                if let Some(point) = meet(delta - k, forward[index(delta - k)], n - x) {
+                    return point;
+                }
+            }
+        }
+    }
+    unreachable!()
+}
+
+/// Writes one line describing `change`, such as
+/// ``~ [3, 1]: `1` (lib.rs:1:15) -> `2` (lib.rs:1:15)``.
+pub(crate) fn render(change: &Change, f: &mut fmt::Formatter) -> fmt::Result {
+    match change.kind {
+        ChangeKind::Inserted => {
+            write!(f, "+ {:?}: ", change.new_path)?;
+            token(change.new.as_ref(), f)
+        }
+        ChangeKind::Deleted => {
+            write!(f, "- {:?}: ", change.old_path)?;
+            token(change.old.as_ref(), f)
+        }
+        ChangeKind::Changed | ChangeKind::DelimiterChanged => {
+            write!(f, "~ {:?}: ", change.old_path)?;
+            token(change.old.as_ref(), f)?;
+            f.write_str(" -> ")?;
+            token(change.new.as_ref(), f)
+        }
+    }
+}
+
+/// Writes a token, abbreviated to fit on one line, and where it is.
+fn token(token: Option<&TokenTree>, f: &mut fmt::Formatter) -> fmt::Result {
+    const MAX_CHARS: usize = 40;
+
+    let Some(token) = token else {
+        return Ok(());
+    };
+    let text = token.to_string();
+    let mut words = text.split_whitespace();
+    let mut short = words.next().unwrap_or("").to_owned();
+    for word in words {
+        short.push(' ');
+        short.push_str(word);
+    }
+    if short.chars().count() > MAX_CHARS {
+        short = short.chars().take(MAX_CHARS - 1).collect();
+        short.push('…');
+    }
+    write!(f, "`{}` ({})", short, Location(token.span()))
+}
+
+struct Location(Span);
+
+impl Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let start = self.0.start();
+        write!(
+            f,
+            "{}:{}:{}",
+            self.0.source_file().path().display(),
+            start.line,
+            start.column + 1,
+        )
+    }
+}
@@ -0,0 +1,498 @@
+//! The binary format of `TokenStream::encode` and `TokenStream::decode`.
+//!
+//! An encoded stream is laid out as:
+//!
+//! - the magic bytes `PM0T` and the format version, a varint;
+//! - a flags byte, whose lowest bit is set if spans are included;
+//! - the string table: a varint count, then each string as a varint length
+//!   and its UTF-8 bytes. Every identifier, literal, doc comment and piece of
+//!   trivia in the stream is stored once here and referred to by index;
+//! - if spans are included, the file table: a varint count, then for each
+//!   file its path as a string index, whether it is real, its length in chars
+//!   and its line start offsets, delta encoded;
+//! - the token stream itself.
+//!
+//! A token stream is a varint count, that many token trees, and then the
+//! stream's trailing trivia. A token tree is a tag byte, its leading trivia,
+//! its span if spans are included, and then its contents. Optional strings
+//! are written as 0 for `None` or as their index plus one. A span is written
+//! as 0 if it behaves like `Span::call_site()`, and otherwise as its file's
+//! index plus one and its start and end offsets within that file.
+//!
+//! The decoder does not trust the file table: a file is only recorded in the
+//! source map if a span points into it, and only as far as the last position
+//! that a span points to, so the positions it uses up are bounded by what
+//! the stream references rather than by a length the input claims. Groups
+//! may be nested at most `MAX_DEPTH` deep.
+
+use crate::fallback::{self, FileRecord};
+use crate::{
+    imp, Delimiter, Group, Ident, LexError, LexErrorKind, Literal, Punct, SourceMap, Spacing, Span,
+    TokenStream, TokenTree,
+};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::error::Error;
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+const MAGIC: &[u8; 4] = b"PM0T";
+const VERSION: u64 = 1;
+
+const FLAG_SPANS: u8 = 1;
+
+/// How deeply groups may be nested in a stream being decoded, so that
+/// corrupted input cannot overflow the stack.
+const MAX_DEPTH: usize = 512;
+
+const TAG_GROUP: u8 = 0;
+const TAG_IDENT: u8 = 1;
+const TAG_RAW_IDENT: u8 = 2;
+const TAG_PUNCT_ALONE: u8 = 3;
+const TAG_PUNCT_JOINT: u8 = 4;
+const TAG_LITERAL: u8 = 5;
+
+pub(crate) fn encode(tokens: &TokenStream, spans: bool, writer: &mut dyn Write) -> io::Result<()> {
+    let mut encoder = Encoder {
+        spans,
+        body: Vec::new(),
+        strings: Vec::new(),
+        string_indices: HashMap::new(),
+        files: Vec::new(),
+        file_indices: HashMap::new(),
+    };
+    encoder.stream(&tokens.inner);
+
+    let mut header = Vec::new();
+    header.extend_from_slice(MAGIC);
+    write_varint(&mut header, VERSION);
+    header.push(if spans { FLAG_SPANS } else { 0 });
+
+    // Resolve the file paths first, since they go in the string table too.
+    let mut records = Vec::new();
+    for file in encoder.files.split_off(0) {
+        let record = fallback::file_record(file).unwrap_or_else(|| FileRecord {
+            path: PathBuf::new(),
+            is_real: false,
+            len: (file.hi - file.lo) as usize,
+            lines: vec![0],
+        });
+        let path = encoder.intern(&record.path.to_string_lossy());
+        records.push((path, record));
+    }
+
+    write_varint(&mut header, encoder.strings.len() as u64);
+    for string in &encoder.strings {
+        write_varint(&mut header, string.len() as u64);
+        header.extend_from_slice(string.as_bytes());
+    }
+    if spans {
+        write_varint(&mut header, records.len() as u64);
+        for (path, record) in &records {
+            write_varint(&mut header, *path as u64);
+            header.push(record.is_real as u8);
+            write_varint(&mut header, record.len as u64);
+            write_varint(&mut header, record.lines.len() as u64);
+            let mut prev = 0;
+            for &line in &record.lines {
+                write_varint(&mut header, (line - prev) as u64);
+                prev = line;
+            }
+        }
+    }
+
+    writer.write_all(&header)?;
+    writer.write_all(&encoder.body)
+}
+
+pub(crate) fn decode(map: Option<&SourceMap>, reader: &mut dyn Read) -> io::Result<TokenStream> {
+    let mut decoder = Decoder {
+        reader,
+        spans: false,
+        strings: Vec::new(),
+        files: Vec::new(),
+        depth: 0,
+    };
+
+    if decoder.take(MAGIC.len())? != MAGIC {
+        return Err(invalid("not an encoded token stream"));
+    }
+    let version = decoder.varint()?;
+    if version != VERSION {
+        return Err(invalid(format!(
+            "unsupported token stream encoding version {version}"
+        )));
+    }
+    let flags = decoder.byte()?;
+    if flags & !FLAG_SPANS != 0 {
+        return Err(invalid("unknown token stream encoding flags"));
+    }
+    decoder.spans = flags & FLAG_SPANS != 0;
+
+    for _ in 0..decoder.varint()? {
+        let len = decoder.len()?;
+        let string = String::from_utf8(decoder.take(len)?).map_err(invalid)?;
+        decoder.strings.push(Arc::from(string));
+    }
+    if decoder.spans {
+        for _ in 0..decoder.varint()? {
+            let path = PathBuf::from(&*decoder.string()?);
+            let is_real = decoder.byte()? != 0;
+            let len = decoder.len()?;
+            let mut lines = Vec::new();
+            let mut line = 0usize;
+            for _ in 0..decoder.varint()? {
+                line = match line.checked_add(decoder.len()?) {
+                    Some(line) if line <= len => line,
+                    _ => return Err(invalid("invalid line offsets")),
+                };
+                if !lines.is_empty() && line == lines[lines.len() - 1] {
+                    return Err(invalid("invalid line offsets"));
+                }
+                lines.push(line);
+            }
+            if lines.first() != Some(&0) || len > u32::MAX as usize {
+                return Err(invalid("invalid line offsets"));
+            }
+            let record = FileRecord {
+                path,
+                is_real,
+                len,
+                lines,
+            };
+            decoder.files.push((record, None));
+        }
+    }
+
+    let mut stream = decoder.stream()?;
+    if decoder.spans {
+        // Spans are decoded relative to their file, which is only recorded
+        // now that it is known how much of it they use.
+        let mut files = Vec::new();
+        for (mut record, end) in decoder.files {
+            let Some(end) = end else {
+                files.push(None);
+                continue;
+            };
+            record.len = end;
+            record.lines.retain(|&line| line <= end);
+            let file =
+                fallback::add_file_record(map.map(|map| &map.inner), record).ok_or_else(|| {
+                    let inner = fallback::LexError::call_site(LexErrorKind::SourceMapFull);
+                    io::Error::new(io::ErrorKind::InvalidData, LexError { inner })
+                })?;
+            files.push(Some(file));
+        }
+        relocate(&mut stream.inner.inner, &files);
+    }
+    Ok(stream)
+}
+
+/// Moves the spans of `tokens`, which the decoder gives the index of their
+/// file plus one in place of a source map id, into the files they point
+/// into.
+fn relocate(tokens: &mut [TokenTree], files: &[Option<fallback::Span>]) {
+    for tt in tokens {
+        let span = tt.span().inner;
+        if let Some(file) = span.map.checked_sub(1).and_then(|i| files[i as usize]) {
+            tt.set_span(Span::_new(imp::Span {
+                lo: file.lo + span.lo,
+                hi: file.lo + span.hi,
+                map: file.map,
+            }));
+        }
+        if let TokenTree::Group(group) = tt {
+            relocate(&mut group.inner.stream.inner.inner, files);
+        }
+    }
+}
+
+struct Encoder {
+    spans: bool,
+    body: Vec<u8>,
+    strings: Vec<Arc<str>>,
+    string_indices: HashMap<Arc<str>, usize>,
+    files: Vec<fallback::Span>,
+    file_indices: HashMap<(u32, u32), usize>,
+}
+
+impl Encoder {
+    fn stream(&mut self, stream: &imp::TokenStream) {
+        write_varint(&mut self.body, stream.inner.len() as u64);
+        for tt in &stream.inner {
+            self.token_tree(tt);
+        }
+        self.optional_string(stream.trailing.as_deref());
+    }
+
+    fn token_tree(&mut self, tt: &TokenTree) {
+        let tag = match tt {
+            TokenTree::Group(_) => TAG_GROUP,
+            TokenTree::Ident(ident) if ident.inner.is_raw() => TAG_RAW_IDENT,
+            TokenTree::Ident(_) => TAG_IDENT,
+            TokenTree::Punct(punct) => match punct.spacing() {
+                Spacing::Alone => TAG_PUNCT_ALONE,
+                Spacing::Joint => TAG_PUNCT_JOINT,
+            },
+            TokenTree::Literal(_) => TAG_LITERAL,
+        };
+        self.body.push(tag);
+        self.optional_string(fallback::leading_trivia(tt));
+        if self.spans {
+            self.span(tt.span());
+        }
+        match tt {
+            TokenTree::Group(group) => {
+                self.body.push(match group.delimiter() {
+                    Delimiter::Parenthesis => 0,
+                    Delimiter::Brace => 1,
+                    Delimiter::Bracket => 2,
+                    Delimiter::None => 3,
+                });
+                self.optional_string(group.inner.doc.as_deref());
+                self.stream(&group.inner.stream.inner);
+            }
+            TokenTree::Ident(ident) => self.string(ident.inner.sym()),
+            TokenTree::Punct(punct) => write_varint(&mut self.body, punct.as_char() as u64),
+            TokenTree::Literal(literal) => self.string(literal.inner.repr()),
+        }
+    }
+
+    fn span(&mut self, span: Span) {
+        let span = span.inner;
+        let Some(file) = fallback::file_span(span) else {
+            return self.body.push(0);
+        };
+        let files = &mut self.files;
+        let index = *self
+            .file_indices
+            .entry((file.map, file.lo))
+            .or_insert_with(|| {
+                files.push(file);
+                files.len() - 1
+            });
+        write_varint(&mut self.body, index as u64 + 1);
+        write_varint(&mut self.body, (span.lo - file.lo) as u64);
+        write_varint(&mut self.body, (span.hi - span.lo) as u64);
+    }
+
+    fn string(&mut self, string: &str) {
+        let index = self.intern(string);
+        write_varint(&mut self.body, index as u64);
+    }
+
+    fn optional_string(&mut self, string: Option<&str>) {
+        match string {
+            Some(string) => {
+                let index = self.intern(string);
+                write_varint(&mut self.body, index as u64 + 1);
+            }
+            None => self.body.push(0),
+        }
+    }
+
+    fn intern(&mut self, string: &str) -> usize {
+        if let Some(&index) = self.string_indices.get(string) {
+            return index;
+        }
+        let string = Arc::<str>::from(string);
+        self.strings.push(Arc::clone(&string));
+        self.string_indices.insert(string, self.strings.len() - 1);
+        self.strings.len() - 1
+    }
+}
+
+struct Decoder<'a> {
+    /// Read from only as far as the end of the stream, so that anything
+    /// after it is left for the caller.
+    reader: &'a mut dyn Read,
+    spans: bool,
+    strings: Vec<Arc<str>>,
+    /// Each file from the file table, with the end of the furthest span into
+    /// it seen so far.
+    files: Vec<(FileRecord, Option<usize>)>,
+    /// Number of groups around the token tree being decoded.
+    depth: usize,
+}
+
+impl Decoder<'_> {
+    fn stream(&mut self) -> io::Result<TokenStream> {
+        let len = self.len()?;
+        let mut trees = Vec::new();
+        for _ in 0..len {
+            trees.push(self.token_tree()?);
+        }
+        let mut stream = imp::TokenStream::from(trees);
+        stream.set_trailing_trivia(self.trivia()?);
+        Ok(TokenStream::_new(stream))
+    }
+
+    fn token_tree(&mut self) -> io::Result<TokenTree> {
+        let tag = self.byte()?;
+        let leading = self.trivia()?;
+        let span = if self.spans {
+            self.span()?
+        } else {
+            Span::call_site()
+        };
+        let mut tt = match tag {
+            TAG_GROUP => {
+                let delimiter = match self.byte()? {
+                    0 => Delimiter::Parenthesis,
+                    1 => Delimiter::Brace,
+                    2 => Delimiter::Bracket,
+                    3 => Delimiter::None,
+                    _ => return Err(invalid("invalid delimiter")),
+                };
+                let doc = self.optional_string()?;
+                if self.depth == MAX_DEPTH {
+                    return Err(invalid("token stream is nested too deeply"));
+                }
+                self.depth += 1;
+                let mut group = Group::new(delimiter, self.stream()?);
+                self.depth -= 1;
+                group.inner.doc = doc;
+                TokenTree::Group(group)
+            }
+            TAG_IDENT | TAG_RAW_IDENT => {
+                let sym = self.string()?;
+                let ident = if tag == TAG_RAW_IDENT {
+                    Ident::try_new_raw(&sym, span)
+                } else {
+                    Ident::try_new(&sym, span)
+                };
+                TokenTree::Ident(ident.map_err(invalid)?)
+            }
+            TAG_PUNCT_ALONE | TAG_PUNCT_JOINT => {
+                let ch = u32::try_from(self.varint()?)
+                    .ok()
+                    .and_then(char::from_u32)
+                    .ok_or_else(|| invalid("invalid punct"))?;
+                let spacing = if tag == TAG_PUNCT_JOINT {
+                    Spacing::Joint
+                } else {
+                    Spacing::Alone
+                };
+                TokenTree::Punct(Punct::try_new(ch, spacing).map_err(invalid)?)
+            }
+            TAG_LITERAL => {
+                let repr = self.string()?;
+                match imp::Literal::parse_unrecorded(&repr) {
+                    Some(literal) => TokenTree::Literal(Literal::_new(literal)),
+                    None => return Err(invalid("invalid literal")),
+                }
+            }
+            _ => return Err(invalid("invalid token tree")),
+        };
+        tt.set_span(span);
+        fallback::set_leading_trivia(&mut tt, leading);
+        Ok(tt)
+    }
+
+    /// Reads a span, which is relative to its file until `relocate` moves it
+    /// into the source map.
+    fn span(&mut self) -> io::Result<Span> {
+        let index = match self.len()? {
+            0 => return Ok(Span::call_site()),
+            index => index,
+        };
+        let lo = self.len()?;
+        let len = self.len()?;
+        let (file, end) = self
+            .files
+            .get_mut(index - 1)
+            .ok_or_else(|| invalid("invalid span"))?;
+        match lo.checked_add(len) {
+            // Files are at most u32::MAX chars long, and there are no more
+            // of them than bytes of input.
+            Some(hi) if hi <= file.len => {
+                *end = Some(end.map_or(hi, |end| end.max(hi)));
+                Ok(Span::_new(imp::Span {
+                    lo: lo as u32,
+                    hi: hi as u32,
+                    map: u32::try_from(index).map_err(invalid)?,
+                }))
+            }
+            _ => Err(invalid("invalid span")),
+        }
+    }
+
+    fn string(&mut self) -> io::Result<Arc<str>> {
+        let index = self.len()?;
+        match self.strings.get(index) {
+            Some(string) => Ok(Arc::clone(string)),
+            None => Err(invalid("invalid string index")),
+        }
+    }
+
+    fn optional_string(&mut self) -> io::Result<Option<Arc<str>>> {
+        match self.len()? {
+            0 => Ok(None),
+            index => match self.strings.get(index - 1) {
+                Some(string) => Ok(Some(Arc::clone(string))),
+                None => Err(invalid("invalid string index")),
+            },
+        }
+    }
+
+    fn trivia(&mut self) -> io::Result<Option<Arc<str>>> {
+        match self.optional_string()? {
+            Some(trivia) if !fallback::is_trivia(&trivia) => Err(invalid("invalid trivia")),
+            trivia => Ok(trivia),
+        }
+    }
+
+    fn byte(&mut self) -> io::Result<u8> {
+        let mut byte = [0];
+        match self.reader.read_exact(&mut byte) {
+            Ok(()) => Ok(byte[0]),
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => Err(truncated()),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn take(&mut self, len: usize) -> io::Result<Vec<u8>> {
+        // Read in chunks rather than allocating `len` bytes up front, since
+        // the length comes from the input.
+        let mut taken = Vec::new();
+        let reader = &mut self.reader;
+        reader.take(len as u64).read_to_end(&mut taken)?;
+        if taken.len() < len {
+            return Err(truncated());
+        }
+        Ok(taken)
+    }
+
+    fn len(&mut self) -> io::Result<usize> {
+        usize::try_from(self.varint()?).map_err(invalid)
+    }
+
+    fn varint(&mut self) -> io::Result<u64> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(invalid("invalid varint"))
+    }
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn truncated() -> io::Error {
+    io::Error::new(io::ErrorKind::UnexpectedEof, "truncated token stream")
+}
+
+fn invalid<E: Into<Box<dyn Error + Send + Sync>>>(error: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
@@ -1,48 +1,88 @@
 use crate::parse::{self, Cursor};
-use crate::{Delimiter, Spacing, TokenTree};
-use once_cell::sync::Lazy;
+use crate::{
+    ColumnEncoding, Delimiter, IdentError, IdentErrorKind, LexErrorKind, LexOptions, LiteralKind,
+    Spacing, TokenTree,
+};
 use parking_lot::RwLock;
 use std::cmp;
+use std::collections::HashMap;
+use std::convert::TryFrom;
 use std::fmt::{self, Debug, Display, Write};
-use std::iter::FromIterator;
+use std::hash::{Hash, Hasher};
+use std::iter::{self, FromIterator};
 
-use std::ops::RangeBounds;
+use std::ops::{Bound, Range, RangeBounds};
 use std::path::Path;
 use std::path::PathBuf;
 use std::str::FromStr;
-use std::vec;
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::sync::{Arc, LazyLock};
+use std::{slice, vec};
 
 #[derive(Clone)]
 pub(crate) struct TokenStream {
     pub(crate) inner: Vec<TokenTree>,
+    /// Whitespace and comments after the last token, if lexed with trivia.
+    pub(crate) trailing: Option<Arc<str>>,
 }
 
 #[derive(Debug)]
 pub(crate) struct LexError {
-    pub(crate) span: Span,
+    pub(crate) kind: LexErrorKind,
+    pub(crate) start: Span,
+    pub(crate) end: Span,
 }
 
 impl LexError {
+    pub(crate) fn kind(&self) -> LexErrorKind {
+        self.kind
+    }
+
     pub(crate) fn span(&self) -> Span {
-        self.span
+        Span {
+            lo: self.start.lo,
+            hi: cmp::max(self.start.hi, self.end.hi),
+            map: self.start.map,
+        }
     }
 
-    fn call_site() -> Self {
+    pub(crate) fn start_span(&self) -> Span {
+        self.start
+    }
+
+    pub(crate) fn end_span(&self) -> Span {
+        self.end
+    }
+
+    pub(crate) fn call_site(kind: LexErrorKind) -> Self {
         LexError {
-            span: Span::call_site(),
+            kind,
+            start: Span::call_site(),
+            end: Span::call_site(),
         }
     }
 }
 
 impl TokenStream {
     pub fn new() -> Self {
-        TokenStream { inner: Vec::new() }
+        TokenStream {
+            inner: Vec::new(),
+            trailing: None,
+        }
     }
 
     pub fn is_empty(&self) -> bool {
         self.inner.len() == 0
     }
 
+    pub fn trailing_trivia(&self) -> Option<&str> {
+        self.trailing.as_deref()
+    }
+
+    pub fn set_trailing_trivia(&mut self, trivia: Option<Arc<str>>) {
+        self.trailing = trivia;
+    }
+
     fn take_inner(&mut self) -> Vec<TokenTree> {
         std::mem::take(&mut self.inner)
     }
@@ -63,6 +103,7 @@ impl TokenStream {
             literal.repr.remove(0);
             let mut punct = crate::Punct::new('-', Spacing::Alone);
             punct.set_span(crate::Span::_new_stable(literal.span));
+            punct.leading = literal.leading.take();
             stream.inner.push(TokenTree::Punct(punct));
             stream
                 .inner
@@ -73,7 +114,10 @@ impl TokenStream {
 
 impl From<Vec<TokenTree>> for TokenStream {
     fn from(inner: Vec<TokenTree>) -> Self {
-        TokenStream { inner }
+        TokenStream {
+            inner,
+            trailing: None,
+        }
     }
 }
 
@@ -86,47 +130,126 @@ impl Drop for TokenStream {
                 _ => continue,
             };
             let mut group = group;
-            self.inner.extend(group.stream.take_inner());
+            self.inner.extend(group.stream.inner.take_inner());
         }
     }
 }
 
-fn get_cursor(src: &str) -> Cursor {
-    // Create a dummy file & add it to the source map
-    let mut cm = SOURCE_MAP.write();
-    let name = format!("<parsed string {}>", cm.files.len());
-    let span = cm.add_file(&name, src);
-    Cursor {
+fn get_cursor<'a>(
+    cm: &SourceMapInner,
+    path: Option<&Path>,
+    src: &'a str,
+) -> Result<Cursor<'a>, LexError> {
+    let span = cm
+        .add_file(path.map(Path::to_owned), src)
+        .ok_or_else(|| LexError::call_site(LexErrorKind::SourceMapFull))?;
+    Ok(Cursor {
         rest: src,
         off: span.lo,
-    }
+        map: span.map,
+    })
 }
 
 impl FromStr for TokenStream {
     type Err = LexError;
 
     fn from_str(src: &str) -> Result<TokenStream, LexError> {
-        // Create a dummy file & add it to the source map
-        let cursor = get_cursor(src);
+        TokenStream::from_str_with(src, LexOptions::new())
+    }
+}
 
-        parse::token_stream(cursor)
+impl TokenStream {
+    pub fn from_str_named(path: &Path, src: &str) -> Result<TokenStream, LexError> {
+        let cursor = get_cursor(&SOURCE_MAP, Some(path), src)?;
+        parse::token_stream(cursor, LexOptions::new())
+    }
+
+    pub fn from_str_with(src: &str, options: LexOptions) -> Result<TokenStream, LexError> {
+        let cursor = get_cursor(&SOURCE_MAP, None, src)?;
+        parse::token_stream(cursor, options)
+    }
+
+    /// Lexes `src` without recording it in a source map, giving every token a
+    /// call_site() span.
+    pub(crate) fn parse_unrecorded(src: &str) -> Option<TokenStream> {
+        let cursor = Cursor {
+            rest: src,
+            off: 0,
+            map: 0,
+        };
+        let mut stream = parse::token_stream(cursor, LexOptions::new()).ok()?;
+        set_call_site(&mut stream.inner);
+        Some(stream)
+    }
+}
+
+fn set_call_site(tokens: &mut [TokenTree]) {
+    for tt in tokens {
+        tt.set_span(crate::Span::call_site());
+        if let TokenTree::Group(group) = tt {
+            set_call_site(&mut group.inner.stream.inner.inner);
+        }
     }
 }
 
 impl Display for LexError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        f.write_str("cannot parse string into token stream")
+        Display::fmt(&self.kind, f)
     }
 }
 
 impl Display for TokenStream {
+    // Tokens lexed with trivia are preceded by their original whitespace and
+    // comments instead of the usual single space, or by a space where empty
+    // trivia would join two tokens. Anything after a line comment is put on
+    // the next line.
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write(f).map(drop)
+    }
+}
+
+impl TokenStream {
+    /// Prints this stream, returning whether the output ends in a line
+    /// comment that has to be followed by a newline.
+    fn write(&self, f: &mut fmt::Formatter) -> Result<bool, fmt::Error> {
         let mut joint = false;
-        for (i, tt) in self.inner.iter().enumerate() {
-            if i != 0 && !joint {
-                write!(f, " ")?;
+        let mut line_comment = false;
+        let mut prev = None;
+        let mut i = 0;
+        while let Some(tt) = self.inner.get(i) {
+            let trivia = leading_trivia(tt);
+            let doc_comment = doc_comment_source(&self.inner[i..]);
+            let space = match (prev, trivia) {
+                (Some(_), None) => !joint,
+                (Some(prev), Some("")) => {
+                    !joint
+                        && !adjacent_in_source(prev, tt)
+                        && needs_space(prev, tt, doc_comment.is_some())
+                }
+                _ => false,
+            };
+            if line_comment && !trivia.is_some_and(starts_with_newline) {
+                f.write_str("\n")?;
+            } else if space {
+                f.write_str(" ")?;
+            }
+            if let Some(trivia) = trivia {
+                f.write_str(trivia)?;
+                if ends_in_line_comment(trivia) {
+                    f.write_str("\n")?;
+                }
             }
             joint = false;
+            if let Some((comment, len)) = doc_comment {
+                f.write_str(comment)?;
+                line_comment = comment.starts_with("//");
+                i += len;
+                prev = self.inner.get(i - 1);
+                continue;
+            }
+            line_comment = false;
+            prev = Some(tt);
+            i += 1;
             match tt {
                 TokenTree::Group(tt) => Display::fmt(tt, f),
                 TokenTree::Ident(tt) => Display::fmt(tt, f),
@@ -138,7 +261,123 @@ impl Display for TokenStream {
             }?;
         }
 
-        Ok(())
+        match self.trailing.as_deref() {
+            Some("") => Ok(line_comment),
+            Some(trivia) => {
+                if line_comment && !starts_with_newline(trivia) {
+                    f.write_str("\n")?;
+                }
+                f.write_str(trivia)?;
+                Ok(ends_in_line_comment(trivia))
+            }
+            None => {
+                if line_comment {
+                    f.write_str("\n")?;
+                }
+                Ok(false)
+            }
+        }
+    }
+}
+
+/// Whether `trivia` ends a line comment written just before it.
+fn starts_with_newline(trivia: &str) -> bool {
+    trivia.starts_with('\n') || trivia.starts_with("\r\n")
+}
+
+/// Whether `trivia` ends in a line comment, which would swallow anything
+/// written after it on the same line.
+fn ends_in_line_comment(trivia: &str) -> bool {
+    parse::check_trivia(trivia) == Some(true)
+}
+
+/// Whether `trivia` is made up only of whitespace and comments which are
+/// not doc comments.
+pub(crate) fn is_trivia(trivia: &str) -> bool {
+    parse::check_trivia(trivia).is_some()
+}
+
+/// Whether `prev` and `next` were lexed from the same source with nothing
+/// between them, so that they are known to lex apart.
+fn adjacent_in_source(prev: &TokenTree, next: &TokenTree) -> bool {
+    let prev = prev.span().inner;
+    let next = next.span().inner;
+    prev.lo < prev.hi && prev.map == next.map && prev.hi == next.lo
+}
+
+/// Whether `next` has to be separated from `prev` by a space, where `next`
+/// is printed as the source of a doc comment if `doc_comment`.
+fn needs_space(prev: &TokenTree, next: &TokenTree, doc_comment: bool) -> bool {
+    if doc_comment {
+        matches!(prev, TokenTree::Punct(punct) if punct.as_char() == '/')
+    } else {
+        !can_join(prev, next)
+    }
+}
+
+/// Whether `prev` and `next` lex as the same two tokens with no space
+/// between them.
+pub(crate) fn can_join(prev: &TokenTree, next: &TokenTree) -> bool {
+    match (prev, next) {
+        // Would change the spacing of `prev`, or lex as a single identifier,
+        // literal or lifetime.
+        (TokenTree::Punct(_), TokenTree::Punct(_))
+        | (
+            TokenTree::Ident(_) | TokenTree::Literal(_),
+            TokenTree::Ident(_) | TokenTree::Literal(_),
+        ) => false,
+        // Could start a raw identifier or raw string literal.
+        (TokenTree::Ident(ident), TokenTree::Punct(punct)) => {
+            punct.as_char() != '#' || !matches!(ident.to_string().as_str(), "r" | "br" | "cr")
+        }
+        (TokenTree::Punct(punct), TokenTree::Ident(_)) => punct.as_char() != '\'',
+        // Could lex as part of a float literal or a character literal.
+        (TokenTree::Literal(_), TokenTree::Punct(punct))
+        | (TokenTree::Punct(punct), TokenTree::Literal(_)) => !".'".contains(punct.as_char()),
+        _ => true,
+    }
+}
+
+/// Returns the whitespace and comments preceding `tt`, if it was lexed with
+/// trivia.
+pub(crate) fn leading_trivia(tt: &TokenTree) -> Option<&str> {
+    match tt {
+        TokenTree::Group(tt) => tt.inner.leading_trivia(),
+        TokenTree::Ident(tt) => tt.inner.leading_trivia(),
+        TokenTree::Punct(tt) => tt.leading.as_deref(),
+        TokenTree::Literal(tt) => tt.inner.leading_trivia(),
+    }
+}
+
+pub(crate) fn set_leading_trivia(tt: &mut TokenTree, trivia: Option<Arc<str>>) {
+    match tt {
+        TokenTree::Group(tt) => tt.inner.set_leading_trivia(trivia),
+        TokenTree::Ident(tt) => tt.inner.set_leading_trivia(trivia),
+        TokenTree::Punct(tt) => tt.leading = trivia,
+        TokenTree::Literal(tt) => tt.inner.set_leading_trivia(trivia),
+    }
+}
+
+/// If `tokens` starts with the `#[doc = "..."]` or `#![doc = "..."]` tokens of
+/// a doc comment lexed with trivia, returns the comment's source text and the
+/// number of tokens it stands for.
+pub(crate) fn doc_comment_source(tokens: &[TokenTree]) -> Option<(&str, usize)> {
+    let (inner, group, len) = match tokens {
+        [TokenTree::Punct(pound), TokenTree::Punct(bang), TokenTree::Group(group), ..]
+            if pound.as_char() == '#' && bang.as_char() == '!' =>
+        {
+            (true, group, 3)
+        }
+        [TokenTree::Punct(pound), TokenTree::Group(group), ..] if pound.as_char() == '#' => {
+            (false, group, 2)
+        }
+        _ => return None,
+    };
+    let (comment, _text, doc_inner) = group.inner.doc_comment()?;
+    if inner == doc_inner {
+        Some((comment, len))
+    } else {
+        None
     }
 }
 
@@ -149,6 +388,87 @@ impl Debug for TokenStream {
     }
 }
 
+impl PartialEq for TokenStream {
+    fn eq(&self, other: &TokenStream) -> bool {
+        tokens_eq(&self.inner, &other.inner)
+    }
+}
+
+impl Hash for TokenStream {
+    fn hash<H: Hasher>(&self, hasher: &mut H) {
+        hash_tokens(&self.inner, hasher);
+    }
+}
+
+/// Iterates over token trees, descending into `Delimiter::None` groups in
+/// place of yielding them.
+pub(crate) struct Transparent<'a> {
+    stack: Vec<slice::Iter<'a, TokenTree>>,
+}
+
+impl<'a> Transparent<'a> {
+    pub(crate) fn new(tokens: &'a [TokenTree]) -> Self {
+        Transparent {
+            stack: vec![tokens.iter()],
+        }
+    }
+}
+
+impl<'a> Iterator for Transparent<'a> {
+    type Item = &'a TokenTree;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.stack.last_mut()?.next() {
+                Some(TokenTree::Group(group)) if group.delimiter() == Delimiter::None => {
+                    self.stack.push(group.inner.stream_tokens().iter());
+                }
+                Some(token) => return Some(token),
+                None => drop(self.stack.pop()),
+            }
+        }
+    }
+}
+
+/// Compares two sequences of token trees, ignoring spans and trivia and
+/// looking through `Delimiter::None` groups.
+pub(crate) fn tokens_eq(a: &[TokenTree], b: &[TokenTree]) -> bool {
+    let mut a = Transparent::new(a);
+    let mut b = Transparent::new(b);
+    loop {
+        match (a.next(), b.next()) {
+            (None, None) => return true,
+            (Some(a), Some(b)) if token_eq(a, b) => {}
+            _ => return false,
+        }
+    }
+}
+
+/// Hashes a sequence of token trees consistently with `tokens_eq`.
+pub(crate) fn hash_tokens<H: Hasher>(tokens: &[TokenTree], hasher: &mut H) {
+    let mut len = 0;
+    for token in Transparent::new(tokens) {
+        match token {
+            TokenTree::Group(t) => (0u8, t).hash(hasher),
+            TokenTree::Ident(t) => (1u8, t).hash(hasher),
+            TokenTree::Punct(t) => (2u8, t).hash(hasher),
+            TokenTree::Literal(t) => (3u8, t).hash(hasher),
+        }
+        len += 1;
+    }
+    hasher.write_usize(len);
+}
+
+pub(crate) fn token_eq(a: &TokenTree, b: &TokenTree) -> bool {
+    match (a, b) {
+        (TokenTree::Group(a), TokenTree::Group(b)) => a == b,
+        (TokenTree::Ident(a), TokenTree::Ident(b)) => a == b,
+        (TokenTree::Punct(a), TokenTree::Punct(b)) => a == b,
+        (TokenTree::Literal(a), TokenTree::Literal(b)) => a == b,
+        _ => false,
+    }
+}
+
 impl From<TokenTree> for TokenStream {
     fn from(tree: TokenTree) -> TokenStream {
         let mut stream = TokenStream::new();
@@ -167,25 +487,65 @@ impl FromIterator<TokenTree> for TokenStream {
 
 impl FromIterator<TokenStream> for TokenStream {
     fn from_iter<I: IntoIterator<Item = TokenStream>>(streams: I) -> Self {
-        let mut v = Vec::new();
-
-        for mut stream in streams {
-            v.extend(stream.take_inner());
-        }
-
-        TokenStream { inner: v }
+        let mut stream = TokenStream::new();
+        stream.extend(streams);
+        stream
     }
 }
 
 impl Extend<TokenTree> for TokenStream {
+    // The trailing trivia is now followed by the first new token, so it
+    // becomes part of that token's leading trivia.
     fn extend<I: IntoIterator<Item = TokenTree>>(&mut self, tokens: I) {
-        tokens.into_iter().for_each(|token| self.push_token(token));
+        let mut tokens = tokens.into_iter();
+        if let Some(mut first) = tokens.next() {
+            self.move_trailing_trivia_onto(&mut first);
+            self.push_token(first);
+        }
+        tokens.for_each(|token| self.push_token(token));
     }
 }
 
 impl Extend<TokenStream> for TokenStream {
+    // Trailing trivia moves onto the first token after it, or is joined with
+    // the trailing trivia of a stream with no tokens.
     fn extend<I: IntoIterator<Item = TokenStream>>(&mut self, streams: I) {
-        self.inner.extend(streams.into_iter().flatten());
+        for mut stream in streams {
+            let mut tokens = stream.take_inner().into_iter();
+            if let Some(mut first) = tokens.next() {
+                self.move_trailing_trivia_onto(&mut first);
+                self.inner.push(first);
+                self.inner.extend(tokens);
+                self.trailing = stream.trailing.take();
+            } else if let Some(trailing) = stream.trailing.take() {
+                self.trailing = Some(match self.trailing.take() {
+                    Some(before) => join_trivia(&before, &trailing),
+                    None => trailing,
+                });
+            }
+        }
+    }
+}
+
+impl TokenStream {
+    fn move_trailing_trivia_onto(&mut self, token: &mut TokenTree) {
+        if let Some(trailing) = self.trailing.take() {
+            let trivia = match leading_trivia(token) {
+                Some(leading) => join_trivia(&trailing, leading),
+                None => trailing,
+            };
+            set_leading_trivia(token, Some(trivia));
+        }
+    }
+}
+
+/// Concatenates two runs of trivia, ending a line comment at the end of
+/// `before` if `after` does not start on a new line.
+fn join_trivia(before: &str, after: &str) -> Arc<str> {
+    if ends_in_line_comment(before) && !starts_with_newline(after) {
+        Arc::from(format!("{before}\n{after}"))
+    } else {
+        Arc::from(format!("{before}{after}"))
     }
 }
 
@@ -203,6 +563,7 @@ impl IntoIterator for TokenStream {
 #[derive(Clone, PartialEq, Eq)]
 pub(crate) struct SourceFile {
     path: PathBuf,
+    is_real: bool,
 }
 
 impl SourceFile {
@@ -212,8 +573,7 @@ impl SourceFile {
     }
 
     pub fn is_real(&self) -> bool {
-        // XXX(nika): Support real files in the future?
-        false
+        self.is_real
     }
 }
 
@@ -232,29 +592,66 @@ pub(crate) struct LineColumn {
     pub column: usize,
 }
 
-static SOURCE_MAP: Lazy<RwLock<SourceMap>> = Lazy::new(|| {
-    RwLock::new(SourceMap {
-        // NOTE: We start with a single dummy file which all call_site() and
-        // def_site() spans reference.
-        files: vec![FileInfo {
-            name: "<unspecified>".to_owned(),
-            span: Span { lo: 0, hi: 0 },
-            lines: vec![0],
-        }],
-    })
-});
+/// The default source map, with id 0, which `FromStr` parses into and which
+/// all call_site() and def_site() spans reference. Files added to it, along
+/// with their source text, are never freed.
+static SOURCE_MAP: LazyLock<SourceMapInner> = LazyLock::new(|| SourceMapInner::new(0));
+
+/// Source maps created through `SourceMap::new`, by id. Entries are removed
+/// when the owning handle is dropped.
+static SCOPED_SOURCE_MAPS: LazyLock<RwLock<HashMap<u32, Arc<SourceMapInner>>>> =
+    LazyLock::new(|| RwLock::new(HashMap::new()));
+
+static NEXT_SOURCE_MAP_ID: AtomicU32 = AtomicU32::new(1);
+
+/// Runs `f` with the file containing `span`, or returns `None` if the source
+/// map that `span` was created in has already been dropped.
+fn with_fileinfo<R>(span: Span, f: impl FnOnce(&FileInfo) -> R) -> Option<R> {
+    if span.map == 0 {
+        return Some(SOURCE_MAP.with_file(span, f));
+    }
+    let cm = SCOPED_SOURCE_MAPS.read().get(&span.map).cloned()?;
+    Some(cm.with_file(span, f))
+}
 
 struct FileInfo {
-    name: String,
+    path: PathBuf,
+    is_real: bool,
     span: Span,
     lines: Vec<usize>,
+    /// Source text of the file, unless it was added from a `FileRecord`.
+    source: Option<SourceText>,
+}
+
+struct SourceText {
+    text: Box<str>,
+    /// Byte offset of the start of each line.
+    line_bytes: Vec<usize>,
+}
+
+impl SourceText {
+    /// Returns the text of the 0-indexed `line`, without its newline.
+    fn line(&self, line: usize) -> &str {
+        let rest = &self.text[self.line_bytes[line]..];
+        rest.split('\n').next().unwrap()
+    }
+}
+
+/// The number of units that `ch` takes up in a column counted in `encoding`.
+fn column_width(ch: char, encoding: ColumnEncoding) -> usize {
+    match encoding {
+        ColumnEncoding::Chars => 1,
+        ColumnEncoding::Utf8 => ch.len_utf8(),
+        ColumnEncoding::Utf16 => ch.len_utf16(),
+    }
 }
 
 impl FileInfo {
     fn offset_line_column(&self, offset: usize) -> LineColumn {
         assert!(self.span_within(Span {
             lo: offset as u32,
-            hi: offset as u32
+            hi: offset as u32,
+            map: self.span.map,
         }));
         let offset = offset - self.span.lo as usize;
         match self.lines.binary_search(&offset) {
@@ -269,9 +666,85 @@ impl FileInfo {
         }
     }
 
+    /// Like `offset_line_column`, but with the column counted in `encoding`.
+    /// Returns `None` if that needs the source text and it is not known.
+    fn offset_line_column_in(&self, offset: usize, encoding: ColumnEncoding) -> Option<LineColumn> {
+        let LineColumn { line, column } = self.offset_line_column(offset);
+        if encoding == ColumnEncoding::Chars || column == 0 {
+            return Some(LineColumn { line, column });
+        }
+        let column = self
+            .source
+            .as_ref()?
+            .line(line - 1)
+            .chars()
+            .take(column)
+            .map(|ch| column_width(ch, encoding))
+            .sum();
+        Some(LineColumn { line, column })
+    }
+
+    /// Converts a line and column, with the column counted in `encoding`,
+    /// into a char offset in the source map. Returns `None` if the position
+    /// is not at a char boundary within a line of this file.
+    fn line_column_offset(&self, position: LineColumn, encoding: ColumnEncoding) -> Option<usize> {
+        let line = position.line.checked_sub(1)?;
+        let start = *self.lines.get(line)?;
+        let end = match self.lines.get(line + 1) {
+            Some(next) => next - 1,
+            None => (self.span.hi - self.span.lo) as usize,
+        };
+        let column = if encoding == ColumnEncoding::Chars {
+            position.column
+        } else {
+            let mut width = 0;
+            let mut chars = 0;
+            for ch in self.source.as_ref()?.line(line).chars() {
+                if width >= position.column {
+                    break;
+                }
+                width += column_width(ch, encoding);
+                chars += 1;
+            }
+            if width != position.column {
+                return None;
+            }
+            chars
+        };
+        if column > end - start {
+            return None;
+        }
+        Some(self.span.lo as usize + start + column)
+    }
+
     fn span_within(&self, span: Span) -> bool {
         span.lo >= self.span.lo && span.hi <= self.span.hi
     }
+
+    /// Converts a char offset in the source map into a byte offset from the
+    /// start of this file, if its source text is known.
+    fn byte_offset(&self, offset: usize) -> Option<usize> {
+        let source = self.source.as_ref()?;
+        let offset = offset - self.span.lo as usize;
+        let line = match self.lines.binary_search(&offset) {
+            Ok(found) => found,
+            Err(idx) => idx - 1,
+        };
+        let start = source.line_bytes[line];
+        let rest = &source.text[start..];
+        let column = offset - self.lines[line];
+        Some(
+            start
+                + rest
+                    .char_indices()
+                    .nth(column)
+                    .map_or(rest.len(), |(i, _)| i),
+        )
+    }
+
+    fn byte_range(&self, span: Span) -> Option<Range<usize>> {
+        Some(self.byte_offset(span.lo as usize)?..self.byte_offset(span.hi as usize)?)
+    }
 }
 
 /// Computes the offsets of each line in the given source string
@@ -290,56 +763,242 @@ fn lines_offsets(s: &str) -> (usize, Vec<usize>) {
     (total, lines)
 }
 
-struct SourceMap {
-    files: Vec<FileInfo>,
+/// Number of shards that the files of each source map are spread over.
+const SHARDS: usize = 16;
+
+static NEXT_SHARD: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    /// The shard which files added by this thread go into.
+    static SHARD: usize = NEXT_SHARD.fetch_add(1, Ordering::Relaxed) % SHARDS;
 }
 
-impl SourceMap {
-    fn next_start_pos(&self) -> u32 {
-        // Add 1 so there's always space between files.
-        //
-        // We'll always have at least 1 file, as we initialize our files list
-        // with a dummy file.
-        self.files.last().unwrap().span.hi + 1
+/// The files of one source map.
+///
+/// Files are spread over shards, one per group of threads, so that threads
+/// adding files at the same time rarely wait for each other. Positions are
+/// reserved from a shared counter while holding the lock of the shard the
+/// file goes into, so that each shard is sorted by position and can be
+/// binary searched.
+struct SourceMapInner {
+    id: u32,
+    /// The dummy file that call_site() spans point into.
+    dummy: FileInfo,
+    shards: [RwLock<Vec<FileInfo>>; SHARDS],
+    /// Position of the start of the next file.
+    next: AtomicU32,
+    /// Number of files added, not counting the dummy file, which unnamed
+    /// files are numbered by.
+    len: AtomicUsize,
+}
+
+impl SourceMapInner {
+    fn new(id: u32) -> Self {
+        SourceMapInner {
+            id,
+            // NOTE: We start with a dummy file so that positions in every map
+            // start at 1, leaving 0 for call_site() spans.
+            dummy: FileInfo {
+                path: PathBuf::from("<unspecified>"),
+                is_real: false,
+                span: Span {
+                    lo: 0,
+                    hi: 0,
+                    map: id,
+                },
+                lines: vec![0],
+                source: None,
+            },
+            shards: Default::default(),
+            next: AtomicU32::new(1),
+            len: AtomicUsize::new(0),
+        }
     }
 
-    fn add_file(&mut self, name: &str, src: &str) -> Span {
+    /// Adds a file with the given source text, named after its index in the
+    /// source map if it has no path. Returns `None` if there are not enough
+    /// positions left in the source map for it.
+    fn add_file(&self, path: Option<PathBuf>, src: &str) -> Option<Span> {
         let (len, lines) = lines_offsets(src);
-        let lo = self.next_start_pos();
-        // XXX(nika): Should we bother doing a checked cast or checked add here?
+        let line_bytes = iter::once(0)
+            .chain(src.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        let source = SourceText {
+            text: Box::from(src),
+            line_bytes,
+        };
+        let is_real = path.is_some();
+        self.add_file_lines(path, is_real, len, lines, Some(source))
+    }
+
+    fn add_file_lines(
+        &self,
+        path: Option<PathBuf>,
+        is_real: bool,
+        len: usize,
+        lines: Vec<usize>,
+        source: Option<SourceText>,
+    ) -> Option<Span> {
+        let len = u32::try_from(len).ok()?;
+        let mut files = self.shards[SHARD.with(|shard| *shard)].write();
+        // Add 1 so there's always space between files. Positions are never
+        // reused, so once they run out every later file is refused rather
+        // than wrapping around onto the spans of earlier ones.
+        let lo = self
+            .next
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |next| {
+                next.checked_add(len)?.checked_add(1)
+            })
+            .ok()?;
         let span = Span {
             lo,
-            hi: lo + (len as u32),
+            hi: lo + len,
+            map: self.id,
         };
 
-        self.files.push(FileInfo {
-            name: name.to_owned(),
+        let index = self.len.fetch_add(1, Ordering::Relaxed) + 1;
+        let path = path.unwrap_or_else(|| PathBuf::from(format!("<parsed string {index}>")));
+
+        files.push(FileInfo {
+            path,
+            is_real,
             span,
             lines,
+            source,
         });
 
-        span
+        Some(span)
     }
 
-    fn fileinfo(&self, span: Span) -> &FileInfo {
-        for file in &self.files {
-            if file.span_within(span) {
-                return file;
+    /// Runs `f` with the file containing `span`, looking in the shard of the
+    /// current thread first since that is where the files it lexed are.
+    fn with_file<R>(&self, span: Span, f: impl FnOnce(&FileInfo) -> R) -> R {
+        if self.dummy.span_within(span) {
+            return f(&self.dummy);
+        }
+        let first = SHARD.with(|shard| *shard);
+        for i in 0..SHARDS {
+            let files = self.shards[(first + i) % SHARDS].read();
+            // Files never overlap, so the only candidate in a shard is the
+            // last one starting at or before the span.
+            let idx = files.partition_point(|file| file.span.lo <= span.lo);
+            match idx.checked_sub(1).map(|idx| &files[idx]) {
+                Some(file) if file.span_within(span) => return f(file),
+                _ => {}
             }
         }
         panic!("Invalid span with no related FileInfo!");
     }
 }
 
+/// A file which spans can point into, without its source text, as written by
+/// `TokenStream::encode`.
+pub(crate) struct FileRecord {
+    pub path: PathBuf,
+    pub is_real: bool,
+    /// Length of the file in chars.
+    pub len: usize,
+    /// Char offset of the start of each line.
+    pub lines: Vec<usize>,
+}
+
+/// Returns the span of the whole file that `span` points into, or `None` if
+/// `span` behaves like `call_site()`.
+pub(crate) fn file_span(span: Span) -> Option<Span> {
+    with_fileinfo(span, |file| file.span).filter(|file| file.hi != 0)
+}
+
+/// Returns the record of the file whose whole span is `file`, as returned by
+/// `file_span`.
+pub(crate) fn file_record(file: Span) -> Option<FileRecord> {
+    with_fileinfo(file, |file| FileRecord {
+        path: file.path.clone(),
+        is_real: file.is_real,
+        len: (file.span.hi - file.span.lo) as usize,
+        lines: file.lines.clone(),
+    })
+}
+
+/// Adds a file with the given record to `map`, or to the default source map,
+/// and returns the span of the whole file, or `None` if the source map is
+/// full.
+pub(crate) fn add_file_record(map: Option<&SourceMap>, record: FileRecord) -> Option<Span> {
+    let FileRecord {
+        path,
+        is_real,
+        len,
+        lines,
+    } = record;
+    match map {
+        Some(map) => map
+            .files
+            .add_file_lines(Some(path), is_real, len, lines, None),
+        None => SOURCE_MAP.add_file_lines(Some(path), is_real, len, lines, None),
+    }
+}
+
+/// A handle to a source map other than the default one. Its files are freed
+/// when the handle is dropped.
+pub(crate) struct SourceMap {
+    id: u32,
+    files: Arc<SourceMapInner>,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        let id = NEXT_SOURCE_MAP_ID.fetch_add(1, Ordering::Relaxed);
+        if id == 0 {
+            panic!("Exhausted source map ids");
+        }
+        let files = Arc::new(SourceMapInner::new(id));
+        SCOPED_SOURCE_MAPS.write().insert(id, Arc::clone(&files));
+        SourceMap { id, files }
+    }
+
+    pub fn token_stream(
+        &self,
+        path: Option<&Path>,
+        src: &str,
+        options: LexOptions,
+    ) -> Result<TokenStream, LexError> {
+        let cursor = get_cursor(&self.files, path, src)?;
+        parse::token_stream(cursor, options)
+    }
+
+    pub fn literal(&self, repr: &str) -> Result<Literal, LexError> {
+        Literal::parse_in(repr, |src| get_cursor(&self.files, None, src))
+    }
+}
+
+impl Drop for SourceMap {
+    fn drop(&mut self) {
+        SCOPED_SOURCE_MAPS.write().remove(&self.id);
+    }
+}
+
+impl Debug for SourceMap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SourceMap")
+            .field("files", &self.files.len.load(Ordering::Relaxed))
+            .finish_non_exhaustive()
+    }
+}
+
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub(crate) struct Span {
     pub(crate) lo: u32,
     pub(crate) hi: u32,
+    /// Id of the source map that `lo` and `hi` are positions in.
+    pub(crate) map: u32,
 }
 
 impl Span {
     pub fn call_site() -> Self {
-        Span { lo: 0, hi: 0 }
+        Span {
+            lo: 0,
+            hi: 0,
+            map: 0,
+        }
     }
 
     pub fn mixed_site() -> Self {
@@ -361,35 +1020,77 @@ impl Span {
         other
     }
 
+    // Spans into a source map which has since been dropped report the same
+    // location as `Span::call_site()`.
+
     pub fn source_file(&self) -> SourceFile {
-        let cm = SOURCE_MAP.read();
-        let fi = cm.fileinfo(*self);
-        SourceFile {
-            path: Path::new(&fi.name).to_owned(),
-        }
+        with_fileinfo(*self, |fi| SourceFile {
+            path: fi.path.clone(),
+            is_real: fi.is_real,
+        })
+        .unwrap_or_else(|| Span::call_site().source_file())
     }
 
     pub fn start(&self) -> LineColumn {
-        let cm = SOURCE_MAP.read();
-        let fi = cm.fileinfo(*self);
-        fi.offset_line_column(self.lo as usize)
+        with_fileinfo(*self, |fi| fi.offset_line_column(self.lo as usize))
+            .unwrap_or(LineColumn { line: 1, column: 0 })
     }
 
     pub fn end(&self) -> LineColumn {
-        let cm = SOURCE_MAP.read();
-        let fi = cm.fileinfo(*self);
-        fi.offset_line_column(self.hi as usize)
+        with_fileinfo(*self, |fi| fi.offset_line_column(self.hi as usize))
+            .unwrap_or(LineColumn { line: 1, column: 0 })
+    }
+
+    pub fn start_in(&self, encoding: ColumnEncoding) -> Option<LineColumn> {
+        with_fileinfo(*self, |fi| {
+            fi.offset_line_column_in(self.lo as usize, encoding)
+        })
+        .unwrap_or(Some(LineColumn { line: 1, column: 0 }))
+    }
+
+    pub fn end_in(&self, encoding: ColumnEncoding) -> Option<LineColumn> {
+        with_fileinfo(*self, |fi| {
+            fi.offset_line_column_in(self.hi as usize, encoding)
+        })
+        .unwrap_or(Some(LineColumn { line: 1, column: 0 }))
+    }
+
+    pub fn at_line_column(&self, position: LineColumn, encoding: ColumnEncoding) -> Option<Span> {
+        with_fileinfo(*self, |fi| {
+            if fi.span.hi == 0 {
+                return None;
+            }
+            let offset = fi.line_column_offset(position, encoding)? as u32;
+            Some(Span {
+                lo: offset,
+                hi: offset,
+                map: self.map,
+            })
+        })?
+    }
+
+    pub fn byte_range(&self) -> Range<usize> {
+        with_fileinfo(*self, |fi| fi.byte_range(*self))
+            .flatten()
+            .unwrap_or(0..0)
+    }
+
+    pub fn source_text(&self) -> Option<String> {
+        with_fileinfo(*self, |fi| {
+            let range = fi.byte_range(*self)?;
+            Some(fi.source.as_ref()?.text[range].to_owned())
+        })?
     }
 
     pub fn join(&self, other: Span) -> Option<Span> {
-        let cm = SOURCE_MAP.read();
         // If `other` is not within the same FileInfo as us, return None.
-        if !cm.fileinfo(*self).span_within(other) {
+        if self.map != other.map || !with_fileinfo(*self, |fi| fi.span_within(other))? {
             return None;
         }
         Some(Span {
             lo: cmp::min(self.lo, other.lo),
             hi: cmp::max(self.hi, other.hi),
+            map: self.map,
         })
     }
 
@@ -397,6 +1098,7 @@ impl Span {
         Span {
             lo: self.lo,
             hi: cmp::min(self.lo.saturating_add(1), self.hi),
+            map: self.map,
         }
     }
 
@@ -404,13 +1106,14 @@ impl Span {
         Span {
             lo: cmp::max(self.hi.saturating_sub(1), self.lo),
             hi: self.hi,
+            map: self.map,
         }
     }
 }
 
 impl Debug for Span {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        return write!(f, "{} bytes", self.hi - self.lo + 1);
+        write!(f, "{} bytes", self.hi - self.lo + 1)
     }
 }
 
@@ -424,19 +1127,31 @@ pub(crate) fn debug_span_field_if_nontrivial(debug: &mut fmt::DebugStruct, span:
     debug.field("span", &span);
 }
 
+pub(crate) fn debug_trivia_field_if_some(debug: &mut fmt::DebugStruct, trivia: Option<&str>) {
+    if let Some(trivia) = trivia {
+        debug.field("leading_trivia", &trivia);
+    }
+}
+
 #[derive(Clone)]
 pub(crate) struct Group {
     delimiter: Delimiter,
-    stream: TokenStream,
+    pub(crate) stream: crate::TokenStream,
     span: Span,
+    leading: Option<Arc<str>>,
+    /// Source text of the doc comment that this `[doc = "..."]` group was
+    /// lexed from, if lexed with trivia.
+    pub(crate) doc: Option<Arc<str>>,
 }
 
 impl Group {
     pub fn new(delimiter: Delimiter, stream: TokenStream) -> Self {
         Group {
             delimiter,
-            stream,
+            stream: crate::TokenStream::_new_stable(stream),
             span: Span::call_site(),
+            leading: None,
+            doc: None,
         }
     }
 
@@ -445,7 +1160,40 @@ impl Group {
     }
 
     pub fn stream(&self) -> TokenStream {
-        self.stream.clone()
+        self.stream.inner.clone()
+    }
+
+    pub(crate) fn stream_tokens(&self) -> &[TokenTree] {
+        &self.stream.inner.inner
+    }
+
+    pub fn stream_mut(&mut self) -> &mut crate::TokenStream {
+        &mut self.stream
+    }
+
+    /// Returns the source text of the doc comment this group was lexed from,
+    /// along with the comment's text and whether it is an inner doc comment,
+    /// as long as the group is still the `[doc = "..."]` which the comment
+    /// stands for. Contents edited in place no longer print as the comment.
+    pub(crate) fn doc_comment(&self) -> Option<(&str, &str, bool)> {
+        let comment = self.doc.as_deref()?;
+        // A line comment ended by "\r\n" keeps its "\r".
+        let (text, inner) = parse::doc_comment_text(comment.strip_suffix('\r').unwrap_or(comment))?;
+        match self.stream_tokens() {
+            [TokenTree::Ident(ident), TokenTree::Punct(eq), TokenTree::Literal(literal)]
+                if self.delimiter == Delimiter::Bracket
+                    && ident == "doc"
+                    && eq.as_char() == '='
+                    && literal.inner.repr == Literal::string(text).repr =>
+            {
+                Some((comment, text, inner))
+            }
+            _ => None,
+        }
+    }
+
+    pub fn into_stream(self) -> crate::TokenStream {
+        self.stream
     }
 
     pub fn span(&self) -> Span {
@@ -463,6 +1211,14 @@ impl Group {
     pub fn set_span(&mut self, span: Span) {
         self.span = span;
     }
+
+    pub fn leading_trivia(&self) -> Option<&str> {
+        self.leading.as_deref()
+    }
+
+    pub fn set_leading_trivia(&mut self, trivia: Option<Arc<str>>) {
+        self.leading = trivia;
+    }
 }
 
 impl Display for Group {
@@ -473,17 +1229,22 @@ impl Display for Group {
     // Nonempty brackets: [...]
     // Empty braces: { }
     // Nonempty braces: { ... }
+    // Braces around a stream lexed with trivia print no extra spaces.
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let lossless = self.stream.inner.trailing.is_some();
         let (open, close) = match self.delimiter {
             Delimiter::Parenthesis => ("(", ")"),
+            Delimiter::Brace if lossless => ("{", "}"),
             Delimiter::Brace => ("{ ", "}"),
             Delimiter::Bracket => ("[", "]"),
             Delimiter::None => ("", ""),
         };
 
         f.write_str(open)?;
-        Display::fmt(&self.stream, f)?;
-        if self.delimiter == Delimiter::Brace && !self.stream.inner.is_empty() {
+        if self.stream.inner.write(f)? {
+            f.write_str("\n")?;
+        }
+        if self.delimiter == Delimiter::Brace && !self.stream.is_empty() && !lossless {
             f.write_str(" ")?;
         }
         f.write_str(close)?;
@@ -492,12 +1253,29 @@ impl Display for Group {
     }
 }
 
+impl PartialEq for Group {
+    fn eq(&self, other: &Group) -> bool {
+        self.delimiter == other.delimiter && self.stream == other.stream
+    }
+}
+
+impl Hash for Group {
+    fn hash<H: Hasher>(&self, hasher: &mut H) {
+        self.delimiter.hash(hasher);
+        self.stream.hash(hasher);
+    }
+}
+
 impl Debug for Group {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         let mut debug = fmt.debug_struct("Group");
         debug.field("delimiter", &self.delimiter);
         debug.field("stream", &self.stream);
         debug_span_field_if_nontrivial(&mut debug, self.span);
+        debug_trivia_field_if_some(&mut debug, self.leading.as_deref());
+        if let Some(doc) = &self.doc {
+            debug.field("doc", doc);
+        }
         debug.finish()
     }
 }
@@ -507,17 +1285,28 @@ pub(crate) struct Ident {
     sym: String,
     span: Span,
     raw: bool,
+    leading: Option<Arc<str>>,
 }
 
 impl Ident {
     fn _new(string: &str, raw: bool, span: Span) -> Self {
-        validate_ident(string, raw);
+        match Ident::_try_new(string, raw, span) {
+            Ok(ident) => ident,
+            Err(err) => panic!("{}", err),
+        }
+    }
 
-        Ident {
+    fn _try_new(string: &str, raw: bool, span: Span) -> Result<Self, IdentError> {
+        if let Err(kind) = validate_ident(string, raw) {
+            return Err(IdentError::_new(kind, string));
+        }
+
+        Ok(Ident {
             sym: string.to_owned(),
             span,
             raw,
-        }
+            leading: None,
+        })
     }
 
     pub fn new(string: &str, span: Span) -> Self {
@@ -528,6 +1317,22 @@ impl Ident {
         Ident::_new(string, true, span)
     }
 
+    pub fn try_new(string: &str, span: Span) -> Result<Self, IdentError> {
+        Ident::_try_new(string, false, span)
+    }
+
+    pub fn try_new_raw(string: &str, span: Span) -> Result<Self, IdentError> {
+        Ident::_try_new(string, true, span)
+    }
+
+    pub(crate) fn sym(&self) -> &str {
+        &self.sym
+    }
+
+    pub(crate) fn is_raw(&self) -> bool {
+        self.raw
+    }
+
     pub fn span(&self) -> Span {
         self.span
     }
@@ -535,6 +1340,14 @@ impl Ident {
     pub fn set_span(&mut self, span: Span) {
         self.span = span;
     }
+
+    pub fn leading_trivia(&self) -> Option<&str> {
+        self.leading.as_deref()
+    }
+
+    pub fn set_leading_trivia(&mut self, trivia: Option<Arc<str>>) {
+        self.leading = trivia;
+    }
 }
 
 pub(crate) fn is_ident_start(c: char) -> bool {
@@ -545,41 +1358,40 @@ pub(crate) fn is_ident_continue(c: char) -> bool {
     unicode_ident::is_xid_continue(c)
 }
 
-fn validate_ident(string: &str, raw: bool) {
+pub(crate) fn is_punct_char(c: char) -> bool {
+    "~!@#$%^&*-=+|;:,<.>/?'".contains(c)
+}
+
+fn validate_ident(string: &str, raw: bool) -> Result<(), IdentErrorKind> {
     if string.is_empty() {
-        panic!("Ident is not allowed to be empty; use Option<Ident>");
+        return Err(IdentErrorKind::Empty);
     }
 
-    if string.bytes().all(|digit| (b'0'..=b'9').contains(&digit)) {
-        panic!("Ident cannot be a number; use Literal instead");
+    if string.bytes().all(|digit| digit.is_ascii_digit()) {
+        return Err(IdentErrorKind::Number);
     }
 
-    fn ident_ok(string: &str) -> bool {
-        let mut chars = string.chars();
-        let first = chars.next().unwrap();
-        if !is_ident_start(first) {
-            return false;
-        }
-        for ch in chars {
-            if !is_ident_continue(ch) {
-                return false;
-            }
-        }
-        true
+    let mut chars = string.chars();
+    let first = chars.next().unwrap();
+    if !is_ident_start(first) {
+        return Err(IdentErrorKind::InvalidChar(first));
     }
-
-    if !ident_ok(string) {
-        panic!("{:?} is not a valid Ident", string);
+    for ch in chars {
+        if !is_ident_continue(ch) {
+            return Err(IdentErrorKind::InvalidChar(ch));
+        }
     }
 
     if raw {
         match string {
             "_" | "super" | "self" | "Self" | "crate" => {
-                panic!("`r#{}` cannot be a raw identifier", string);
+                return Err(IdentErrorKind::ReservedRaw);
             }
             _ => {}
         }
     }
+
+    Ok(())
 }
 
 impl PartialEq for Ident {
@@ -618,8 +1430,10 @@ impl Debug for Ident {
     // }
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let mut debug = f.debug_struct("Ident");
-        debug.field("sym", &format_args!("{}", self));
+        let raw = if self.raw { "r#" } else { "" };
+        debug.field("sym", &format_args!("{}{}", raw, self.sym));
         debug_span_field_if_nontrivial(&mut debug, self.span);
+        debug_trivia_field_if_some(&mut debug, self.leading.as_deref());
         debug.finish()
     }
 }
@@ -628,6 +1442,7 @@ impl Debug for Ident {
 pub(crate) struct Literal {
     repr: String,
     span: Span,
+    leading: Option<Arc<str>>,
 }
 
 macro_rules! suffixed_numbers {
@@ -651,6 +1466,7 @@ impl Literal {
         Literal {
             repr,
             span: Span::call_site(),
+            leading: None,
         }
     }
 
@@ -744,7 +1560,7 @@ impl Literal {
                 b'\\' => escaped.push_str("\\\\"),
                 b'\x20'..=b'\x7E' => escaped.push(*b as char),
                 _ => {
-                    let _ = write!(escaped, "\\x{:02X}", b);
+                    let _ = write!(escaped, "\\x{b:02X}");
                 }
             }
         }
@@ -752,6 +1568,10 @@ impl Literal {
         Literal::_new(escaped)
     }
 
+    pub(crate) fn repr(&self) -> &str {
+        &self.repr
+    }
+
     pub fn span(&self) -> Span {
         self.span
     }
@@ -760,7 +1580,229 @@ impl Literal {
         self.span = span;
     }
 
-    pub fn subspan<R: RangeBounds<usize>>(&self, _range: R) -> Option<Span> {
+    pub fn leading_trivia(&self) -> Option<&str> {
+        self.leading.as_deref()
+    }
+
+    pub fn set_leading_trivia(&mut self, trivia: Option<Arc<str>>) {
+        self.leading = trivia;
+    }
+
+    pub fn subspan<R: RangeBounds<usize>>(&self, range: R) -> Option<Span> {
+        let (start, end) = byte_range(&self.repr, range)?;
+        // The span of a negative literal parsed by `Literal::from_str` does
+        // not include the `-`.
+        let len = self.span.hi.checked_sub(self.span.lo)? as usize;
+        let skipped = match self.repr.chars().count().checked_sub(len)? {
+            0 => 0,
+            1 if self.repr.starts_with('-') && start > 0 => 1,
+            _ => return None,
+        };
+        let lo = self.repr[..start].chars().count() - skipped;
+        let hi = lo + self.repr[start..end].chars().count();
+        Some(Span {
+            lo: self.span.lo + lo as u32,
+            hi: self.span.lo + hi as u32,
+            map: self.span.map,
+        })
+    }
+
+    pub fn value_subspan<R: RangeBounds<usize>>(&self, range: R) -> Option<Span> {
+        let (kind, offset, contents) = self.string_contents()?;
+        let (start, end) = match kind {
+            LiteralKind::Str | LiteralKind::ByteStr => {
+                // Byte range of the value and byte range of the source, for
+                // each unescaped character.
+                let mut chars = Vec::new();
+                let mut len = 0;
+                parse::cooked_value(contents, |source, ch| {
+                    let value_len = match kind {
+                        LiteralKind::Str => ch.len_utf8(),
+                        _ => 1,
+                    };
+                    chars.push((len..len + value_len, source));
+                    len += value_len;
+                })?;
+                let (start, end) = byte_range_in(len, range)?;
+                let lo = match chars.iter().find(|(value, _)| value.start == start) {
+                    Some((_, source)) => source.start,
+                    None if start == len => contents.len(),
+                    None => return None,
+                };
+                let hi = match chars.iter().find(|(value, _)| value.end == end) {
+                    _ if start == end => lo,
+                    Some((_, source)) => source.end,
+                    None => return None,
+                };
+                (lo, hi)
+            }
+            _ => byte_range(contents, range)?,
+        };
+        self.subspan(offset + start..offset + end)
+    }
+
+    /// Returns the kind of a string or byte string literal, the offset of
+    /// its contents in the repr, and its contents between the quotes.
+    fn string_contents(&self) -> Option<(LiteralKind, usize, &str)> {
+        let (kind, token, _suffix) = self.parts()?;
+        let quote = token.find('"')?;
+        let hashes = match kind {
+            LiteralKind::Str | LiteralKind::ByteStr => 0,
+            LiteralKind::RawStr | LiteralKind::RawByteStr => {
+                quote - token[..quote].trim_end_matches('#').len()
+            }
+            _ => return None,
+        };
+        let start = quote + 1;
+        let end = token.len() - hashes - 1;
+        Some((kind, start, &token[start..end]))
+    }
+
+    fn parts(&self) -> Option<(LiteralKind, &str, &str)> {
+        parse::literal_parts(self.repr.strip_prefix('-').unwrap_or(&self.repr))
+    }
+
+    pub fn kind(&self) -> LiteralKind {
+        match self.parts() {
+            Some((kind, _token, _suffix)) => kind,
+            None => LiteralKind::Other,
+        }
+    }
+
+    pub fn suffix(&self) -> &str {
+        match self.parts() {
+            Some((_kind, _token, suffix)) => suffix,
+            None => "",
+        }
+    }
+
+    pub fn str_value(&self) -> Option<String> {
+        let mut value = String::new();
+        match self.string_contents()? {
+            (LiteralKind::Str, _offset, contents) => {
+                parse::cooked_value(contents, |_source, ch| value.push(ch))?;
+            }
+            (LiteralKind::RawStr, _offset, contents) => value.push_str(contents),
+            _ => return None,
+        }
+        Some(value)
+    }
+
+    pub fn bytes_value(&self) -> Option<Vec<u8>> {
+        let mut value = Vec::new();
+        match self.string_contents()? {
+            (LiteralKind::ByteStr, _offset, contents) => {
+                let mut valid = true;
+                parse::cooked_value(contents, |_source, ch| match u8::try_from(ch) {
+                    Ok(byte) => value.push(byte),
+                    Err(_) => valid = false,
+                })?;
+                if !valid {
+                    return None;
+                }
+            }
+            (LiteralKind::RawByteStr, _offset, contents) => {
+                value.extend_from_slice(contents.as_bytes());
+            }
+            _ => return None,
+        }
+        Some(value)
+    }
+
+    pub fn char_value(&self) -> Option<char> {
+        match self.parts()? {
+            (LiteralKind::Char, token, _suffix) => single_char(&token[1..token.len() - 1]),
+            _ => None,
+        }
+    }
+
+    pub fn byte_value(&self) -> Option<u8> {
+        match self.parts()? {
+            (LiteralKind::Byte, token, _suffix) => {
+                u8::try_from(single_char(&token[2..token.len() - 1])?).ok()
+            }
+            _ => None,
+        }
+    }
+
+    pub fn int_value<T>(&self) -> Option<T>
+    where
+        T: TryFrom<u128> + TryFrom<i128>,
+    {
+        let (LiteralKind::Int, token, _suffix) = self.parts()? else {
+            return None;
+        };
+        let (radix, digits) = if let Some(digits) = token.strip_prefix("0x") {
+            (16, digits)
+        } else if let Some(digits) = token.strip_prefix("0o") {
+            (8, digits)
+        } else if let Some(digits) = token.strip_prefix("0b") {
+            (2, digits)
+        } else {
+            (10, token)
+        };
+        let digits = digits.replace('_', "");
+        let magnitude = u128::from_str_radix(&digits, radix).ok()?;
+        if self.repr.starts_with('-') {
+            T::try_from(0i128.checked_sub_unsigned(magnitude)?).ok()
+        } else {
+            T::try_from(magnitude).ok()
+        }
+    }
+
+    pub fn float_value(&self) -> Option<f64> {
+        let (LiteralKind::Float, token, _suffix) = self.parts()? else {
+            return None;
+        };
+        let mut float = token.replace('_', "");
+        if self.repr.starts_with('-') {
+            float.insert(0, '-');
+        }
+        float.parse().ok()
+    }
+}
+
+/// Resolves `range` to a pair of byte offsets into `s`, which must be in
+/// bounds and on character boundaries.
+fn byte_range<R: RangeBounds<usize>>(s: &str, range: R) -> Option<(usize, usize)> {
+    let (start, end) = byte_range_in(s.len(), range)?;
+    if s.is_char_boundary(start) && s.is_char_boundary(end) {
+        Some((start, end))
+    } else {
+        None
+    }
+}
+
+/// Resolves `range` to a pair of offsets no greater than `len`.
+fn byte_range_in<R: RangeBounds<usize>>(len: usize, range: R) -> Option<(usize, usize)> {
+    let start = match range.start_bound() {
+        Bound::Included(&start) => start,
+        Bound::Excluded(&start) => start.checked_add(1)?,
+        Bound::Unbounded => 0,
+    };
+    let end = match range.end_bound() {
+        Bound::Included(&end) => end.checked_add(1)?,
+        Bound::Excluded(&end) => end,
+        Bound::Unbounded => len,
+    };
+    if start <= end && end <= len {
+        Some((start, end))
+    } else {
+        None
+    }
+}
+
+/// Unescapes the contents of a character or byte literal.
+fn single_char(contents: &str) -> Option<char> {
+    let mut value = None;
+    let mut len = 0;
+    parse::cooked_value(contents, |_source, ch| {
+        value = Some(ch);
+        len += 1;
+    })?;
+    if len == 1 {
+        value
+    } else {
         None
     }
 }
@@ -768,24 +1810,65 @@ impl Literal {
 impl FromStr for Literal {
     type Err = LexError;
 
-    fn from_str(mut repr: &str) -> Result<Self, Self::Err> {
+    fn from_str(repr: &str) -> Result<Self, Self::Err> {
+        Literal::parse_in(repr, |src| get_cursor(&SOURCE_MAP, None, src))
+    }
+}
+
+impl Literal {
+    /// Parses `repr` without recording it in a source map, giving the
+    /// literal a call_site() span.
+    pub(crate) fn parse_unrecorded(repr: &str) -> Option<Self> {
+        let cursor = |rest| {
+            Ok(Cursor {
+                rest,
+                off: 0,
+                map: 0,
+            })
+        };
+        let mut literal = Literal::parse_in(repr, cursor).ok()?;
+        literal.span = Span::call_site();
+        Some(literal)
+    }
+
+    fn parse_in<'a>(
+        mut repr: &'a str,
+        get_cursor: impl FnOnce(&'a str) -> Result<Cursor<'a>, LexError>,
+    ) -> Result<Self, LexError> {
         let negative = repr.starts_with('-');
         if negative {
             repr = &repr[1..];
             if !repr.starts_with(|ch: char| ch.is_ascii_digit()) {
-                return Err(LexError::call_site());
+                return Err(LexError::call_site(LexErrorKind::InvalidLiteral));
             }
         }
-        let cursor = get_cursor(repr);
-        if let Ok((_rest, mut literal)) = parse::literal(cursor) {
-            if literal.repr.len() == repr.len() {
+        let cursor = get_cursor(repr)?;
+        match parse::literal(cursor) {
+            Ok((rest, mut literal)) if rest.rest.is_empty() => {
                 if negative {
                     literal.repr.insert(0, '-');
                 }
-                return Ok(literal);
+                Ok(literal)
             }
+            Ok((rest, _literal)) => Err(LexError {
+                kind: LexErrorKind::InvalidLiteral,
+                start: cursor.char_span(),
+                end: rest.char_span(),
+            }),
+            Err(_) => Err(parse::literal_error(cursor)),
         }
-        Err(LexError::call_site())
+    }
+}
+
+impl PartialEq for Literal {
+    fn eq(&self, other: &Literal) -> bool {
+        self.repr == other.repr
+    }
+}
+
+impl Hash for Literal {
+    fn hash<H: Hasher>(&self, hasher: &mut H) {
+        self.repr.hash(hasher);
     }
 }
 
@@ -800,6 +1883,7 @@ impl Debug for Literal {
         let mut debug = fmt.debug_struct("Literal");
         debug.field("lit", &format_args!("{}", self.repr));
         debug_span_field_if_nontrivial(&mut debug, self.span);
+        debug_trivia_field_if_some(&mut debug, self.leading.as_deref());
         debug.finish()
     }
 }
@@ -0,0 +1,341 @@
+//! Conversions to and from `proc_macro2` types, behind the `proc-macro2`
+//! feature.
+//!
+//! `proc_macro2` has no way to create a span at a given location, so spans
+//! converted into it are always `Span::call_site()`.
+//!
+//! In the other direction, the `From` and `TryFrom` conversions make every
+//! span `Span::call_site()` as well, since keeping its location means
+//! recording its file in a source map, and the process-wide one is never
+//! freed. `SourceMap::convert_token_stream` keeps them instead: it first
+//! collects the line/column of every span in the stream, grouped by file, then
+//! records each file in that source map as a file with no text, just long
+//! enough lines that every one of those positions exists, so that the
+//! converted spans report the same file and line/column as the originals.
+//!
+//! Every literal this crate lexes is one that `proc_macro2` lexes too, so
+//! converting into `proc_macro2` cannot fail. Converting from it fails on the
+//! literals this crate does not lex, such as C string literals.
+
+use crate::fallback::{self, FileRecord};
+use crate::{
+    imp, Delimiter, Group, Ident, LexError, LexErrorKind, Literal, Punct, Spacing, Span,
+    TokenStream, TokenTree,
+};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::path::PathBuf;
+
+/// Converts a token stream into `proc_macro2`, with every span
+/// `proc_macro2::Span::call_site()`.
+impl From<TokenStream> for proc_macro2::TokenStream {
+    fn from(tokens: TokenStream) -> Self {
+        convert_trees(&tokens.inner.inner)
+    }
+}
+
+/// Converts a token tree like `proc_macro2::TokenStream::from`.
+impl From<TokenTree> for proc_macro2::TokenTree {
+    fn from(tt: TokenTree) -> Self {
+        match tt {
+            TokenTree::Group(t) => proc_macro2::TokenTree::Group(t.into()),
+            TokenTree::Ident(t) => proc_macro2::TokenTree::Ident(t.into()),
+            TokenTree::Punct(t) => proc_macro2::TokenTree::Punct(t.into()),
+            TokenTree::Literal(t) => proc_macro2::TokenTree::Literal(t.into()),
+        }
+    }
+}
+
+/// Converts a group like `proc_macro2::TokenStream::from`.
+impl From<Group> for proc_macro2::Group {
+    fn from(group: Group) -> Self {
+        let stream = convert_trees(group.inner.stream_tokens());
+        let delimiter = match group.delimiter() {
+            Delimiter::Parenthesis => proc_macro2::Delimiter::Parenthesis,
+            Delimiter::Brace => proc_macro2::Delimiter::Brace,
+            Delimiter::Bracket => proc_macro2::Delimiter::Bracket,
+            Delimiter::None => proc_macro2::Delimiter::None,
+        };
+        proc_macro2::Group::new(delimiter, stream)
+    }
+}
+
+/// Converts an identifier with the span `proc_macro2::Span::call_site()`.
+impl From<Ident> for proc_macro2::Ident {
+    fn from(ident: Ident) -> Self {
+        let span = proc_macro2::Span::call_site();
+        if ident.inner.is_raw() {
+            proc_macro2::Ident::new_raw(ident.inner.sym(), span)
+        } else {
+            proc_macro2::Ident::new(ident.inner.sym(), span)
+        }
+    }
+}
+
+/// Converts a punctuation character with the span
+/// `proc_macro2::Span::call_site()`.
+impl From<Punct> for proc_macro2::Punct {
+    fn from(punct: Punct) -> Self {
+        let spacing = match punct.spacing() {
+            Spacing::Alone => proc_macro2::Spacing::Alone,
+            Spacing::Joint => proc_macro2::Spacing::Joint,
+        };
+        proc_macro2::Punct::new(punct.as_char(), spacing)
+    }
+}
+
+/// Converts a literal with the span `proc_macro2::Span::call_site()`.
+impl From<Literal> for proc_macro2::Literal {
+    fn from(literal: Literal) -> Self {
+        // Every literal this crate lexes or constructs lexes in `proc_macro2`
+        // too.
+        literal.inner.repr().parse().unwrap()
+    }
+}
+
+/// Always returns `proc_macro2::Span::call_site()`.
+impl From<Span> for proc_macro2::Span {
+    fn from(_span: Span) -> Self {
+        proc_macro2::Span::call_site()
+    }
+}
+
+fn convert_trees(tokens: &[TokenTree]) -> proc_macro2::TokenStream {
+    tokens
+        .iter()
+        .map(|tt| proc_macro2::TokenTree::from(tt.clone()))
+        .collect()
+}
+
+/// Converts a token stream from `proc_macro2`, with every span
+/// `Span::call_site()`. Use `SourceMap::convert_token_stream` to keep their
+/// line/column.
+///
+/// Fails with `LexErrorKind::InvalidLiteral` if the stream contains a literal
+/// which this crate does not lex, such as a C string literal.
+impl TryFrom<proc_macro2::TokenStream> for TokenStream {
+    type Error = LexError;
+
+    fn try_from(tokens: proc_macro2::TokenStream) -> Result<Self, Self::Error> {
+        Files::default().stream(tokens)
+    }
+}
+
+/// Converts a token tree like `TokenStream::try_from`.
+impl TryFrom<proc_macro2::TokenTree> for TokenTree {
+    type Error = LexError;
+
+    fn try_from(tt: proc_macro2::TokenTree) -> Result<Self, Self::Error> {
+        Files::default().tree(tt)
+    }
+}
+
+/// Converts a group like `TokenStream::try_from`.
+impl TryFrom<proc_macro2::Group> for Group {
+    type Error = LexError;
+
+    fn try_from(group: proc_macro2::Group) -> Result<Self, Self::Error> {
+        Files::default().group(&group)
+    }
+}
+
+/// Converts an identifier, with the span `Span::call_site()`.
+impl From<proc_macro2::Ident> for Ident {
+    fn from(ident: proc_macro2::Ident) -> Self {
+        ident_from(&ident, Span::call_site())
+    }
+}
+
+/// Converts a punctuation character, with the span `Span::call_site()`.
+impl From<proc_macro2::Punct> for Punct {
+    fn from(punct: proc_macro2::Punct) -> Self {
+        punct_from(&punct, Span::call_site())
+    }
+}
+
+/// Converts a literal, with the span `Span::call_site()`.
+///
+/// Fails with `LexErrorKind::InvalidLiteral` if this crate does not lex the
+/// literal, such as a C string literal.
+impl TryFrom<proc_macro2::Literal> for Literal {
+    type Error = LexError;
+
+    fn try_from(literal: proc_macro2::Literal) -> Result<Self, Self::Error> {
+        literal_from(&literal, Span::call_site())
+    }
+}
+
+/// Always returns `Span::call_site()`. Use `SourceMap::convert_token_stream`
+/// to keep the line/column of spans.
+impl From<proc_macro2::Span> for Span {
+    fn from(_span: proc_macro2::Span) -> Self {
+        Span::call_site()
+    }
+}
+
+/// Converts `tokens` with the line/column of their spans, recording the files
+/// they point into in `map`.
+pub(crate) fn convert(
+    map: &fallback::SourceMap,
+    tokens: proc_macro2::TokenStream,
+) -> Result<TokenStream, LexError> {
+    let mut files = Files::default();
+    files.add_stream(tokens.clone());
+    files.record(map)?;
+    files.stream(tokens)
+}
+
+fn ident_from(ident: &proc_macro2::Ident, span: Span) -> Ident {
+    let string = ident.to_string();
+    match string.strip_prefix("r#") {
+        Some(sym) => Ident::new_raw(sym, span),
+        None => Ident::new(&string, span),
+    }
+}
+
+fn punct_from(punct: &proc_macro2::Punct, span: Span) -> Punct {
+    let spacing = match punct.spacing() {
+        proc_macro2::Spacing::Alone => Spacing::Alone,
+        proc_macro2::Spacing::Joint => Spacing::Joint,
+    };
+    let mut out = Punct::new(punct.as_char(), spacing);
+    out.set_span(span);
+    out
+}
+
+fn literal_from(literal: &proc_macro2::Literal, span: Span) -> Result<Literal, LexError> {
+    // Not `Literal::from_str`, which would record the literal as a file in
+    // the global source map.
+    match imp::Literal::parse_unrecorded(&literal.to_string()) {
+        Some(inner) => {
+            let mut literal = Literal::_new(inner);
+            literal.set_span(span);
+            Ok(literal)
+        }
+        None => Err(LexError {
+            inner: fallback::LexError::call_site(LexErrorKind::InvalidLiteral),
+        }),
+    }
+}
+
+/// The files which the spans being converted point into. Spans into files
+/// which have not been recorded convert to `Span::call_site()`.
+#[derive(Default)]
+struct Files {
+    files: HashMap<String, File>,
+}
+
+struct File {
+    is_real: bool,
+    /// The greatest column used on each line.
+    widths: Vec<usize>,
+    /// The offset of the start of each line, once recorded.
+    lines: Vec<usize>,
+    /// The span of the whole file in the source map, once recorded.
+    span: Option<fallback::Span>,
+}
+
+impl Files {
+    fn add_stream(&mut self, tokens: proc_macro2::TokenStream) {
+        for tt in tokens {
+            self.add_tree(&tt);
+        }
+    }
+
+    fn add_tree(&mut self, tt: &proc_macro2::TokenTree) {
+        self.add_span(tt.span());
+        if let proc_macro2::TokenTree::Group(group) = tt {
+            self.add_stream(group.stream());
+        }
+    }
+
+    fn add_span(&mut self, span: proc_macro2::Span) {
+        let (start, end) = (span.start(), span.end());
+        if start == end {
+            return;
+        }
+        let file = self.files.entry(span.file()).or_insert_with(|| File {
+            is_real: span.local_file().is_some(),
+            widths: Vec::new(),
+            lines: Vec::new(),
+            span: None,
+        });
+        for position in &[start, end] {
+            if file.widths.len() < position.line {
+                file.widths.resize(position.line, 0);
+            }
+            let width = &mut file.widths[position.line - 1];
+            *width = (*width).max(position.column);
+        }
+    }
+
+    /// Adds every file to `map`.
+    fn record(&mut self, map: &fallback::SourceMap) -> Result<(), LexError> {
+        for (path, file) in &mut self.files {
+            let mut lines = Vec::with_capacity(file.widths.len());
+            let mut len = 0;
+            for width in &file.widths {
+                lines.push(len);
+                len += width + 1;
+            }
+            let record = FileRecord {
+                path: PathBuf::from(path),
+                is_real: file.is_real,
+                len,
+                lines: lines.clone(),
+            };
+            let Some(span) = fallback::add_file_record(Some(map), record) else {
+                let inner = fallback::LexError::call_site(LexErrorKind::SourceMapFull);
+                return Err(LexError { inner });
+            };
+            file.span = Some(span);
+            file.lines = lines;
+        }
+        Ok(())
+    }
+
+    fn span(&self, span: proc_macro2::Span) -> Span {
+        let (start, end) = (span.start(), span.end());
+        let (file, base) = match self.files.get(&span.file()) {
+            Some(file) if start != end => match file.span {
+                Some(base) => (file, base),
+                None => return Span::call_site(),
+            },
+            _ => return Span::call_site(),
+        };
+        let offset = |position: proc_macro2::LineColumn| {
+            base.lo + (file.lines[position.line - 1] + position.column) as u32
+        };
+        Span::_new(imp::Span {
+            lo: offset(start),
+            hi: offset(end),
+            map: base.map,
+        })
+    }
+
+    fn stream(&self, tokens: proc_macro2::TokenStream) -> Result<TokenStream, LexError> {
+        tokens.into_iter().map(|tt| self.tree(tt)).collect()
+    }
+
+    fn tree(&self, tt: proc_macro2::TokenTree) -> Result<TokenTree, LexError> {
+        let span = self.span(tt.span());
+        Ok(match tt {
+            proc_macro2::TokenTree::Group(group) => TokenTree::Group(self.group(&group)?),
+            proc_macro2::TokenTree::Ident(t) => TokenTree::Ident(ident_from(&t, span)),
+            proc_macro2::TokenTree::Punct(t) => TokenTree::Punct(punct_from(&t, span)),
+            proc_macro2::TokenTree::Literal(t) => TokenTree::Literal(literal_from(&t, span)?),
+        })
+    }
+
+    fn group(&self, group: &proc_macro2::Group) -> Result<Group, LexError> {
+        let delimiter = match group.delimiter() {
+            proc_macro2::Delimiter::Parenthesis => Delimiter::Parenthesis,
+            proc_macro2::Delimiter::Brace => Delimiter::Brace,
+            proc_macro2::Delimiter::Bracket => Delimiter::Bracket,
+            proc_macro2::Delimiter::None => Delimiter::None,
+        };
+        let mut out = Group::new(delimiter, self.stream(group.stream())?);
+        out.set_span(self.span(group.span()));
+        Ok(out)
+    }
+}
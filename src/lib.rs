@@ -15,21 +15,36 @@
     clippy::vec_init_then_push
 )]
 #![warn(unsafe_code)]
-#![feature(doc_auto_cfg, doc_cfg)]
+#![feature(doc_cfg)]
 
+mod diff;
+mod encoding;
 mod fallback;
 mod incompatible;
+#[cfg(feature = "proc-macro2")]
+mod interop;
 mod parse;
+mod pretty;
+mod quote;
+mod search;
+#[cfg(feature = "serde")]
+mod serialization;
+pub mod visit;
 
 use crate::fallback as imp;
 
+use std::convert::TryFrom;
 use std::error::Error;
 use std::fmt::{self, Debug, Display};
 use std::hash::{Hash, Hasher};
-use std::iter::FromIterator;
-use std::ops::RangeBounds;
+use std::iter::{self, FromIterator};
+use std::ops::{Range, RangeBounds};
+use std::path::Path;
+use std::slice;
 use std::str::FromStr;
+use std::sync::Arc;
 use std::{cmp::Ordering, path::PathBuf};
+use std::{fs, io};
 
 /// An abstract stream of tokens, or more concretely a sequence of token trees.
 ///
@@ -44,10 +59,61 @@ pub struct TokenStream {
 }
 
 /// Error returned from `TokenStream::from_str`.
+///
+/// Its `Display` implementation gives a human-readable description of the
+/// error, such as ``mismatched closing delimiter `]`, expected `)` ``.
 pub struct LexError {
     inner: imp::LexError,
 }
 
+/// The reason that a `LexError` was returned.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum LexErrorKind {
+    /// An opening delimiter which is never closed.
+    UnclosedDelimiter(Delimiter),
+    /// A closing delimiter which does not close any group.
+    UnexpectedClosingDelimiter(Delimiter),
+    /// A closing delimiter which does not match the innermost open group, e.g.
+    /// the `]` in `(]`.
+    MismatchedDelimiter {
+        /// The delimiter of the innermost open group.
+        open: Delimiter,
+        /// The closing delimiter that was found instead.
+        close: Delimiter,
+    },
+    /// A string, byte string or character literal which reaches the end of
+    /// the input without its closing quote.
+    UnterminatedLiteral,
+    /// A block comment which reaches the end of the input without its closing
+    /// `*/`.
+    UnterminatedBlockComment,
+    /// An invalid escape sequence in a string, byte string or character
+    /// literal, such as `\q` or `\u{110000}`.
+    InvalidEscape,
+    /// A carriage return which is not followed by a newline inside a literal
+    /// or doc comment.
+    BareCarriageReturn,
+    /// Any other malformed literal, such as `0b2` or `'ab'`.
+    InvalidLiteral,
+    /// A malformed raw identifier, such as `r#_` or `r#1`.
+    InvalidIdent,
+    /// A character which cannot start any token.
+    UnexpectedChar(char),
+    /// The source map has run out of positions for new files, after more than
+    /// 4 billion chars of input were lexed into it.
+    ///
+    /// Positions are never reused, so a full source map stays full: every
+    /// later call that records a file in it fails the same way, while tokens
+    /// lexed before keep their spans. Each `SourceMap` has positions of its
+    /// own, so to keep going, lex the input into a fresh `SourceMap` with
+    /// `SourceMap::parse_token_stream`. If the process-wide map is the one
+    /// that is full, that means switching from `TokenStream::from_str` and
+    /// friends to a `SourceMap`, ideally one per input so that each is freed
+    /// once its tokens are no longer needed.
+    SourceMapFull,
+}
+
 impl TokenStream {
     fn _new(inner: imp::TokenStream) -> Self {
         TokenStream { inner }
@@ -66,6 +132,580 @@ impl TokenStream {
     pub fn is_empty(&self) -> bool {
         self.inner.is_empty()
     }
+
+    /// Parses `src` like `TokenStream::from_str`, but records it in the source
+    /// map as the real file at `path`.
+    ///
+    /// The resulting spans report `path` from `SourceFile::path`, and
+    /// `SourceFile::is_real` returns `true` for them.
+    ///
+    /// # Errors
+    ///
+    /// Fails like `TokenStream::from_str` if `src` does not lex.
+    pub fn from_str_named<P: AsRef<Path>>(path: P, src: &str) -> Result<TokenStream, LexError> {
+        match imp::TokenStream::from_str_named(path.as_ref(), src) {
+            Ok(stream) => Ok(TokenStream::_new(stream)),
+            Err(inner) => Err(LexError { inner }),
+        }
+    }
+
+    /// Reads the file at `path` and parses it as with
+    /// `TokenStream::from_str_named`.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error from reading the file if that fails. If the contents
+    /// fail to lex, the returned error has kind `io::ErrorKind::InvalidData`
+    /// and wraps the `LexError`.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> io::Result<TokenStream> {
+        let path = path.as_ref();
+        let src = fs::read_to_string(path)?;
+        TokenStream::from_str_named(path, &src)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    /// Parses `src` like `TokenStream::from_str`, with the given options.
+    ///
+    /// # Errors
+    ///
+    /// Fails like `TokenStream::from_str` if `src` does not lex.
+    pub fn from_str_with(src: &str, options: &LexOptions) -> Result<TokenStream, LexError> {
+        match imp::TokenStream::from_str_with(src, *options) {
+            Ok(stream) => Ok(TokenStream::_new(stream)),
+            Err(inner) => Err(LexError { inner }),
+        }
+    }
+
+    /// Returns a value which displays this stream formatted over multiple
+    /// lines.
+    ///
+    /// The contents of braces are indented and split into one statement,
+    /// item or field per line, and the contents of parentheses and brackets
+    /// which do not fit within the maximum width are split into one element
+    /// per line. Only whitespace differs from the `Display` output, so the
+    /// result parses back into the same tokens. Trivia recorded by
+    /// `LexOptions::preserve_trivia` is not printed.
+    ///
+    /// ```
+    /// use proc_macro0::{PrettyOptions, TokenStream};
+    ///
+    /// let tokens: TokenStream = "fn f(x: &u8) -> u8 { let y = *x; y + 1 }".parse().unwrap();
+    /// let pretty = tokens.pretty(&PrettyOptions::new()).to_string();
+    /// assert_eq!(pretty, "fn f(x: &u8) -> u8 {\n    let y = *x;\n    y + 1\n}");
+    /// ```
+    pub fn pretty(&self, options: &PrettyOptions) -> Pretty<'_> {
+        Pretty {
+            stream: self,
+            options: *options,
+        }
+    }
+
+    /// Compares this stream with `other` token by token, returning what
+    /// changed between them.
+    ///
+    /// The diff is empty exactly when the streams are equal. Changes inside
+    /// groups which are in both streams are reported at their position
+    /// within the group, and the `Display` implementation of the returned
+    /// `Diff` lists one change per line:
+    ///
+    /// ```
+    /// use proc_macro0::{ChangeKind, SourceMap};
+    ///
+    /// let map = SourceMap::new();
+    /// let old = map.parse_token_stream_named("old.rs", "f(x, 1)").unwrap();
+    /// let new = map.parse_token_stream_named("new.rs", "f(x, 2, y)").unwrap();
+    /// let diff = old.diff(&new);
+    ///
+    /// let change = &diff.changes()[0];
+    /// assert_eq!(change.kind(), ChangeKind::Changed);
+    /// assert_eq!(change.old_path(), [1, 2]);
+    /// assert_eq!(
+    ///     diff.to_string(),
+    ///     "~ [1, 2]: `1` (old.rs:1:6) -> `2` (new.rs:1:6)\n\
+    ///      + [1, 3]: `,` (new.rs:1:7)\n\
+    ///      + [1, 4]: `y` (new.rs:1:9)",
+    /// );
+    /// ```
+    pub fn diff(&self, other: &TokenStream) -> Diff {
+        Diff {
+            changes: diff::diff(&self.inner.inner, &other.inner.inner),
+        }
+    }
+
+    /// Writes this stream to `writer` in a compact binary format, which
+    /// `TokenStream::decode` reads back without lexing it again.
+    ///
+    /// Spans are left out, like with `TokenStream::encode_with` and the
+    /// default `EncodeOptions`. Trivia and doc comments are always kept.
+    ///
+    /// ```
+    /// use proc_macro0::TokenStream;
+    ///
+    /// let tokens: TokenStream = "struct S { x: u8 }".parse().unwrap();
+    /// let mut bytes = Vec::new();
+    /// tokens.encode(&mut bytes).unwrap();
+    /// let decoded = TokenStream::decode(&mut &bytes[..]).unwrap();
+    /// assert_eq!(decoded.to_string(), tokens.to_string());
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns any error from writing to `writer`.
+    pub fn encode(&self, writer: &mut impl io::Write) -> io::Result<()> {
+        self.encode_with(writer, &EncodeOptions::new())
+    }
+
+    /// Writes this stream to `writer` like `TokenStream::encode`, with the
+    /// given options.
+    ///
+    /// # Errors
+    ///
+    /// Returns any error from writing to `writer`.
+    pub fn encode_with(
+        &self,
+        writer: &mut impl io::Write,
+        options: &EncodeOptions,
+    ) -> io::Result<()> {
+        encoding::encode(self, options.spans, writer)
+    }
+
+    /// Reads a stream written by `TokenStream::encode` from `reader`.
+    ///
+    /// Only the bytes of the one stream are read, leaving `reader` positioned
+    /// just after it, so several streams can be written to and read back
+    /// from the same file. The input is read a few bytes at a time, so an
+    /// unbuffered reader is best wrapped in an `io::BufReader`.
+    ///
+    /// If the stream was encoded with spans, the files they point into are
+    /// recorded in the process-wide source map, without their source text,
+    /// so that the decoded spans report the same files and line/column
+    /// positions. Use `SourceMap::decode_token_stream` to record them in a
+    /// `SourceMap` instead.
+    ///
+    /// Only the part of each file up to the furthest position that a span
+    /// points to is recorded, and files that no span points into are not
+    /// recorded at all. A single span can still point billions of chars into
+    /// its file, so input from an untrusted source is best decoded with
+    /// `SourceMap::decode_token_stream`, into a map that is dropped along
+    /// with the tokens.
+    ///
+    /// # Errors
+    ///
+    /// Returns any error from reading `reader`, or an error of kind
+    /// `io::ErrorKind::InvalidData` if the input is not an encoded stream or
+    /// was encoded by an incompatible version of this crate, if any of its
+    /// tokens are invalid, or if its groups are nested more than 512 deep. If
+    /// the source map has no room left for its files, the error wraps a
+    /// `LexError` of kind `LexErrorKind::SourceMapFull`.
+    pub fn decode(reader: &mut impl io::Read) -> io::Result<TokenStream> {
+        encoding::decode(None, reader)
+    }
+
+    /// Finds every occurrence of `pattern` in this stream and in the groups
+    /// inside it, in the order that they start.
+    ///
+    /// Occurrences in the same stream do not overlap, but occurrences inside
+    /// the groups of another occurrence are found as well.
+    pub fn find_all(&self, pattern: &Pattern) -> Vec<Match> {
+        search::find_all(&pattern.nodes, &self.inner.inner)
+    }
+
+    /// Returns a cursor at the start of this stream, for parsing it without
+    /// copying its tokens.
+    pub fn cursor(&self) -> Cursor<'_> {
+        Cursor::new(&self.inner.inner)
+    }
+
+    /// Parses the whole stream as a `T`, failing if any tokens are left over.
+    ///
+    /// ```
+    /// use proc_macro0::{Cursor, Delimiter, Ident, Parse, ParseError, TokenStream, TokenTree};
+    ///
+    /// // `name(arg, ...)`
+    /// struct Call {
+    ///     name: Ident,
+    ///     args: Vec<Ident>,
+    /// }
+    ///
+    /// impl Parse for Call {
+    ///     fn parse(input: &mut Cursor) -> Result<Self, ParseError> {
+    ///         let name = input.parse()?;
+    ///         match input.peek() {
+    ///             Some(TokenTree::Group(group)) if group.delimiter() == Delimiter::Parenthesis => {
+    ///                 input.enter();
+    ///             }
+    ///             _ => return Err(input.error("expected `(`")),
+    ///         }
+    ///         let mut args = Vec::new();
+    ///         while !input.eof() {
+    ///             args.push(input.parse()?);
+    ///             if !input.eof() && input.punct(',').is_none() {
+    ///                 return Err(input.error("expected `,`"));
+    ///             }
+    ///         }
+    ///         input.exit();
+    ///         Ok(Call { name, args })
+    ///     }
+    /// }
+    ///
+    /// let tokens: TokenStream = "f(a, b)".parse().unwrap();
+    /// let call: Call = tokens.parse().unwrap();
+    /// assert_eq!(call.name, "f");
+    /// assert_eq!(call.args.len(), 2);
+    ///
+    /// let tokens: TokenStream = "f(a b)".parse().unwrap();
+    /// let err = tokens.parse::<Call>().err().unwrap();
+    /// assert_eq!(err.to_string(), "expected `,`");
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns the error from `T::parse`, or an error at the first token left
+    /// over after it.
+    pub fn parse<T: Parse>(&self) -> Result<T, ParseError> {
+        let mut cursor = self.cursor();
+        let value = T::parse(&mut cursor)?;
+        if cursor.eof() {
+            Ok(value)
+        } else {
+            Err(cursor.error("unexpected token"))
+        }
+    }
+
+    /// Returns the whitespace and comments after the last token of this
+    /// stream, if it was lexed with `LexOptions::preserve_trivia`.
+    ///
+    /// For the stream of a `Group` this is the trivia before its closing
+    /// delimiter.
+    pub fn trailing_trivia(&self) -> Option<&str> {
+        self.inner.trailing_trivia()
+    }
+
+    /// Replaces the trailing trivia of this stream.
+    ///
+    /// `None` goes back to the default formatting, which also puts spaces
+    /// inside the braces of a `Group` containing this stream.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `trivia` contains anything other than whitespace and
+    /// comments which are not doc comments.
+    pub fn set_trailing_trivia(&mut self, trivia: Option<&str>) {
+        self.inner.set_trailing_trivia(checked_trivia(trivia));
+    }
+}
+
+fn checked_trivia(trivia: Option<&str>) -> Option<Arc<str>> {
+    let trivia = trivia?;
+    assert!(
+        imp::is_trivia(trivia),
+        "{:?} is not only whitespace and comments which are not doc comments",
+        trivia,
+    );
+    Some(Arc::from(trivia))
+}
+
+/// Options for lexing source text into a `TokenStream`.
+///
+/// ```
+/// use proc_macro0::{LexOptions, TokenStream};
+///
+/// let src = "fn f() {\n    // comment\n    g( 1 );\n}\n";
+/// let options = LexOptions::new().preserve_trivia(true);
+/// let tokens = TokenStream::from_str_with(src, &options).unwrap();
+/// assert_eq!(tokens.to_string(), src);
+/// ```
+#[derive(Copy, Clone, Debug, Default)]
+pub struct LexOptions {
+    pub(crate) trivia: bool,
+    pub(crate) doc_comments: bool,
+}
+
+impl LexOptions {
+    /// Returns the default options, with which lexing behaves like
+    /// `TokenStream::from_str`.
+    pub fn new() -> Self {
+        LexOptions::default()
+    }
+
+    /// Whether to attach the whitespace and comments in the source to the
+    /// tokens that follow them, off by default.
+    ///
+    /// With this set, every token records its leading trivia, available from
+    /// `TokenTree::leading_trivia`, and every stream records the trivia after
+    /// its last token, available from `TokenStream::trailing_trivia`. The
+    /// `Display` output of the resulting stream is then exactly the source
+    /// it was lexed from, including doc comments, even when they are lexed
+    /// into `#[doc = "..."]` tokens.
+    ///
+    /// Tokens which are added to such a stream later have no trivia and are
+    /// separated from their neighbors as usual, so only the edited parts of
+    /// the output differ from the source.
+    pub fn preserve_trivia(mut self, preserve: bool) -> Self {
+        self.trivia = preserve;
+        self
+    }
+
+    /// Whether doc comments remember how they were written, off by default.
+    ///
+    /// Doc comments are always lexed into `#[doc = "..."]` attribute tokens.
+    /// With this set, the bracketed group of each one also keeps the
+    /// comment's source text, available from `Group::doc_comment`, and
+    /// printing the stream writes the comment back instead of the attribute
+    /// for as long as the group's contents are left unchanged.
+    pub fn keep_doc_comments(mut self, keep: bool) -> Self {
+        self.doc_comments = keep;
+        self
+    }
+}
+
+/// The differences between two token streams, returned by
+/// `TokenStream::diff`.
+#[derive(Clone, Debug)]
+pub struct Diff {
+    changes: Vec<Change>,
+}
+
+impl Diff {
+    /// Returns `true` if the two streams are equal.
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// Returns the changes from the old stream to the new one, in order.
+    pub fn changes(&self) -> &[Change] {
+        &self.changes
+    }
+}
+
+/// Prints one line per change, marked with `+` for an insertion, `-` for a
+/// deletion and `~` for a change, followed by the path to the change and the
+/// tokens involved along with their locations.
+impl Display for Diff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, change) in self.changes.iter().enumerate() {
+            if i > 0 {
+                f.write_str("\n")?;
+            }
+            Display::fmt(change, f)?;
+        }
+        Ok(())
+    }
+}
+
+/// A single difference between two token streams.
+///
+/// The position of a change in each stream is given as a path of indices,
+/// one per level of group nesting, where the last index is the position of
+/// the token in its group. Tokens inside `Delimiter::None` groups are counted
+/// as if they were not in a group.
+#[derive(Clone, Debug)]
+pub struct Change {
+    kind: ChangeKind,
+    old: Option<TokenTree>,
+    new: Option<TokenTree>,
+    old_path: Vec<usize>,
+    new_path: Vec<usize>,
+}
+
+/// What kind of difference a `Change` is.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum ChangeKind {
+    /// A token which is only in the new stream.
+    Inserted,
+    /// A token which is only in the old stream.
+    Deleted,
+    /// An identifier, punctuation character or literal which was changed into
+    /// another one of the same kind.
+    Changed,
+    /// A group whose delimiter changed. Changes to its contents are reported
+    /// separately.
+    DelimiterChanged,
+}
+
+impl Change {
+    /// Returns what kind of difference this is.
+    pub fn kind(&self) -> ChangeKind {
+        self.kind
+    }
+
+    /// Returns the token in the old stream, or `None` for an insertion.
+    pub fn old_token(&self) -> Option<&TokenTree> {
+        self.old.as_ref()
+    }
+
+    /// Returns the token in the new stream, or `None` for a deletion.
+    pub fn new_token(&self) -> Option<&TokenTree> {
+        self.new.as_ref()
+    }
+
+    /// Returns the path to the token in the old stream, or for an insertion
+    /// the path that it was inserted at.
+    pub fn old_path(&self) -> &[usize] {
+        &self.old_path
+    }
+
+    /// Returns the path to the token in the new stream, or for a deletion
+    /// the path that it was deleted from.
+    pub fn new_path(&self) -> &[usize] {
+        &self.new_path
+    }
+
+    /// Returns the span of the token in the old stream, if any.
+    pub fn old_span(&self) -> Option<Span> {
+        self.old.as_ref().map(TokenTree::span)
+    }
+
+    /// Returns the span of the token in the new stream, if any.
+    pub fn new_span(&self) -> Option<Span> {
+        self.new.as_ref().map(TokenTree::span)
+    }
+}
+
+impl Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        diff::render(self, f)
+    }
+}
+
+/// A pattern of tokens to search for with `TokenStream::find_all`.
+///
+/// A pattern is written as tokens, in which:
+///
+/// - `$_` matches any one token tree, and `$name` also captures it as `name`.
+/// - `$..` matches any sequence of token trees, including none, and
+///   `$..name` also captures it as `name`.
+/// - `$$` matches a `$` punctuation character.
+/// - A group matches a group with the same delimiter whose contents match the
+///   pattern inside it from start to end.
+/// - Any other token matches an equal token, ignoring spans, except that
+///   punctuation which is `Spacing::Alone` in the pattern also matches joint
+///   punctuation.
+///
+/// Like `PartialEq`, matching looks through `Delimiter::None` groups in the
+/// searched stream. A sequence matches as few tokens as possible, so `$..` at
+/// the very end of a pattern always matches nothing. Since an identifier
+/// right after `$..` is taken as its name, write `$.._` for an uncaptured
+/// sequence followed by an identifier.
+///
+/// ```
+/// use proc_macro0::{Pattern, TokenStream};
+///
+/// let tokens: TokenStream = "
+///     #[derive(Debug, Clone)]
+///     struct S;
+///     fn f() { foo!(1, 2); bar!(); foo![3] }
+/// "
+/// .parse()
+/// .unwrap();
+///
+/// let derives: Pattern = "#[derive($..traits)]".parse().unwrap();
+/// let found = tokens.find_all(&derives);
+/// assert_eq!(found.len(), 1);
+/// assert_eq!(found[0].get("traits").unwrap().to_string(), "Debug , Clone");
+///
+/// let calls: Pattern = "foo!$args".parse().unwrap();
+/// let args: Vec<String> = tokens
+///     .find_all(&calls)
+///     .iter()
+///     .map(|found| found.get("args").unwrap().to_string())
+///     .collect();
+/// assert_eq!(args, ["(1 , 2)", "[3]"]);
+/// ```
+#[derive(Clone, Debug)]
+pub struct Pattern {
+    nodes: Vec<search::Node>,
+}
+
+/// Parses a pattern from the tokens up to the end of the group the cursor is
+/// in.
+impl Parse for Pattern {
+    fn parse(input: &mut Cursor) -> Result<Self, ParseError> {
+        Ok(Pattern {
+            nodes: search::compile(input)?,
+        })
+    }
+}
+
+/// Lexes and parses a pattern.
+impl FromStr for Pattern {
+    type Err = ParseError;
+
+    fn from_str(src: &str) -> Result<Self, ParseError> {
+        match src.parse::<TokenStream>() {
+            Ok(tokens) => tokens.parse(),
+            Err(err) => Err(ParseError::new(err.span(), err)),
+        }
+    }
+}
+
+/// An occurrence of a `Pattern` in a token stream, returned by
+/// `TokenStream::find_all`.
+#[derive(Clone, Debug)]
+pub struct Match {
+    tokens: TokenStream,
+    span: Span,
+    path: Vec<usize>,
+    captures: Vec<(String, TokenStream)>,
+}
+
+impl Match {
+    /// Returns the matched tokens.
+    pub fn tokens(&self) -> &TokenStream {
+        &self.tokens
+    }
+
+    /// Returns the span of the matched tokens, or of the first of them if
+    /// they do not all come from the same file.
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    /// Returns the path to the first matched token, in the same form as the
+    /// paths of a `Change`.
+    pub fn path(&self) -> &[usize] {
+        &self.path
+    }
+
+    /// Returns the tokens captured as `name`.
+    pub fn get(&self, name: &str) -> Option<&TokenStream> {
+        self.captures
+            .iter()
+            .find(|(capture, _)| capture == name)
+            .map(|(_, tokens)| tokens)
+    }
+
+    /// Returns the name and tokens of each capture, in the order that they
+    /// appear in the pattern.
+    pub fn captures(&self) -> impl Iterator<Item = (&str, &TokenStream)> {
+        self.captures
+            .iter()
+            .map(|(name, tokens)| (name.as_str(), tokens))
+    }
+}
+
+/// Options for `TokenStream::encode_with`.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct EncodeOptions {
+    pub(crate) spans: bool,
+}
+
+impl EncodeOptions {
+    /// Returns the default options, with which encoding behaves like
+    /// `TokenStream::encode`.
+    pub fn new() -> Self {
+        EncodeOptions::default()
+    }
+
+    /// Whether to include the span of each token, off by default.
+    ///
+    /// Spans are stored along with a table of the files they point into,
+    /// holding each file's path and line offsets but not its source text.
+    pub fn spans(mut self, spans: bool) -> Self {
+        self.spans = spans;
+        self
+    }
 }
 
 /// `TokenStream::default()` returns an empty stream,
@@ -80,10 +720,8 @@ impl Default for TokenStream {
 /// stream.
 ///
 /// May fail for a number of reasons, for example, if the string contains
-/// unbalanced delimiters or characters not existing in the language.
-///
-/// NOTE: Some errors may cause panics instead of returning `LexError`. We
-/// reserve the right to change these errors into `LexError`s later.
+/// unbalanced delimiters or characters not existing in the language. Malformed
+/// input never causes a panic.
 impl FromStr for TokenStream {
     type Err = LexError;
 
@@ -128,9 +766,157 @@ impl FromIterator<TokenStream> for TokenStream {
 /// convertible back into the same token stream (modulo spans), except for
 /// possibly `TokenTree::Group`s with `Delimiter::None` delimiters and negative
 /// numeric literals.
+///
+/// The alternate form `{:#}` pretty-prints the stream with the default
+/// `PrettyOptions`, like `TokenStream::pretty`.
 impl Display for TokenStream {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        Display::fmt(&self.inner, f)
+        if f.alternate() {
+            Display::fmt(&self.pretty(&PrettyOptions::new()), f)
+        } else {
+            Display::fmt(&self.inner, f)
+        }
+    }
+}
+
+/// Options for `TokenStream::pretty`.
+#[derive(Copy, Clone, Debug)]
+pub struct PrettyOptions {
+    pub(crate) indent: usize,
+    pub(crate) max_width: usize,
+}
+
+impl PrettyOptions {
+    /// Returns the default options: an indent of 4 spaces and a maximum width
+    /// of 100 columns.
+    pub fn new() -> Self {
+        PrettyOptions {
+            indent: 4,
+            max_width: 100,
+        }
+    }
+
+    /// Sets the number of spaces to indent by for each level of nesting.
+    pub fn indent(mut self, spaces: usize) -> Self {
+        self.indent = spaces;
+        self
+    }
+
+    /// Sets the width beyond which the contents of parentheses and brackets
+    /// are split over multiple lines.
+    ///
+    /// This is not a hard limit, since lines are only ever broken between the
+    /// elements of a list and around the contents of delimiters.
+    pub fn max_width(mut self, columns: usize) -> Self {
+        self.max_width = columns;
+        self
+    }
+}
+
+impl Default for PrettyOptions {
+    fn default() -> Self {
+        PrettyOptions::new()
+    }
+}
+
+/// A token stream formatted over multiple lines, returned by
+/// `TokenStream::pretty`.
+pub struct Pretty<'a> {
+    stream: &'a TokenStream,
+    options: PrettyOptions,
+}
+
+impl Display for Pretty<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let printer = pretty::Printer::new(self.options);
+        f.write_str(&printer.print(&self.stream.inner.inner))
+    }
+}
+
+impl Debug for Pretty<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
+/// Options for serializing tokens with serde, available with the `serde`
+/// feature.
+///
+/// Tokens serialized directly leave out their spans. Wrapping the value to
+/// serialize with `SerializeOptions::serializable` applies these options to
+/// every token within it instead, including tokens inside of other types.
+///
+/// Deserialized tokens always have the span `Span::call_site()`, since the
+/// source text which a serialized span points into is not serialized with it.
+///
+/// ```
+/// use proc_macro0::{SerializeOptions, SourceMap};
+///
+/// let map = SourceMap::new();
+/// let tokens = map.parse_token_stream_named("lib.rs", "x").unwrap();
+/// let options = SerializeOptions::new().spans(true);
+/// let json = serde_json::to_string(&options.serializable(&tokens)).unwrap();
+/// assert_eq!(
+///     json,
+///     r#"[{"Ident":{"name":"x","span":{"file":"lib.rs","start":{"line":1,"column":0},"end":{"line":1,"column":1}}}}]"#,
+/// );
+/// ```
+#[cfg(feature = "serde")]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct SerializeOptions {
+    pub(crate) spans: bool,
+}
+
+#[cfg(feature = "serde")]
+impl SerializeOptions {
+    /// Returns the default options, with which tokens serialize the same as
+    /// they do without a `Serializable` wrapper.
+    pub fn new() -> Self {
+        SerializeOptions::default()
+    }
+
+    /// Whether to serialize the span of each token as its file name and
+    /// start and end line/column, off by default.
+    pub fn spans(mut self, spans: bool) -> Self {
+        self.spans = spans;
+        self
+    }
+
+    /// Wraps `value` so that serializing it uses these options.
+    pub fn serializable<'a, T: ?Sized>(&self, value: &'a T) -> Serializable<'a, T> {
+        Serializable {
+            value,
+            options: *self,
+        }
+    }
+}
+
+/// A value serialized with the given options, returned by
+/// `SerializeOptions::serializable`.
+#[cfg(feature = "serde")]
+pub struct Serializable<'a, T: ?Sized> {
+    value: &'a T,
+    options: SerializeOptions,
+}
+
+/// Compares token streams by their tokens alone, ignoring spans and trivia.
+///
+/// The contents of `Delimiter::None` groups compare as if they were not in a
+/// group, since they print the same. Literals are equal only if they are
+/// written the same, so `0x10` is not equal to `16`.
+impl PartialEq for TokenStream {
+    fn eq(&self, other: &TokenStream) -> bool {
+        self.inner == other.inner
+    }
+}
+
+impl Eq for TokenStream {}
+
+/// Hashes a token stream consistently with its `PartialEq` implementation,
+/// so that streams can be used as map keys.
+impl Hash for TokenStream {
+    fn hash<H: Hasher>(&self, hasher: &mut H) {
+        self.inner.hash(hasher);
     }
 }
 
@@ -142,9 +928,33 @@ impl Debug for TokenStream {
 }
 
 impl LexError {
+    /// Returns the span of the whole erroneous input, from `start_span` to
+    /// `end_span`.
     pub fn span(&self) -> Span {
         Span::_new(self.inner.span())
     }
+
+    /// Returns the reason for this error.
+    pub fn kind(&self) -> LexErrorKind {
+        self.inner.kind()
+    }
+
+    /// Returns the span of the start of the erroneous input.
+    ///
+    /// For delimiter errors this is the opening delimiter of the group, if
+    /// any. For malformed tokens this is the first character of the token.
+    pub fn start_span(&self) -> Span {
+        Span::_new(self.inner.start_span())
+    }
+
+    /// Returns the span at which the error was detected.
+    ///
+    /// For delimiter errors this is the closing delimiter, or the end of the
+    /// input for unclosed groups. For malformed tokens this is the offending
+    /// character or escape sequence.
+    pub fn end_span(&self) -> Span {
+        Span::_new(self.inner.end_span())
+    }
 }
 
 impl Debug for LexError {
@@ -159,8 +969,60 @@ impl Display for LexError {
     }
 }
 
-impl Error for LexError {}
-
+impl Error for LexError {}
+
+impl Display for LexErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fn open(delimiter: Delimiter) -> &'static str {
+            match delimiter {
+                Delimiter::Parenthesis => "(",
+                Delimiter::Brace => "{",
+                Delimiter::Bracket => "[",
+                Delimiter::None => "",
+            }
+        }
+
+        fn close(delimiter: Delimiter) -> &'static str {
+            match delimiter {
+                Delimiter::Parenthesis => ")",
+                Delimiter::Brace => "}",
+                Delimiter::Bracket => "]",
+                Delimiter::None => "",
+            }
+        }
+
+        match self {
+            LexErrorKind::UnclosedDelimiter(open_delimiter) => {
+                write!(f, "unclosed delimiter `{}`", open(*open_delimiter))
+            }
+            LexErrorKind::UnexpectedClosingDelimiter(close_delimiter) => {
+                write!(
+                    f,
+                    "unexpected closing delimiter `{}`",
+                    close(*close_delimiter)
+                )
+            }
+            LexErrorKind::MismatchedDelimiter {
+                open: open_delimiter,
+                close: close_delimiter,
+            } => write!(
+                f,
+                "mismatched closing delimiter `{}`, expected `{}`",
+                close(*close_delimiter),
+                close(*open_delimiter),
+            ),
+            LexErrorKind::UnterminatedLiteral => f.write_str("unterminated literal"),
+            LexErrorKind::UnterminatedBlockComment => f.write_str("unterminated block comment"),
+            LexErrorKind::InvalidEscape => f.write_str("invalid escape sequence in literal"),
+            LexErrorKind::BareCarriageReturn => f.write_str("bare carriage return"),
+            LexErrorKind::InvalidLiteral => f.write_str("invalid literal"),
+            LexErrorKind::InvalidIdent => f.write_str("invalid raw identifier"),
+            LexErrorKind::UnexpectedChar(ch) => write!(f, "unexpected character {ch:?}"),
+            LexErrorKind::SourceMapFull => f.write_str("source map is full"),
+        }
+    }
+}
+
 /// The source file of a given `Span`.
 #[derive(Clone, PartialEq, Eq)]
 pub struct SourceFile {
@@ -191,6 +1053,9 @@ impl SourceFile {
 
     /// Returns `true` if this source file is a real source file, and not
     /// generated by an external macro's expansion.
+    ///
+    /// Only files parsed through `TokenStream::from_file`,
+    /// `TokenStream::from_str_named` or their `SourceMap` equivalents are real.
     pub fn is_real(&self) -> bool {
         self.inner.is_real()
     }
@@ -202,6 +1067,184 @@ impl Debug for SourceFile {
     }
 }
 
+/// An owned collection of source files which spans can point into.
+///
+/// Parsing through `TokenStream::from_str` or `Literal::from_str` records the
+/// parsed text in a process-wide source map which is never freed. Parsing
+/// through a `SourceMap` instead records it in that map, and all of its files
+/// are released when the `SourceMap` is dropped.
+///
+/// Spans produced by a `SourceMap` remain valid to use after it is dropped,
+/// but from then on report the same location and source file as
+/// `Span::call_site()`. Spans from different source maps never `join`.
+///
+/// Every source map, including the process-wide one, can hold about 4 billion
+/// chars of input in total. Once it is full, lexing into it fails with
+/// `LexErrorKind::SourceMapFull`, and lexing into a new `SourceMap` is the
+/// way to continue.
+///
+/// ```
+/// use proc_macro0::SourceMap;
+///
+/// let map = SourceMap::new();
+/// let tokens = map.parse_token_stream("struct S;").unwrap();
+/// let span = tokens.into_iter().nth(1).unwrap().span();
+/// assert_eq!(span.start().column, 7);
+///
+/// drop(map);
+/// assert_eq!(span.start().column, 0);
+/// ```
+pub struct SourceMap {
+    inner: imp::SourceMap,
+}
+
+impl SourceMap {
+    /// Creates a new, empty source map.
+    pub fn new() -> Self {
+        SourceMap {
+            inner: imp::SourceMap::new(),
+        }
+    }
+
+    /// Parses `src` into a token stream whose spans point into this source
+    /// map, like `TokenStream::from_str`.
+    ///
+    /// # Errors
+    ///
+    /// Fails like `TokenStream::from_str` if `src` does not lex, or with
+    /// `LexErrorKind::SourceMapFull` if this source map has no room left for
+    /// it.
+    pub fn parse_token_stream(&self, src: &str) -> Result<TokenStream, LexError> {
+        self.parse_token_stream_with(src, &LexOptions::new())
+    }
+
+    /// Parses `src` into a token stream whose spans point into this source
+    /// map, like `TokenStream::from_str_with`.
+    ///
+    /// # Errors
+    ///
+    /// Fails like `SourceMap::parse_token_stream`.
+    pub fn parse_token_stream_with(
+        &self,
+        src: &str,
+        options: &LexOptions,
+    ) -> Result<TokenStream, LexError> {
+        match self.inner.token_stream(None, src, *options) {
+            Ok(stream) => Ok(TokenStream::_new(stream)),
+            Err(inner) => Err(LexError { inner }),
+        }
+    }
+
+    /// Parses `src` into a token stream whose spans point into this source
+    /// map, like `TokenStream::from_str_named`.
+    ///
+    /// # Errors
+    ///
+    /// Fails like `SourceMap::parse_token_stream`.
+    pub fn parse_token_stream_named<P: AsRef<Path>>(
+        &self,
+        path: P,
+        src: &str,
+    ) -> Result<TokenStream, LexError> {
+        match self
+            .inner
+            .token_stream(Some(path.as_ref()), src, LexOptions::new())
+        {
+            Ok(stream) => Ok(TokenStream::_new(stream)),
+            Err(inner) => Err(LexError { inner }),
+        }
+    }
+
+    /// Reads and parses the file at `path` into this source map, like
+    /// `TokenStream::from_file`.
+    ///
+    /// # Errors
+    ///
+    /// Fails like `TokenStream::from_file`.
+    pub fn parse_file<P: AsRef<Path>>(&self, path: P) -> io::Result<TokenStream> {
+        self.parse_file_with(path, &LexOptions::new())
+    }
+
+    /// Reads and parses the file at `path` into this source map with the
+    /// given options.
+    ///
+    /// # Errors
+    ///
+    /// Fails like `SourceMap::parse_file`.
+    pub fn parse_file_with<P: AsRef<Path>>(
+        &self,
+        path: P,
+        options: &LexOptions,
+    ) -> io::Result<TokenStream> {
+        let path = path.as_ref();
+        let src = fs::read_to_string(path)?;
+        match self.inner.token_stream(Some(path), &src, *options) {
+            Ok(stream) => Ok(TokenStream::_new(stream)),
+            Err(inner) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                LexError { inner },
+            )),
+        }
+    }
+
+    /// Reads a stream written by `TokenStream::encode`, recording the files
+    /// that its spans point into in this source map, like
+    /// `TokenStream::decode`.
+    ///
+    /// # Errors
+    ///
+    /// Fails like `TokenStream::decode`.
+    pub fn decode_token_stream(&self, reader: &mut impl io::Read) -> io::Result<TokenStream> {
+        encoding::decode(Some(self), reader)
+    }
+
+    /// Parses `repr` into a literal, recording it in this source map, like
+    /// `Literal::from_str`.
+    ///
+    /// # Errors
+    ///
+    /// Fails if `repr` does not lex as a single literal, or with
+    /// `LexErrorKind::SourceMapFull` like `SourceMap::parse_token_stream`.
+    pub fn parse_literal(&self, repr: &str) -> Result<Literal, LexError> {
+        match self.inner.literal(repr) {
+            Ok(literal) => Ok(Literal::_new(literal)),
+            Err(inner) => Err(LexError { inner }),
+        }
+    }
+
+    /// Converts a token stream from `proc_macro2`, available with the
+    /// `proc-macro2` feature. Unlike `TokenStream::try_from`, this keeps the
+    /// file and line/column of spans where `proc_macro2` reports them, by
+    /// recording the files they point into in this source map, without
+    /// their source text.
+    ///
+    /// # Errors
+    ///
+    /// Fails with `LexErrorKind::InvalidLiteral` if the stream contains a
+    /// literal which this crate does not lex, such as a C string literal, or
+    /// with `LexErrorKind::SourceMapFull` if this source map has no room left
+    /// for the files.
+    #[cfg(feature = "proc-macro2")]
+    pub fn convert_token_stream(
+        &self,
+        tokens: proc_macro2::TokenStream,
+    ) -> Result<TokenStream, LexError> {
+        interop::convert(&self.inner, tokens)
+    }
+}
+
+impl Default for SourceMap {
+    fn default() -> Self {
+        SourceMap::new()
+    }
+}
+
+impl Debug for SourceMap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        Debug::fmt(&self.inner, f)
+    }
+}
+
 /// A line-column pair representing the start or end of a `Span`.
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct LineColumn {
@@ -209,7 +1252,8 @@ pub struct LineColumn {
     /// (inclusive).
     pub line: usize,
     /// The 0-indexed column (in UTF-8 characters) in the source file on which
-    /// the span starts or ends (inclusive).
+    /// the span starts or ends (inclusive), unless counted in another
+    /// `ColumnEncoding`.
     pub column: usize,
 }
 
@@ -227,6 +1271,19 @@ impl PartialOrd for LineColumn {
     }
 }
 
+/// The units that a column is counted in, for `Span::start_in`,
+/// `Span::end_in` and `Span::at_line_column`.
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+pub enum ColumnEncoding {
+    /// Chars, as in the columns of `Span::start` and `Span::end`.
+    Chars,
+    /// Bytes of UTF-8, as in byte offsets into a `str`.
+    Utf8,
+    /// Code units of UTF-16, as in the positions of the Language Server
+    /// Protocol.
+    Utf16,
+}
+
 /// A region of source code, along with macro expansion information.
 #[derive(Copy, Clone, PartialEq, Eq)]
 pub struct Span {
@@ -261,7 +1318,6 @@ impl Span {
     }
 
     /// A span that resolves at the macro definition site.
-
     pub fn def_site() -> Self {
         Span::_new(imp::Span::def_site())
     }
@@ -279,7 +1335,6 @@ impl Span {
     }
 
     /// The original source file into which this span points.
-
     pub fn source_file(&self) -> SourceFile {
         SourceFile::_new(self.inner.source_file())
     }
@@ -308,6 +1363,80 @@ impl Span {
         LineColumn { line, column }
     }
 
+    /// Get the starting line/column in the source file for this span, with the
+    /// column counted in `encoding`.
+    ///
+    /// Returns `None` if counting the column in `encoding` needs the source
+    /// text and it is not known, as for files read by `TokenStream::decode`.
+    /// Columns in chars are always known.
+    ///
+    /// ```
+    /// use proc_macro0::{ColumnEncoding, LineColumn, TokenStream};
+    ///
+    /// let tokens: TokenStream = "\"🦀\" x".parse().unwrap();
+    /// let x = tokens.into_iter().nth(1).unwrap().span();
+    /// assert_eq!(x.start().column, 4);
+    /// assert_eq!(x.start_in(ColumnEncoding::Utf8).unwrap().column, 7);
+    /// assert_eq!(x.start_in(ColumnEncoding::Utf16).unwrap().column, 5);
+    ///
+    /// let position = LineColumn { line: 1, column: 5 };
+    /// let found = x.at_line_column(position, ColumnEncoding::Utf16).unwrap();
+    /// assert_eq!(found.start(), x.start());
+    /// ```
+    pub fn start_in(&self, encoding: ColumnEncoding) -> Option<LineColumn> {
+        let imp::LineColumn { line, column } = self.inner.start_in(encoding)?;
+        Some(LineColumn { line, column })
+    }
+
+    /// Get the ending line/column in the source file for this span, with the
+    /// column counted in `encoding`, like `start_in`.
+    pub fn end_in(&self, encoding: ColumnEncoding) -> Option<LineColumn> {
+        let imp::LineColumn { line, column } = self.inner.end_in(encoding)?;
+        Some(LineColumn { line, column })
+    }
+
+    /// Returns an empty span at `position` in the source file that this span
+    /// points into, with the column of `position` counted in `encoding`. This
+    /// is the reverse of `start_in`.
+    ///
+    /// Returns `None` if the column is past the end of the line or falls
+    /// within a char, if the line is past the end of the file, or if this span
+    /// behaves like `Span::call_site()`. Columns of files without source text,
+    /// such as those read by `TokenStream::decode`, can only be given in
+    /// chars.
+    pub fn at_line_column(&self, position: LineColumn, encoding: ColumnEncoding) -> Option<Span> {
+        let LineColumn { line, column } = position;
+        self.inner
+            .at_line_column(imp::LineColumn { line, column }, encoding)
+            .map(Span::_new)
+    }
+
+    /// Returns the range of bytes in the source file that this span covers.
+    ///
+    /// Returns `0..0` for spans into files without source text, such as those
+    /// read by `TokenStream::decode`, since their byte offsets are not known,
+    /// and for spans which behave like `Span::call_site()`.
+    pub fn byte_range(&self) -> Range<usize> {
+        self.inner.byte_range()
+    }
+
+    /// Returns the source text behind this span, preserving whitespace and
+    /// comments as they were written.
+    ///
+    /// Returns `None` for spans without source text, such as those created by
+    /// `Span::call_site()` or read by `TokenStream::decode`.
+    ///
+    /// ```
+    /// use proc_macro0::TokenStream;
+    ///
+    /// let tokens: TokenStream = "f(a,  /* b */ c)".parse().unwrap();
+    /// let group = tokens.into_iter().nth(1).unwrap();
+    /// assert_eq!(group.span().source_text().unwrap(), "(a,  /* b */ c)");
+    /// ```
+    pub fn source_text(&self) -> Option<String> {
+        self.inner.source_text()
+    }
+
     /// Create a new span encompassing `self` and `other`.
     ///
     /// Returns `None` if `self` and `other` are from different files.
@@ -368,6 +1497,36 @@ impl TokenTree {
             TokenTree::Literal(t) => t.set_span(span),
         }
     }
+
+    /// Returns the whitespace and comments preceding this token in the
+    /// source, if it was lexed with `LexOptions::preserve_trivia`.
+    pub fn leading_trivia(&self) -> Option<&str> {
+        match self {
+            TokenTree::Group(t) => t.leading_trivia(),
+            TokenTree::Ident(t) => t.leading_trivia(),
+            TokenTree::Punct(t) => t.leading_trivia(),
+            TokenTree::Literal(t) => t.leading_trivia(),
+        }
+    }
+
+    /// Replaces the leading trivia of this token.
+    ///
+    /// When a stream is printed, the trivia is written out verbatim before
+    /// the token. With `None`, the token is separated from the one before it
+    /// by the usual spacing.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `trivia` contains anything other than whitespace and
+    /// comments which are not doc comments.
+    pub fn set_leading_trivia(&mut self, trivia: Option<&str>) {
+        match self {
+            TokenTree::Group(t) => t.set_leading_trivia(trivia),
+            TokenTree::Ident(t) => t.set_leading_trivia(trivia),
+            TokenTree::Punct(t) => t.set_leading_trivia(trivia),
+            TokenTree::Literal(t) => t.set_leading_trivia(trivia),
+        }
+    }
 }
 
 impl From<Group> for TokenTree {
@@ -409,6 +1568,22 @@ impl Display for TokenTree {
     }
 }
 
+/// Compares token trees like `TokenStream`s of one token tree each, so a
+/// `Delimiter::None` group is equal to the tokens inside it.
+impl PartialEq for TokenTree {
+    fn eq(&self, other: &TokenTree) -> bool {
+        imp::tokens_eq(slice::from_ref(self), slice::from_ref(other))
+    }
+}
+
+impl Eq for TokenTree {}
+
+impl Hash for TokenTree {
+    fn hash<H: Hasher>(&self, hasher: &mut H) {
+        imp::hash_tokens(slice::from_ref(self), hasher);
+    }
+}
+
 /// Prints token tree in a form convenient for debugging.
 impl Debug for TokenTree {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -418,8 +1593,9 @@ impl Debug for TokenTree {
             TokenTree::Group(t) => Debug::fmt(t, f),
             TokenTree::Ident(t) => {
                 let mut debug = f.debug_struct("Ident");
-                debug.field("sym", &format_args!("{}", t));
+                debug.field("sym", &format_args!("{t}"));
                 imp::debug_span_field_if_nontrivial(&mut debug, t.span().inner);
+                imp::debug_trivia_field_if_some(&mut debug, t.leading_trivia());
                 debug.finish()
             }
             TokenTree::Punct(t) => Debug::fmt(t, f),
@@ -438,7 +1614,7 @@ pub struct Group {
 }
 
 /// Describes how a sequence of token trees is delimited.
-#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
 pub enum Delimiter {
     /// `( ... )`
     Parenthesis,
@@ -489,6 +1665,47 @@ impl Group {
         TokenStream::_new(self.inner.stream())
     }
 
+    /// Returns the tokens delimited in this `Group` for editing in place,
+    /// without cloning them.
+    ///
+    /// The delimiter and spans of the group are unchanged.
+    ///
+    /// ```
+    /// use proc_macro0::{Group, Ident, Span, TokenStream, TokenTree};
+    ///
+    /// let tokens: TokenStream = "f(a, b)".parse().unwrap();
+    /// let mut group = match tokens.into_iter().nth(1) {
+    ///     Some(TokenTree::Group(group)) => group,
+    ///     _ => unreachable!(),
+    /// };
+    /// let span = group.span();
+    /// group.stream_mut().extend(Some(TokenTree::from(Ident::new("c", span))));
+    /// assert_eq!(group.to_string(), "(a , b c)");
+    /// assert_eq!(group.span_open().start(), span.start());
+    /// ```
+    pub fn stream_mut(&mut self) -> &mut TokenStream {
+        self.inner.stream_mut()
+    }
+
+    /// Takes the tokens delimited in this `Group` without cloning them,
+    /// dropping the delimiter.
+    pub fn into_stream(self) -> TokenStream {
+        self.inner.into_stream()
+    }
+
+    /// Replaces the tokens delimited in this `Group` with the result of `f`,
+    /// which is given the current tokens without cloning them.
+    ///
+    /// The delimiter, spans and leading trivia of the group are unchanged.
+    pub fn map_stream<F>(mut self, f: F) -> Group
+    where
+        F: FnOnce(TokenStream) -> TokenStream,
+    {
+        let stream = self.stream_mut();
+        *stream = f(std::mem::take(stream));
+        self
+    }
+
     /// Returns the span for the delimiters of this token stream, spanning the
     /// entire `Group`.
     ///
@@ -529,6 +1746,47 @@ impl Group {
     pub fn set_span(&mut self, span: Span) {
         self.inner.set_span(span.inner);
     }
+
+    /// Returns the whitespace and comments preceding the opening delimiter,
+    /// if this group was lexed with `LexOptions::preserve_trivia`.
+    ///
+    /// The trivia before the closing delimiter is the trailing trivia of
+    /// `self.stream()`.
+    pub fn leading_trivia(&self) -> Option<&str> {
+        self.inner.leading_trivia()
+    }
+
+    /// Replaces the leading trivia of this group.
+    ///
+    /// # Panics
+    ///
+    /// Panics like `TokenTree::set_leading_trivia`.
+    pub fn set_leading_trivia(&mut self, trivia: Option<&str>) {
+        self.inner.set_leading_trivia(checked_trivia(trivia));
+    }
+
+    /// Returns the doc comment that this `[doc = "..."]` group was lexed
+    /// from, if it was lexed with `LexOptions::keep_doc_comments` or
+    /// `LexOptions::preserve_trivia` and its contents have not changed since.
+    ///
+    /// The comment has the span of this group.
+    pub fn doc_comment(&self) -> Option<DocComment> {
+        let (source, text, inner) = self.inner.doc_comment()?;
+        Some(DocComment {
+            style: if inner {
+                AttrStyle::Inner
+            } else {
+                AttrStyle::Outer
+            },
+            kind: if source.starts_with("//") {
+                CommentKind::Line
+            } else {
+                CommentKind::Block
+            },
+            text: text.to_owned(),
+            span: self.span(),
+        })
+    }
 }
 
 /// Prints the group as a string that should be losslessly convertible back
@@ -540,6 +1798,21 @@ impl Display for Group {
     }
 }
 
+/// Compares the delimiters and streams of two groups, ignoring spans.
+impl PartialEq for Group {
+    fn eq(&self, other: &Group) -> bool {
+        self.inner == other.inner
+    }
+}
+
+impl Eq for Group {}
+
+impl Hash for Group {
+    fn hash<H: Hasher>(&self, hasher: &mut H) {
+        self.inner.hash(hasher);
+    }
+}
+
 impl Debug for Group {
     fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
         Debug::fmt(&self.inner, formatter)
@@ -555,11 +1828,12 @@ pub struct Punct {
     ch: char,
     spacing: Spacing,
     span: Span,
+    leading: Option<Arc<str>>,
 }
 
 /// Whether a `Punct` is followed immediately by another `Punct` or followed by
 /// another token or whitespace.
-#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
 pub enum Spacing {
     /// E.g. `+` is `Alone` in `+ =`, `+ident` or `+()`.
     Alone,
@@ -570,65 +1844,351 @@ pub enum Spacing {
     Joint,
 }
 
-impl Punct {
-    /// Creates a new `Punct` from the given character and spacing.
+impl Punct {
+    /// Creates a new `Punct` from the given character and spacing.
+    ///
+    /// The returned `Punct` will have the default span of `Span::call_site()`
+    /// which can be further configured with the `set_span` method below.
+    ///
+    /// # Panics
+    ///
+    /// The `ch` argument must be a valid punctuation character permitted by the
+    /// language, otherwise the function will panic. Use `Punct::try_new` to
+    /// handle this case instead.
+    pub fn new(ch: char, spacing: Spacing) -> Self {
+        match Punct::try_new(ch, spacing) {
+            Ok(punct) => punct,
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    /// Same as `Punct::new`, but returns an error instead of panicking.
+    ///
+    /// # Errors
+    ///
+    /// Fails if `ch` is not a punctuation character permitted by the
+    /// language.
+    pub fn try_new(ch: char, spacing: Spacing) -> Result<Self, PunctError> {
+        if imp::is_punct_char(ch) {
+            Ok(Punct {
+                ch,
+                spacing,
+                span: Span::call_site(),
+                leading: None,
+            })
+        } else {
+            Err(PunctError { ch })
+        }
+    }
+
+    /// Returns the value of this punctuation character as `char`.
+    pub fn as_char(&self) -> char {
+        self.ch
+    }
+
+    /// Returns the spacing of this punctuation character, indicating whether
+    /// it's immediately followed by another `Punct` in the token stream, so
+    /// they can potentially be combined into a multicharacter operator
+    /// (`Joint`), or it's followed by some other token or whitespace (`Alone`)
+    /// so the operator has certainly ended.
+    pub fn spacing(&self) -> Spacing {
+        self.spacing
+    }
+
+    /// Returns the span for this punctuation character.
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    /// Configure the span for this punctuation character.
+    pub fn set_span(&mut self, span: Span) {
+        self.span = span;
+    }
+
+    /// Returns the whitespace and comments preceding this punctuation
+    /// character, if it was lexed with `LexOptions::preserve_trivia`.
+    pub fn leading_trivia(&self) -> Option<&str> {
+        self.leading.as_deref()
+    }
+
+    /// Replaces the leading trivia of this punctuation character.
+    ///
+    /// # Panics
+    ///
+    /// Panics like `TokenTree::set_leading_trivia`.
+    pub fn set_leading_trivia(&mut self, trivia: Option<&str>) {
+        self.leading = checked_trivia(trivia);
+    }
+}
+
+/// Prints the punctuation character as a string that should be losslessly
+/// convertible back into the same character.
+impl Display for Punct {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        Display::fmt(&self.ch, f)
+    }
+}
+
+/// Compares the characters and spacing of two punctuation tokens, ignoring
+/// spans.
+impl PartialEq for Punct {
+    fn eq(&self, other: &Punct) -> bool {
+        self.ch == other.ch && self.spacing == other.spacing
+    }
+}
+
+impl Eq for Punct {}
+
+impl Hash for Punct {
+    fn hash<H: Hasher>(&self, hasher: &mut H) {
+        self.ch.hash(hasher);
+        self.spacing.hash(hasher);
+    }
+}
+
+impl Debug for Punct {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let mut debug = fmt.debug_struct("Punct");
+        debug.field("char", &self.ch);
+        debug.field("spacing", &self.spacing);
+        imp::debug_span_field_if_nontrivial(&mut debug, self.span.inner);
+        imp::debug_trivia_field_if_some(&mut debug, self.leading.as_deref());
+        debug.finish()
+    }
+}
+
+/// Error returned from `Punct::try_new`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PunctError {
+    ch: char,
+}
+
+impl PunctError {
+    /// Returns the character which was rejected.
+    pub fn as_char(&self) -> char {
+        self.ch
+    }
+}
+
+impl Display for PunctError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unsupported character {:?}", self.ch)
+    }
+}
+
+impl Error for PunctError {}
+
+/// A doc comment, such as `/// text` or `/*! text */`.
+///
+/// Doc comments are lexed into `#[doc = "..."]` attribute tokens. When
+/// lexing with `LexOptions::keep_doc_comments`, the bracketed group of each
+/// one remembers the comment it was written as, which `Group::doc_comment`
+/// returns.
+///
+/// ```
+/// use proc_macro0::{AttrStyle, LexOptions, TokenStream, TokenTree};
+///
+/// let options = LexOptions::new().keep_doc_comments(true);
+/// let tokens = TokenStream::from_str_with("/// Hello\nfn f() {}", &options).unwrap();
+/// assert_eq!(tokens.to_string(), "/// Hello\nfn f () { }");
+///
+/// let comment = match tokens.into_iter().nth(1) {
+///     Some(TokenTree::Group(group)) => group.doc_comment().unwrap(),
+///     _ => unreachable!(),
+/// };
+/// assert_eq!(comment.style(), AttrStyle::Outer);
+/// assert_eq!(comment.text(), " Hello");
+/// ```
+#[derive(Clone)]
+pub struct DocComment {
+    style: AttrStyle,
+    kind: CommentKind,
+    text: String,
+    span: Span,
+}
+
+/// Whether a doc comment documents the item after it or the one it is in.
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+pub enum AttrStyle {
+    /// `///` or `/** */`, equivalent to `#[doc = "..."]`.
+    Outer,
+    /// `//!` or `/*! */`, equivalent to `#![doc = "..."]`.
+    Inner,
+}
+
+/// Whether a doc comment is a line comment or a block comment.
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+pub enum CommentKind {
+    /// `///` or `//!`, running to the end of the line.
+    Line,
+    /// `/** */` or `/*! */`.
+    Block,
+}
+
+impl DocComment {
+    /// Creates a new doc comment with the given text, which is everything
+    /// between the `///`, `//!`, `/**` or `/*!` and the end of the comment.
+    ///
+    /// The returned `DocComment` will have the default span of
+    /// `Span::call_site()`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the comment would not lex back as the same doc comment, for
+    /// example if the text of a line comment contains a newline or the text
+    /// of a block comment contains an unbalanced `*/`. Use
+    /// `DocComment::try_new` to handle this case instead.
+    pub fn new(style: AttrStyle, kind: CommentKind, text: &str) -> Self {
+        match DocComment::try_new(style, kind, text) {
+            Ok(comment) => comment,
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    /// Same as `DocComment::new`, but returns an error instead of panicking.
     ///
-    /// The `ch` argument must be a valid punctuation character permitted by the
-    /// language, otherwise the function will panic.
+    /// # Errors
     ///
-    /// The returned `Punct` will have the default span of `Span::call_site()`
-    /// which can be further configured with the `set_span` method below.
-    pub fn new(ch: char, spacing: Spacing) -> Self {
-        Punct {
-            ch,
-            spacing,
+    /// Fails if the comment would not lex back as the same doc comment.
+    pub fn try_new(
+        style: AttrStyle,
+        kind: CommentKind,
+        text: &str,
+    ) -> Result<Self, DocCommentError> {
+        let comment = DocComment {
+            style,
+            kind,
+            text: text.to_owned(),
             span: Span::call_site(),
+        };
+        if parse::is_doc_comment(&comment.to_string(), style, text) {
+            Ok(comment)
+        } else {
+            Err(DocCommentError {
+                kind,
+                text: comment.text,
+            })
         }
     }
 
-    /// Returns the value of this punctuation character as `char`.
-    pub fn as_char(&self) -> char {
-        self.ch
+    /// Returns whether this is an inner or outer doc comment.
+    pub fn style(&self) -> AttrStyle {
+        self.style
     }
 
-    /// Returns the spacing of this punctuation character, indicating whether
-    /// it's immediately followed by another `Punct` in the token stream, so
-    /// they can potentially be combined into a multicharacter operator
-    /// (`Joint`), or it's followed by some other token or whitespace (`Alone`)
-    /// so the operator has certainly ended.
-    pub fn spacing(&self) -> Spacing {
-        self.spacing
+    /// Returns whether this is a line or block doc comment.
+    pub fn kind(&self) -> CommentKind {
+        self.kind
     }
 
-    /// Returns the span for this punctuation character.
+    /// Returns the raw text of the comment, without the comment markers.
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Returns the `#[doc = "..."]` or `#![doc = "..."]` tokens which this
+    /// doc comment stands for, all with this comment's span.
+    ///
+    /// Unlike the tokens from `TokenStream::from(comment)`, these print as
+    /// the attribute rather than as the comment.
+    pub fn to_attribute(&self) -> TokenStream {
+        let inner = self.style == AttrStyle::Inner;
+        let tokens = parse::doc_attribute(&self.text, inner, self.span);
+        TokenStream::_new(imp::TokenStream::from(tokens))
+    }
+
+    /// Returns the span for this doc comment.
     pub fn span(&self) -> Span {
         self.span
     }
 
-    /// Configure the span for this punctuation character.
+    /// Configure the span for this doc comment.
     pub fn set_span(&mut self, span: Span) {
         self.span = span;
     }
 }
 
-/// Prints the punctuation character as a string that should be losslessly
-/// convertible back into the same character.
-impl Display for Punct {
+/// Converts a doc comment into its `#[doc = "..."]` tokens, which print back
+/// as the comment like those lexed with `LexOptions::keep_doc_comments`.
+impl From<DocComment> for TokenStream {
+    fn from(comment: DocComment) -> TokenStream {
+        let mut tokens = comment.to_attribute();
+        if let Some(TokenTree::Group(group)) = tokens.inner.inner.last_mut() {
+            group.inner.doc = Some(Arc::from(comment.to_string()));
+        }
+        tokens
+    }
+}
+
+/// Prints the doc comment as it would appear in source code.
+impl Display for DocComment {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        Display::fmt(&self.ch, f)
+        let (open, close) = match (self.style, self.kind) {
+            (AttrStyle::Outer, CommentKind::Line) => ("///", ""),
+            (AttrStyle::Inner, CommentKind::Line) => ("//!", ""),
+            (AttrStyle::Outer, CommentKind::Block) => ("/**", "*/"),
+            (AttrStyle::Inner, CommentKind::Block) => ("/*!", "*/"),
+        };
+        f.write_str(open)?;
+        f.write_str(&self.text)?;
+        f.write_str(close)
     }
 }
 
-impl Debug for Punct {
+/// Compares the style, kind and text of two doc comments, ignoring spans.
+impl PartialEq for DocComment {
+    fn eq(&self, other: &DocComment) -> bool {
+        self.style == other.style && self.kind == other.kind && self.text == other.text
+    }
+}
+
+impl Eq for DocComment {}
+
+impl Hash for DocComment {
+    fn hash<H: Hasher>(&self, hasher: &mut H) {
+        self.style.hash(hasher);
+        self.kind.hash(hasher);
+        self.text.hash(hasher);
+    }
+}
+
+impl Debug for DocComment {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
-        let mut debug = fmt.debug_struct("Punct");
-        debug.field("char", &self.ch);
-        debug.field("spacing", &self.spacing);
+        let mut debug = fmt.debug_struct("DocComment");
+        debug.field("style", &self.style);
+        debug.field("kind", &self.kind);
+        debug.field("text", &self.text);
         imp::debug_span_field_if_nontrivial(&mut debug, self.span.inner);
         debug.finish()
     }
 }
 
+/// Error returned by `DocComment::try_new`.
+#[derive(Clone, Debug)]
+pub struct DocCommentError {
+    kind: CommentKind,
+    text: String,
+}
+
+impl DocCommentError {
+    /// Returns the text which was rejected.
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+}
+
+impl Display for DocCommentError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:?} is not a valid {:?} doc comment",
+            self.text, self.kind
+        )
+    }
+}
+
+impl Error for DocCommentError {}
+
 /// A word of Rust code, which may be a keyword or legal variable name.
 ///
 /// An identifier consists of at least one Unicode code point, the first of
@@ -711,11 +2271,8 @@ impl Ident {
     ///
     /// Panics if the input string is neither a keyword nor a legal variable
     /// name. If you are not sure whether the string contains an identifier and
-    /// need to handle an error case, use
-    /// <a href="https://docs.rs/syn/1.0/syn/fn.parse_str.html"><code
-    ///   style="padding-right:0;">syn::parse_str</code></a><code
-    ///   style="padding-left:0;">::&lt;Ident&gt;</code>
-    /// rather than `Ident::new`.
+    /// need to handle an error case, use `Ident::try_new` rather than
+    /// `Ident::new`.
     pub fn new(string: &str, span: Span) -> Self {
         Ident::_new(imp::Ident::new(string, span.inner))
     }
@@ -729,6 +2286,33 @@ impl Ident {
         Ident::_new_raw(string, span)
     }
 
+    /// Same as `Ident::new`, but returns an error describing why `string` is
+    /// not a valid identifier instead of panicking.
+    ///
+    /// ```
+    /// use proc_macro0::{Ident, IdentErrorKind, Span};
+    ///
+    /// let err = Ident::try_new("a-b", Span::call_site()).unwrap_err();
+    /// assert_eq!(err.kind(), IdentErrorKind::InvalidChar('-'));
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Fails in the cases where `Ident::new` panics.
+    pub fn try_new(string: &str, span: Span) -> Result<Self, IdentError> {
+        imp::Ident::try_new(string, span.inner).map(Ident::_new)
+    }
+
+    /// Same as `Ident::new_raw`, but returns an error describing why `string`
+    /// is not a valid raw identifier instead of panicking.
+    ///
+    /// # Errors
+    ///
+    /// Fails in the cases where `Ident::new_raw` panics.
+    pub fn try_new_raw(string: &str, span: Span) -> Result<Self, IdentError> {
+        imp::Ident::try_new_raw(string, span.inner).map(Ident::_new)
+    }
+
     fn _new_raw(string: &str, span: Span) -> Self {
         Ident::_new(imp::Ident::new_raw(string, span.inner))
     }
@@ -743,6 +2327,21 @@ impl Ident {
     pub fn set_span(&mut self, span: Span) {
         self.inner.set_span(span.inner);
     }
+
+    /// Returns the whitespace and comments preceding this `Ident`, if it was
+    /// lexed with `LexOptions::preserve_trivia`.
+    pub fn leading_trivia(&self) -> Option<&str> {
+        self.inner.leading_trivia()
+    }
+
+    /// Replaces the leading trivia of this `Ident`.
+    ///
+    /// # Panics
+    ///
+    /// Panics like `TokenTree::set_leading_trivia`.
+    pub fn set_leading_trivia(&mut self, trivia: Option<&str>) {
+        self.inner.set_leading_trivia(checked_trivia(trivia));
+    }
 }
 
 impl PartialEq for Ident {
@@ -794,6 +2393,65 @@ impl Debug for Ident {
     }
 }
 
+/// Error returned from `Ident::try_new` and `Ident::try_new_raw`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IdentError {
+    kind: IdentErrorKind,
+    string: String,
+}
+
+/// The reason that a string is not a valid identifier.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum IdentErrorKind {
+    /// The string is empty.
+    Empty,
+    /// The string consists only of ASCII digits, so is an integer literal.
+    Number,
+    /// The string contains a character which cannot appear at that position
+    /// of an identifier.
+    InvalidChar(char),
+    /// The string is a keyword which cannot be used as a raw identifier, such
+    /// as `self` or `crate`.
+    ReservedRaw,
+}
+
+impl IdentError {
+    fn _new(kind: IdentErrorKind, string: &str) -> Self {
+        IdentError {
+            kind,
+            string: string.to_owned(),
+        }
+    }
+
+    /// Returns the reason that the string is not a valid identifier.
+    pub fn kind(&self) -> IdentErrorKind {
+        self.kind
+    }
+
+    /// Returns the string which was rejected.
+    pub fn as_str(&self) -> &str {
+        &self.string
+    }
+}
+
+impl Display for IdentError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            IdentErrorKind::Empty => {
+                f.write_str("Ident is not allowed to be empty; use Option<Ident>")
+            }
+            IdentErrorKind::Number => f.write_str("Ident cannot be a number; use Literal instead"),
+            IdentErrorKind::InvalidChar(_) => write!(f, "{:?} is not a valid Ident", self.string),
+            IdentErrorKind::ReservedRaw => {
+                write!(f, "`r#{}` cannot be a raw identifier", self.string)
+            }
+        }
+    }
+}
+
+impl Error for IdentError {}
+
 /// A literal string (`"hello"`), byte string (`b"hello"`), character (`'a'`),
 /// byte character (`b'a'`), an integer or floating point number with or without
 /// a suffix (`1`, `1u8`, `2.3`, `2.3f32`).
@@ -983,10 +2641,137 @@ impl Literal {
 
     /// Returns a `Span` that is a subset of `self.span()` containing only
     /// the source bytes in range `range`. Returns `None` if the would-be
-    /// trimmed span is outside the bounds of `self`.
+    /// trimmed span is outside the bounds of `self`, or if `self.span()` does
+    /// not cover the literal's source text, as for a literal that was not
+    /// parsed from source.
     pub fn subspan<R: RangeBounds<usize>>(&self, range: R) -> Option<Span> {
         self.inner.subspan(range).map(Span::_new)
     }
+
+    /// Returns the whitespace and comments preceding this literal, if it was
+    /// lexed with `LexOptions::preserve_trivia`.
+    pub fn leading_trivia(&self) -> Option<&str> {
+        self.inner.leading_trivia()
+    }
+
+    /// Replaces the leading trivia of this literal.
+    ///
+    /// # Panics
+    ///
+    /// Panics like `TokenTree::set_leading_trivia`.
+    pub fn set_leading_trivia(&mut self, trivia: Option<&str>) {
+        self.inner.set_leading_trivia(checked_trivia(trivia));
+    }
+
+    /// Returns a `Span` that is a subset of `self.span()` containing only
+    /// the source text which produced the bytes in range `range` of the
+    /// literal's value, as returned by `as_str_value` or `as_bytes_value`.
+    ///
+    /// Unlike `subspan`, escape sequences are accounted for, so this can be
+    /// used to point at a placeholder in a format string. Returns `None` if
+    /// this is not a string or byte string literal, if the range is out of
+    /// bounds or splits a character, or if `self.span()` does not cover the
+    /// literal's source text.
+    ///
+    /// ```
+    /// use proc_macro0::Literal;
+    ///
+    /// let literal: Literal = r#""\t{x}""#.parse().unwrap();
+    /// let value = literal.as_str_value().unwrap();
+    /// let start = value.find('{').unwrap();
+    /// let placeholder = literal.value_subspan(start..start + 3).unwrap();
+    /// assert_eq!(placeholder.start().column, 3);
+    /// assert_eq!(placeholder.end().column, 6);
+    /// ```
+    pub fn value_subspan<R: RangeBounds<usize>>(&self, range: R) -> Option<Span> {
+        self.inner.value_subspan(range).map(Span::_new)
+    }
+
+    /// Returns which kind of literal token this is.
+    ///
+    /// A leading `-` on a numeric literal does not affect the kind.
+    pub fn kind(&self) -> LiteralKind {
+        self.inner.kind()
+    }
+
+    /// Returns the suffix of this literal, such as `u8` in `1u8`, or an
+    /// empty string if it has none.
+    pub fn suffix(&self) -> &str {
+        self.inner.suffix()
+    }
+
+    /// Returns the unescaped value of a string or raw string literal.
+    ///
+    /// Returns `None` if this is not a string literal.
+    pub fn as_str_value(&self) -> Option<String> {
+        self.inner.str_value()
+    }
+
+    /// Returns the unescaped value of a byte string or raw byte string
+    /// literal.
+    ///
+    /// Returns `None` if this is not a byte string literal.
+    pub fn as_bytes_value(&self) -> Option<Vec<u8>> {
+        self.inner.bytes_value()
+    }
+
+    /// Returns the unescaped value of a character literal.
+    ///
+    /// Returns `None` if this is not a character literal.
+    pub fn as_char_value(&self) -> Option<char> {
+        self.inner.char_value()
+    }
+
+    /// Returns the unescaped value of a byte literal.
+    ///
+    /// Returns `None` if this is not a byte literal.
+    pub fn as_byte_value(&self) -> Option<u8> {
+        self.inner.byte_value()
+    }
+
+    /// Returns the value of an integer literal, converted to `T`.
+    ///
+    /// The suffix is ignored. Returns `None` if this is not an integer
+    /// literal or if its value does not fit in `T`.
+    pub fn as_int<T>(&self) -> Option<T>
+    where
+        T: TryFrom<u128> + TryFrom<i128>,
+    {
+        self.inner.int_value()
+    }
+
+    /// Returns the value of a floating-point literal.
+    ///
+    /// The suffix is ignored. Returns `None` if this is not a floating-point
+    /// literal.
+    pub fn as_float(&self) -> Option<f64> {
+        self.inner.float_value()
+    }
+}
+
+/// The kind of a [`Literal`], as returned by [`Literal::kind`].
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum LiteralKind {
+    /// A string literal, `"..."`.
+    Str,
+    /// A raw string literal, `r"..."` or `r#"..."#`.
+    RawStr,
+    /// A byte string literal, `b"..."`.
+    ByteStr,
+    /// A raw byte string literal, `br"..."` or `br#"..."#`.
+    RawByteStr,
+    /// A character literal, `'a'`.
+    Char,
+    /// A byte literal, `b'a'`.
+    Byte,
+    /// An integer literal, such as `1`, `0xff` or `1u8`.
+    Int,
+    /// A floating-point literal, such as `1.0`, `1e3` or `1f32`.
+    Float,
+    /// A literal whose text is not recognized as any of the above. Literals
+    /// lexed by this crate are never `Other`.
+    Other,
 }
 
 impl FromStr for Literal {
@@ -999,6 +2784,22 @@ impl FromStr for Literal {
     }
 }
 
+/// Compares two literals by how they are written, ignoring spans, so `1u8`
+/// is not equal to `1_u8` and `"a"` is not equal to `r"a"`.
+impl PartialEq for Literal {
+    fn eq(&self, other: &Literal) -> bool {
+        self.inner == other.inner
+    }
+}
+
+impl Eq for Literal {}
+
+impl Hash for Literal {
+    fn hash<H: Hasher>(&self, hasher: &mut H) {
+        self.inner.hash(hasher);
+    }
+}
+
 impl Debug for Literal {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         Debug::fmt(&self.inner, f)
@@ -1011,6 +2812,412 @@ impl Display for Literal {
     }
 }
 
+/// Types that can be interpolated into a [`quote!`] invocation as `#var`.
+///
+/// Implemented for the token types, `TokenStream`, strings, characters,
+/// booleans and primitive numbers, and for `Option` and `Vec` of any of those.
+/// Numbers become suffixed literals, so `1u8` is interpolated as `1u8` rather
+/// than `1`.
+pub trait ToTokens {
+    /// Appends the tokens of `self` to `tokens`.
+    fn to_tokens(&self, tokens: &mut TokenStream);
+
+    /// Returns the tokens of `self` as a new stream.
+    fn to_token_stream(&self) -> TokenStream {
+        let mut tokens = TokenStream::new();
+        self.to_tokens(&mut tokens);
+        tokens
+    }
+
+    /// Converts `self` into a stream of its tokens.
+    fn into_token_stream(self) -> TokenStream
+    where
+        Self: Sized,
+    {
+        self.to_token_stream()
+    }
+}
+
+/// Support for the expansion of `quote!`.
+#[doc(hidden)]
+pub mod __private {
+    pub use crate::quote::{
+        ext, parse, push_group, HasIterator, RepInterp, ThereIsNoIteratorInRepetition,
+    };
+}
+
+/// A position in a `TokenStream`, for parsing it into other types without
+/// copying its tokens.
+///
+/// The cursor steps over one token tree at a time, so a `Group` is a single
+/// step unless the cursor `enter`s it. `Delimiter::None` groups are looked
+/// through, as if their tokens were spliced into the enclosing stream.
+///
+/// Cloning a cursor forks it: the clone can be moved ahead speculatively and
+/// either assigned back to the original to commit to what it parsed, or
+/// dropped to backtrack.
+#[derive(Clone)]
+pub struct Cursor<'a> {
+    rest: &'a [TokenTree],
+    /// The groups that the cursor is inside, innermost last.
+    scopes: Vec<Scope<'a>>,
+}
+
+#[derive(Clone)]
+struct Scope<'a> {
+    group: &'a Group,
+    /// The tokens after the group in its enclosing stream.
+    after: &'a [TokenTree],
+}
+
+impl<'a> Cursor<'a> {
+    fn new(tokens: &'a [TokenTree]) -> Self {
+        let mut cursor = Cursor {
+            rest: tokens,
+            scopes: Vec::new(),
+        };
+        cursor.look_through_none_groups();
+        cursor
+    }
+
+    /// Enters any `Delimiter::None` group at the cursor and leaves any which
+    /// the cursor is at the end of, so that the cursor is either at a token
+    /// tree or at the end of a delimited group or of the whole stream.
+    fn look_through_none_groups(&mut self) {
+        loop {
+            match self.rest.first() {
+                Some(TokenTree::Group(group)) if group.delimiter() == Delimiter::None => {
+                    self.scopes.push(Scope {
+                        group,
+                        after: &self.rest[1..],
+                    });
+                    self.rest = group.inner.stream_tokens();
+                }
+                Some(_) => return,
+                None => match self.scopes.last() {
+                    Some(scope) if scope.group.delimiter() == Delimiter::None => {
+                        self.rest = scope.after;
+                        self.scopes.pop();
+                    }
+                    _ => return,
+                },
+            }
+        }
+    }
+
+    /// Checks whether the cursor is at the end of the group it is in, or of
+    /// the whole stream.
+    pub fn eof(&self) -> bool {
+        self.rest.is_empty()
+    }
+
+    /// Returns the token tree at the cursor without moving past it.
+    pub fn peek(&self) -> Option<&'a TokenTree> {
+        self.rest.first()
+    }
+
+    /// Moves past the token tree at the cursor, if any.
+    pub fn advance(&mut self) {
+        if !self.rest.is_empty() {
+            self.rest = &self.rest[1..];
+            self.look_through_none_groups();
+        }
+    }
+
+    /// Returns the span of the token tree at the cursor, or the span of the
+    /// closing delimiter at the end of a group, or `Span::call_site()` at the
+    /// end of the whole stream.
+    pub fn span(&self) -> Span {
+        match (self.peek(), self.scopes.last()) {
+            (Some(tt), _) => tt.span(),
+            (None, Some(scope)) => scope.group.span_close(),
+            (None, None) => Span::call_site(),
+        }
+    }
+
+    /// Moves into the group at the cursor, returning it, or returns `None`
+    /// without moving if the cursor is not at a group.
+    pub fn enter(&mut self) -> Option<&'a Group> {
+        let Some(TokenTree::Group(group)) = self.peek() else {
+            return None;
+        };
+        self.scopes.push(Scope {
+            group,
+            after: &self.rest[1..],
+        });
+        self.rest = group.inner.stream_tokens();
+        self.look_through_none_groups();
+        Some(group)
+    }
+
+    /// Moves out of the group that the cursor is in, to just after it,
+    /// skipping any tokens left in the group. Returns the group, or `None`
+    /// without moving if the cursor is not in a group.
+    pub fn exit(&mut self) -> Option<&'a Group> {
+        let index = self
+            .scopes
+            .iter()
+            .rposition(|scope| scope.group.delimiter() != Delimiter::None)?;
+        let scope = self.scopes[index].clone();
+        self.scopes.truncate(index);
+        self.rest = scope.after;
+        self.look_through_none_groups();
+        Some(scope.group)
+    }
+
+    /// Moves past the identifier at the cursor, returning it.
+    pub fn ident(&mut self) -> Option<&'a Ident> {
+        match self.peek() {
+            Some(TokenTree::Ident(ident)) => {
+                self.advance();
+                Some(ident)
+            }
+            _ => None,
+        }
+    }
+
+    /// Moves past the punctuation character `ch` at the cursor, returning it.
+    pub fn punct(&mut self, ch: char) -> Option<&'a Punct> {
+        match self.peek() {
+            Some(TokenTree::Punct(punct)) if punct.as_char() == ch => {
+                self.advance();
+                Some(punct)
+            }
+            _ => None,
+        }
+    }
+
+    /// Moves past the literal at the cursor, returning it.
+    pub fn literal(&mut self) -> Option<&'a Literal> {
+        match self.peek() {
+            Some(TokenTree::Literal(literal)) => {
+                self.advance();
+                Some(literal)
+            }
+            _ => None,
+        }
+    }
+
+    /// Moves past the group with the given delimiter at the cursor, returning
+    /// it, without entering it.
+    pub fn group(&mut self, delimiter: Delimiter) -> Option<&'a Group> {
+        match self.peek() {
+            Some(TokenTree::Group(group)) if group.delimiter() == delimiter => {
+                self.advance();
+                Some(group)
+            }
+            _ => None,
+        }
+    }
+
+    /// Parses a `T` at the cursor, moving past it.
+    ///
+    /// On error the cursor may have moved past some of the tokens; parse a
+    /// clone of the cursor to backtrack instead.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error from `T::parse`.
+    pub fn parse<T: Parse>(&mut self) -> Result<T, ParseError> {
+        T::parse(self)
+    }
+
+    /// Returns an error with the given message at the span of the cursor.
+    pub fn error<M: Display>(&self, message: M) -> ParseError {
+        ParseError::new(self.span(), message)
+    }
+
+    /// Moves past the outermost `Delimiter::None` group which starts at the
+    /// cursor, returning it, or returns `None` without moving if there is no
+    /// such group.
+    fn none_group(&mut self) -> Option<&'a Group> {
+        let mut index = None;
+        let mut len = self.rest.len();
+        for (i, scope) in self.scopes.iter().enumerate().rev() {
+            let group = scope.group;
+            if group.delimiter() != Delimiter::None || len != group.inner.stream_tokens().len() {
+                break;
+            }
+            index = Some(i);
+            len = scope.after.len() + 1;
+        }
+        let scope = self.scopes[index?].clone();
+        self.scopes.truncate(index?);
+        self.rest = scope.after;
+        self.look_through_none_groups();
+        Some(scope.group)
+    }
+
+    /// Moves to the end of the group that the cursor is in, returning the
+    /// token trees moved past. The `Delimiter::None` groups that the cursor
+    /// is inside are kept around their tokens, with just the tokens left in
+    /// them where the cursor is part way through.
+    fn take_rest(&mut self) -> Vec<TokenTree> {
+        let mut tokens = self.rest.to_vec();
+        let mut whole = true;
+        while let Some(scope) = self.scopes.last() {
+            if scope.group.delimiter() != Delimiter::None {
+                break;
+            }
+            whole &= tokens.len() == scope.group.inner.stream_tokens().len();
+            let group = if whole {
+                scope.group.clone()
+            } else {
+                let mut group = Group::new(Delimiter::None, tokens.into_iter().collect());
+                group.set_span(scope.group.span());
+                group
+            };
+            tokens = iter::once(TokenTree::Group(group))
+                .chain(scope.after.iter().cloned())
+                .collect();
+            self.scopes.pop();
+        }
+        self.rest = &[];
+        tokens
+    }
+}
+
+/// Iterates over the token trees from the cursor to the end of the group it
+/// is in, like calling `peek` and then `advance`.
+impl<'a> Iterator for Cursor<'a> {
+    type Item = &'a TokenTree;
+
+    fn next(&mut self) -> Option<&'a TokenTree> {
+        let tt = self.peek()?;
+        self.advance();
+        Some(tt)
+    }
+}
+
+impl Debug for Cursor<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Cursor")
+            .field("next", &self.peek())
+            .field("depth", &self.scopes.len())
+            .finish()
+    }
+}
+
+/// Types that can be parsed from the tokens at a `Cursor`.
+pub trait Parse: Sized {
+    /// Parses a value at `input`, moving it past the tokens parsed.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the tokens at `input` are not a valid `Self`.
+    fn parse(input: &mut Cursor) -> Result<Self, ParseError>;
+}
+
+/// Error returned from `Parse::parse`, with a message and the span of the
+/// tokens it is about.
+#[derive(Clone, Debug)]
+pub struct ParseError {
+    span: Span,
+    message: String,
+}
+
+impl ParseError {
+    /// Creates an error with the given message at `span`.
+    pub fn new<M: Display>(span: Span, message: M) -> Self {
+        ParseError {
+            span,
+            message: message.to_string(),
+        }
+    }
+
+    /// Returns the span of the tokens which the error is about.
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    /// Returns the message of the error.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl Error for ParseError {}
+
+/// Parses any one token tree. A `Delimiter::None` group which starts at the
+/// cursor is parsed as a whole, rather than looked through.
+impl Parse for TokenTree {
+    fn parse(input: &mut Cursor) -> Result<Self, ParseError> {
+        if let Some(group) = input.none_group() {
+            return Ok(TokenTree::Group(group.clone()));
+        }
+        match input.next() {
+            Some(tt) => Ok(tt.clone()),
+            None => Err(input.error("unexpected end of input")),
+        }
+    }
+}
+
+/// Parses all of the tokens up to the end of the group the cursor is in,
+/// keeping them in the `Delimiter::None` groups they are in.
+impl Parse for TokenStream {
+    fn parse(input: &mut Cursor) -> Result<Self, ParseError> {
+        Ok(input.take_rest().into_iter().collect())
+    }
+}
+
+macro_rules! parse_token {
+    ($($ty:ident => $expected:expr,)*) => {$(
+        impl Parse for $ty {
+            fn parse(input: &mut Cursor) -> Result<Self, ParseError> {
+                match input.peek() {
+                    Some(TokenTree::$ty(tt)) => {
+                        let tt = tt.clone();
+                        input.advance();
+                        Ok(tt)
+                    }
+                    _ => Err(input.error(concat!("expected ", $expected))),
+                }
+            }
+        }
+    )*};
+}
+
+/// Parses a group. A `Delimiter::None` group which starts at the cursor is
+/// parsed as a whole, like for `TokenTree`, rather than looked through.
+impl Parse for Group {
+    fn parse(input: &mut Cursor) -> Result<Self, ParseError> {
+        if let Some(group) = input.none_group() {
+            return Ok(group.clone());
+        }
+        match input.peek() {
+            Some(TokenTree::Group(group)) => {
+                let group = group.clone();
+                input.advance();
+                Ok(group)
+            }
+            _ => Err(input.error("expected a group")),
+        }
+    }
+}
+
+parse_token! {
+    Ident => "an identifier",
+    Punct => "punctuation",
+    Literal => "a literal",
+}
+
+/// Parses `T`s up to the end of the group the cursor is in.
+impl<T: Parse> Parse for Vec<T> {
+    fn parse(input: &mut Cursor) -> Result<Self, ParseError> {
+        let mut values = Vec::new();
+        while !input.eof() {
+            values.push(input.parse()?);
+        }
+        Ok(values)
+    }
+}
+
 /// Public implementation details for the `TokenStream` type, such as iterators.
 #[doc(hidden)]
 pub mod token_stream {
@@ -1,14 +1,20 @@
 use crate::fallback::{
-    is_ident_continue, is_ident_start, Group, LexError, Literal, Span, TokenStream,
+    is_ident_continue, is_ident_start, is_punct_char, set_leading_trivia, Group, LexError, Literal,
+    Span, TokenStream,
+};
+use crate::{
+    AttrStyle, Delimiter, LexErrorKind, LexOptions, LiteralKind, Punct, Spacing, TokenTree,
 };
-use crate::{Delimiter, Punct, Spacing, TokenTree};
 use std::char;
+use std::ops::Range;
 use std::str::{Bytes, CharIndices, Chars};
+use std::sync::Arc;
 
 #[derive(Copy, Clone, Eq, PartialEq)]
 pub(crate) struct Cursor<'a> {
     pub rest: &'a str,
     pub off: u32,
+    pub map: u32,
 }
 
 impl<'a> Cursor<'a> {
@@ -17,6 +23,7 @@ impl<'a> Cursor<'a> {
         Cursor {
             rest,
             off: self.off + _front.chars().count() as u32,
+            map: self.map,
         }
     }
 
@@ -48,6 +55,20 @@ impl<'a> Cursor<'a> {
         self.rest.char_indices()
     }
 
+    /// The span of the next character, or an empty span at the end of input.
+    pub(crate) fn char_span(&self) -> Span {
+        let len = self.chars().next().map_or(0, char::len_utf8);
+        self.advance(len).span_from(self.off)
+    }
+
+    fn span_from(&self, lo: u32) -> Span {
+        Span {
+            lo,
+            hi: self.off,
+            map: self.map,
+        }
+    }
+
     fn parse(&self, tag: &str) -> Result<Cursor<'a>, Reject> {
         if self.starts_with(tag) {
             Ok(self.advance(tag.len()))
@@ -60,55 +81,88 @@ impl<'a> Cursor<'a> {
 pub(crate) struct Reject;
 type PResult<'a, O> = Result<(Cursor<'a>, O), Reject>;
 
+/// A literal which was recognized by its opening but is malformed, with the
+/// position at which lexing it failed.
+struct Malformed<'a> {
+    kind: LexErrorKind,
+    at: Cursor<'a>,
+}
+
+impl<'a> Malformed<'a> {
+    fn new(kind: LexErrorKind, input: Cursor<'a>, offset: usize) -> Self {
+        Malformed {
+            kind,
+            at: input.advance(offset),
+        }
+    }
+}
+
+impl From<Malformed<'_>> for Reject {
+    fn from(_: Malformed) -> Self {
+        Reject
+    }
+}
+
 fn skip_whitespace(input: Cursor) -> Cursor {
     let mut s = input;
+    while let Some((rest, _line_comment)) = trivia_item(s) {
+        s = rest;
+    }
+    s
+}
 
-    while !s.is_empty() {
-        let byte = s.as_bytes()[0];
-        if byte == b'/' {
-            if s.starts_with("//")
-                && (!s.starts_with("///") || s.starts_with("////"))
-                && !s.starts_with("//!")
-            {
-                let (cursor, _) = take_until_newline_or_eof(s);
-                s = cursor;
-                continue;
-            } else if s.starts_with("/**/") {
-                s = s.advance(4);
-                continue;
-            } else if s.starts_with("/*")
-                && (!s.starts_with("/**") || s.starts_with("/***"))
-                && !s.starts_with("/*!")
-            {
-                match block_comment(s) {
-                    Ok((rest, _)) => {
-                        s = rest;
-                        continue;
-                    }
-                    Err(Reject) => return s,
-                }
-            }
+/// Skips a single whitespace character or comment which is not a doc
+/// comment, also returning whether it was a line comment.
+fn trivia_item(s: Cursor) -> Option<(Cursor, bool)> {
+    let byte = *s.as_bytes().first()?;
+    if byte == b'/' {
+        if s.starts_with("//")
+            && (!s.starts_with("///") || s.starts_with("////"))
+            && !s.starts_with("//!")
+        {
+            let (cursor, _) = take_until_newline_or_eof(s);
+            return Some((cursor, true));
+        } else if s.starts_with("/**/") {
+            return Some((s.advance(4), false));
+        } else if s.starts_with("/*")
+            && (!s.starts_with("/**") || s.starts_with("/***"))
+            && !s.starts_with("/*!")
+        {
+            let (rest, _) = block_comment(s).ok()?;
+            return Some((rest, false));
         }
-        match byte {
-            b' ' | 0x09..=0x0d => {
-                s = s.advance(1);
-                continue;
-            }
-            b if b <= 0x7f => {}
-            _ => {
-                let ch = s.chars().next().unwrap();
-                if is_whitespace(ch) {
-                    s = s.advance(ch.len_utf8());
-                    continue;
-                }
+    }
+    match byte {
+        b' ' | 0x09..=0x0d => Some((s.advance(1), false)),
+        b if b <= 0x7f => None,
+        _ => {
+            let ch = s.chars().next().unwrap();
+            if is_whitespace(ch) {
+                Some((s.advance(ch.len_utf8()), false))
+            } else {
+                None
             }
         }
-        return s;
     }
-    s
 }
 
-fn block_comment(input: Cursor) -> PResult<&str> {
+/// Checks that `trivia` is made up only of whitespace and comments which are
+/// not doc comments. Returns whether it ends in a line comment, in which case
+/// a newline has to be written after it before anything else.
+pub(crate) fn check_trivia(trivia: &str) -> Option<bool> {
+    let mut input = Cursor {
+        rest: trivia,
+        off: 0,
+        map: 0,
+    };
+    let mut line_comment = false;
+    while !input.is_empty() {
+        (input, line_comment) = trivia_item(input)?;
+    }
+    Some(line_comment)
+}
+
+fn block_comment(input: Cursor<'_>) -> PResult<'_, &str> {
     if !input.starts_with("/*") {
         return Err(Reject);
     }
@@ -147,14 +201,33 @@ fn word_break(input: Cursor) -> Result<Cursor, Reject> {
     }
 }
 
-pub(crate) fn token_stream(mut input: Cursor) -> Result<TokenStream, LexError> {
+pub(crate) fn token_stream(
+    mut input: Cursor,
+    options: LexOptions,
+) -> Result<TokenStream, LexError> {
     let mut trees = Vec::new();
     let mut stack = Vec::new();
 
     loop {
+        let before_trivia = input;
         input = skip_whitespace(input);
-
-        if let Ok((rest, tt)) = doc_comment(input) {
+        let trivia = options
+            .trivia
+            .then(|| Arc::from(&before_trivia.rest[..before_trivia.len() - input.len()]));
+
+        if let Ok((rest, mut tt)) = doc_comment(input) {
+            set_leading_trivia(&mut tt[0], trivia);
+            if options.trivia || options.doc_comments {
+                if let Some(TokenTree::Group(group)) = tt.last_mut() {
+                    let mut source = &input.rest[..input.len() - rest.len()];
+                    if !options.trivia {
+                        // The "\r" of a "\r\n" line ending is only kept with
+                        // the rest of the whitespace.
+                        source = source.strip_suffix('\r').unwrap_or(source);
+                    }
+                    group.inner.doc = Some(Arc::from(source));
+                }
+            }
             trees.extend(tt);
             input = rest;
             continue;
@@ -162,16 +235,23 @@ pub(crate) fn token_stream(mut input: Cursor) -> Result<TokenStream, LexError> {
 
         let lo = input.off;
 
-        let first = match input.bytes().next() {
-            Some(first) => first,
-            None => match stack.last() {
-                None => return Ok(TokenStream::from(trees)),
-                Some((lo, _frame)) => {
-                    return Err(LexError {
-                        span: Span { lo: *lo, hi: *lo },
-                    })
+        let Some(first) = input.bytes().next() else {
+            return match stack.last() {
+                None => {
+                    let mut stream = TokenStream::from(trees);
+                    stream.set_trailing_trivia(trivia);
+                    Ok(stream)
                 }
-            },
+                Some((lo, (open_delimiter, _trees, _trivia))) => Err(LexError {
+                    kind: LexErrorKind::UnclosedDelimiter(*open_delimiter),
+                    start: Span {
+                        lo: *lo,
+                        hi: *lo + 1,
+                        map: input.map,
+                    },
+                    end: input.char_span(),
+                }),
+            };
         };
 
         if let Some(open_delimiter) = match first {
@@ -181,9 +261,7 @@ pub(crate) fn token_stream(mut input: Cursor) -> Result<TokenStream, LexError> {
             _ => None,
         } {
             input = input.advance(1);
-            let frame = (open_delimiter, trees);
-            let frame = (lo, frame);
-            stack.push(frame);
+            stack.push((lo, (open_delimiter, trees, trivia)));
             trees = Vec::new();
         } else if let Some(close_delimiter) = match first {
             b')' => Some(Delimiter::Parenthesis),
@@ -191,38 +269,115 @@ pub(crate) fn token_stream(mut input: Cursor) -> Result<TokenStream, LexError> {
             b'}' => Some(Delimiter::Brace),
             _ => None,
         } {
-            let frame = match stack.pop() {
-                Some(frame) => frame,
-                None => return Err(lex_error(input)),
+            let Some(frame) = stack.pop() else {
+                return Err(LexError {
+                    kind: LexErrorKind::UnexpectedClosingDelimiter(close_delimiter),
+                    start: input.char_span(),
+                    end: input.char_span(),
+                });
             };
             let (lo, frame) = frame;
-            let (open_delimiter, outer) = frame;
+            let (open_delimiter, outer, leading) = frame;
             if open_delimiter != close_delimiter {
-                return Err(lex_error(input));
+                return Err(LexError {
+                    kind: LexErrorKind::MismatchedDelimiter {
+                        open: open_delimiter,
+                        close: close_delimiter,
+                    },
+                    start: Span {
+                        lo,
+                        hi: lo + 1,
+                        map: input.map,
+                    },
+                    end: input.char_span(),
+                });
             }
             input = input.advance(1);
-            let mut g = Group::new(open_delimiter, TokenStream::from(trees));
-            g.set_span(Span { lo, hi: input.off });
+            let mut stream = TokenStream::from(trees);
+            stream.set_trailing_trivia(trivia);
+            let mut g = Group::new(open_delimiter, stream);
+            g.set_span(input.span_from(lo));
+            g.set_leading_trivia(leading);
             trees = outer;
             trees.push(TokenTree::Group(crate::Group::_new_stable(g)));
         } else {
             let (rest, mut tt) = match leaf_token(input) {
                 Ok((rest, tt)) => (rest, tt),
-                Err(Reject) => return Err(lex_error(input)),
+                Err(Reject) => return Err(leaf_error(input)),
             };
-            tt.set_span(crate::Span::_new_stable(Span { lo, hi: rest.off }));
+            tt.set_span(crate::Span::_new_stable(rest.span_from(lo)));
+            set_leading_trivia(&mut tt, trivia);
             trees.push(tt);
             input = rest;
         }
     }
 }
 
-fn lex_error(cursor: Cursor) -> LexError {
+/// Explains why no token could be lexed at `input`.
+fn leaf_error(input: Cursor) -> LexError {
+    let malformed = if input.starts_with("r#") && raw_string_open(input.advance(1)).is_err() {
+        Malformed::new(LexErrorKind::InvalidIdent, input, 0)
+    } else if let Some(malformed) = malformed_literal(input) {
+        malformed
+    } else if input.starts_with("//") || input.starts_with("/*") {
+        malformed_comment(input)
+    } else {
+        match input.chars().next() {
+            Some(ch) if ch.is_ascii_digit() => {
+                Malformed::new(LexErrorKind::InvalidLiteral, input, 0)
+            }
+            Some(ch) => Malformed::new(LexErrorKind::UnexpectedChar(ch), input, 0),
+            None => Malformed::new(LexErrorKind::InvalidLiteral, input, 0),
+        }
+    };
+    LexError {
+        kind: malformed.kind,
+        start: input.char_span(),
+        end: malformed.at.char_span(),
+    }
+}
+
+/// Explains why `input` is not a literal, for `Literal::from_str`.
+pub(crate) fn literal_error(input: Cursor) -> LexError {
+    let malformed = match malformed_literal(input) {
+        Some(malformed) => malformed,
+        None => Malformed::new(LexErrorKind::InvalidLiteral, input, 0),
+    };
     LexError {
-        span: Span {
-            lo: cursor.off,
-            hi: cursor.off,
+        kind: malformed.kind,
+        start: input.char_span(),
+        end: malformed.at.char_span(),
+    }
+}
+
+fn malformed_literal(input: Cursor) -> Option<Malformed> {
+    if let Ok(rest) = input.parse("\"") {
+        cooked_string(rest).err()
+    } else if let Ok(rest) = input.parse("b\"") {
+        cooked_byte_string(rest).err()
+    } else if let Ok(rest) = input.parse("b'") {
+        byte_body(rest).err()
+    } else if let Ok(rest) = input.parse("'") {
+        character_body(rest).err()
+    } else if let Ok(rest) = input.parse("br") {
+        let n = raw_string_open(rest).ok()?;
+        raw_string_body(rest, n, true).err()
+    } else if let Ok(rest) = input.parse("r") {
+        let n = raw_string_open(rest).ok()?;
+        raw_string_body(rest, n, false).err()
+    } else {
+        None
+    }
+}
+
+fn malformed_comment(input: Cursor) -> Malformed {
+    match doc_comment_contents(input) {
+        // Both `///` and `/**` are 3 bytes long.
+        Ok((_rest, (comment, _inner))) => match find_bare_cr(comment) {
+            Some(cr) => Malformed::new(LexErrorKind::BareCarriageReturn, input, 3 + cr),
+            None => Malformed::new(LexErrorKind::UnexpectedChar('/'), input, 0),
         },
+        Err(Reject) => Malformed::new(LexErrorKind::UnterminatedBlockComment, input, input.len()),
     }
 }
 
@@ -261,15 +416,16 @@ fn ident_any(input: Cursor) -> PResult<crate::Ident> {
         return Ok((rest, ident));
     }
 
-    if sym == "_" {
-        return Err(Reject);
+    match sym {
+        "_" | "super" | "self" | "Self" | "crate" => return Err(Reject),
+        _ => {}
     }
 
     let ident = crate::Ident::_new_raw(sym, crate::Span::call_site());
     Ok((rest, ident))
 }
 
-fn ident_not_raw(input: Cursor) -> PResult<&str> {
+fn ident_not_raw(input: Cursor<'_>) -> PResult<'_, &str> {
     let mut chars = input.char_indices();
 
     match chars.next() {
@@ -291,7 +447,9 @@ fn ident_not_raw(input: Cursor) -> PResult<&str> {
 pub(crate) fn literal(input: Cursor) -> PResult<Literal> {
     let rest = literal_nocapture(input)?;
     let end = input.len() - rest.len();
-    Ok((rest, Literal::_new(input.rest[..end].to_string())))
+    let mut literal = Literal::_new(input.rest[..end].to_string());
+    literal.set_span(rest.span_from(input.off));
+    Ok((rest, literal))
 }
 
 fn literal_nocapture(input: Cursor) -> Result<Cursor, Reject> {
@@ -319,17 +477,111 @@ fn literal_suffix(input: Cursor) -> Cursor {
     }
 }
 
+/// Splits the repr of a literal lexed by `literal`, without any leading `-`,
+/// into its kind, the token without its suffix, and the suffix.
+pub(crate) fn literal_parts(repr: &str) -> Option<(LiteralKind, &str, &str)> {
+    let kind = if repr.starts_with('"') {
+        LiteralKind::Str
+    } else if repr.starts_with('r') {
+        LiteralKind::RawStr
+    } else if repr.starts_with("b\"") {
+        LiteralKind::ByteStr
+    } else if repr.starts_with("br") {
+        LiteralKind::RawByteStr
+    } else if repr.starts_with("b'") {
+        LiteralKind::Byte
+    } else if repr.starts_with('\'') {
+        LiteralKind::Char
+    } else {
+        let input = Cursor {
+            rest: repr,
+            off: 0,
+            map: 0,
+        };
+        let (kind, rest) = match float_digits(input) {
+            Ok(rest) => (LiteralKind::Float, rest),
+            Err(Reject) => (LiteralKind::Int, digits(input).ok()?),
+        };
+        let (token, suffix) = repr.split_at(repr.len() - rest.len());
+        let radix = token.starts_with("0x") || token.starts_with("0o") || token.starts_with("0b");
+        let kind = match suffix {
+            "f32" | "f64" if !radix => LiteralKind::Float,
+            _ => kind,
+        };
+        return Some((kind, token, suffix));
+    };
+    let end = repr.rfind(['"', '\'', '#'])? + 1;
+    let (token, suffix) = repr.split_at(end);
+    Some((kind, token, suffix))
+}
+
+/// Unescapes the contents of a cooked string, byte string, character or byte
+/// literal, passing each resulting character to `push` along with the byte
+/// range of `contents` that it was written as.
+pub(crate) fn cooked_value(contents: &str, mut push: impl FnMut(Range<usize>, char)) -> Option<()> {
+    let mut chars = contents.char_indices().peekable();
+    while let Some((start, ch)) = chars.next() {
+        let ch = match ch {
+            '\\' => match chars.next()?.1 {
+                'n' => '\n',
+                'r' => '\r',
+                't' => '\t',
+                '\\' => '\\',
+                '0' => '\0',
+                '\'' => '\'',
+                '"' => '"',
+                'x' => {
+                    let hi = chars.next()?.1.to_digit(16)?;
+                    let lo = chars.next()?.1.to_digit(16)?;
+                    char::from_u32(hi * 0x10 + lo)?
+                }
+                'u' => {
+                    if chars.next()?.1 != '{' {
+                        return None;
+                    }
+                    let mut value = 0u32;
+                    loop {
+                        match chars.next()?.1 {
+                            '}' => break,
+                            '_' => {}
+                            ch => value = value.checked_mul(0x10)? + ch.to_digit(16)?,
+                        }
+                    }
+                    char::from_u32(value)?
+                }
+                '\n' | '\r' => {
+                    while chars.peek().is_some_and(|(_, ch)| ch.is_whitespace()) {
+                        chars.next();
+                    }
+                    continue;
+                }
+                _ => return None,
+            },
+            '\r' => {
+                if chars.next()?.1 != '\n' {
+                    return None;
+                }
+                '\n'
+            }
+            ch => ch,
+        };
+        let end = chars.peek().map_or(contents.len(), |&(end, _)| end);
+        push(start..end, ch);
+    }
+    Some(())
+}
+
 fn string(input: Cursor) -> Result<Cursor, Reject> {
     if let Ok(input) = input.parse("\"") {
-        cooked_string(input)
+        Ok(cooked_string(input)?)
     } else if let Ok(input) = input.parse("r") {
-        raw_string(input)
+        raw_string(input, false)
     } else {
         Err(Reject)
     }
 }
 
-fn cooked_string(input: Cursor) -> Result<Cursor, Reject> {
+fn cooked_string(input: Cursor) -> Result<Cursor, Malformed> {
     let mut chars = input.char_indices().peekable();
 
     while let Some((i, ch)) = chars.next() {
@@ -340,54 +592,60 @@ fn cooked_string(input: Cursor) -> Result<Cursor, Reject> {
             }
             '\r' => match chars.next() {
                 Some((_, '\n')) => {}
-                _ => break,
+                _ => return Err(Malformed::new(LexErrorKind::BareCarriageReturn, input, i)),
             },
             '\\' => match chars.next() {
                 Some((_, 'x')) => {
                     if !backslash_x_char(&mut chars) {
-                        break;
+                        return Err(Malformed::new(LexErrorKind::InvalidEscape, input, i));
                     }
                 }
                 Some((_, 'n' | 'r' | 't' | '\\' | '\'' | '"' | '0')) => {}
                 Some((_, 'u')) => {
                     if !backslash_u(&mut chars) {
-                        break;
+                        return Err(Malformed::new(LexErrorKind::InvalidEscape, input, i));
                     }
                 }
-                Some((_, ch @ ('\n' | '\r'))) => {
-                    let mut last = ch;
+                Some((i, ch @ ('\n' | '\r'))) => {
+                    let mut last = (i, ch);
                     loop {
-                        if last == '\r' && chars.next().map_or(true, |(_, ch)| ch != '\n') {
-                            return Err(Reject);
+                        if last.1 == '\r' && chars.next().is_none_or(|(_, ch)| ch != '\n') {
+                            let kind = LexErrorKind::BareCarriageReturn;
+                            return Err(Malformed::new(kind, input, last.0));
                         }
                         match chars.peek() {
-                            Some((_, ch)) if ch.is_whitespace() => {
-                                last = *ch;
+                            Some(&(i, ch)) if ch.is_whitespace() => {
+                                last = (i, ch);
                                 chars.next();
                             }
                             _ => break,
                         }
                     }
                 }
-                _ => break,
+                Some(_) => return Err(Malformed::new(LexErrorKind::InvalidEscape, input, i)),
+                None => break,
             },
             _ch => {}
         }
     }
-    Err(Reject)
+    Err(Malformed::new(
+        LexErrorKind::UnterminatedLiteral,
+        input,
+        input.len(),
+    ))
 }
 
 fn byte_string(input: Cursor) -> Result<Cursor, Reject> {
     if let Ok(input) = input.parse("b\"") {
-        cooked_byte_string(input)
+        Ok(cooked_byte_string(input)?)
     } else if let Ok(input) = input.parse("br") {
-        raw_string(input)
+        raw_string(input, true)
     } else {
         Err(Reject)
     }
 }
 
-fn cooked_byte_string(mut input: Cursor) -> Result<Cursor, Reject> {
+fn cooked_byte_string(mut input: Cursor) -> Result<Cursor, Malformed> {
     let mut bytes = input.bytes().enumerate();
     while let Some((offset, b)) = bytes.next() {
         match b {
@@ -395,58 +653,82 @@ fn cooked_byte_string(mut input: Cursor) -> Result<Cursor, Reject> {
                 let input = input.advance(offset + 1);
                 return Ok(literal_suffix(input));
             }
-            b'\r' => match bytes.next() {
-                Some((_, b'\n')) => {}
-                _ => break,
-            },
+            b'\r' => {
+                if !matches!(bytes.next(), Some((_, b'\n'))) {
+                    let kind = LexErrorKind::BareCarriageReturn;
+                    return Err(Malformed::new(kind, input, offset));
+                }
+            }
             b'\\' => match bytes.next() {
                 Some((_, b'x')) => {
                     if !backslash_x_byte(&mut bytes) {
-                        break;
+                        let kind = LexErrorKind::InvalidEscape;
+                        return Err(Malformed::new(kind, input, offset));
                     }
                 }
                 Some((_, b'n' | b'r' | b't' | b'\\' | b'0' | b'\'' | b'"')) => {}
                 Some((newline, b @ (b'\n' | b'\r'))) => {
-                    let mut last = b as char;
-                    let rest = input.advance(newline + 1);
-                    let mut chars = rest.char_indices();
+                    let mut last = (0, b as char);
+                    let rest = input.advance(newline);
+                    let mut chars = rest.char_indices().skip(1);
                     loop {
-                        if last == '\r' && chars.next().map_or(true, |(_, ch)| ch != '\n') {
-                            return Err(Reject);
+                        if last.1 == '\r' && chars.next().is_none_or(|(_, ch)| ch != '\n') {
+                            let kind = LexErrorKind::BareCarriageReturn;
+                            return Err(Malformed::new(kind, rest, last.0));
                         }
                         match chars.next() {
-                            Some((_, ch)) if ch.is_whitespace() => last = ch,
+                            Some((i, ch)) if ch.is_whitespace() => last = (i, ch),
                             Some((offset, _)) => {
                                 input = rest.advance(offset);
                                 bytes = input.bytes().enumerate();
                                 break;
                             }
-                            None => return Err(Reject),
+                            None => {
+                                let kind = LexErrorKind::UnterminatedLiteral;
+                                return Err(Malformed::new(kind, rest, rest.len()));
+                            }
                         }
                     }
                 }
-                _ => break,
+                Some(_) => {
+                    let kind = LexErrorKind::InvalidEscape;
+                    return Err(Malformed::new(kind, input, offset));
+                }
+                None => break,
             },
             b if b < 0x80 => {}
-            _ => break,
+            _ => return Err(Malformed::new(LexErrorKind::InvalidLiteral, input, offset)),
         }
     }
-    Err(Reject)
+    Err(Malformed::new(
+        LexErrorKind::UnterminatedLiteral,
+        input,
+        input.len(),
+    ))
 }
 
-fn raw_string(input: Cursor) -> Result<Cursor, Reject> {
-    let mut chars = input.char_indices();
-    let mut n = 0;
-    for (i, ch) in &mut chars {
+fn raw_string(input: Cursor, bytes: bool) -> Result<Cursor, Reject> {
+    let n = raw_string_open(input)?;
+    Ok(raw_string_body(input, n, bytes)?)
+}
+
+/// Returns the offset of the opening quote of a raw string, which is also the
+/// number of `#` around it.
+fn raw_string_open(input: Cursor) -> Result<usize, Reject> {
+    for (i, ch) in input.char_indices() {
         match ch {
-            '"' => {
-                n = i;
-                break;
-            }
+            '"' => return Ok(i),
             '#' => {}
             _ => return Err(Reject),
         }
     }
+    Err(Reject)
+}
+
+/// Lexes the rest of a raw string after its opening quote, which only allows
+/// ASCII if it is a raw byte string.
+fn raw_string_body(input: Cursor, n: usize, bytes: bool) -> Result<Cursor, Malformed> {
+    let mut chars = input.char_indices().skip(n + 1);
     while let Some((i, ch)) = chars.next() {
         match ch {
             '"' if input.rest[i + 1..].starts_with(&input.rest[..n]) => {
@@ -455,16 +737,27 @@ fn raw_string(input: Cursor) -> Result<Cursor, Reject> {
             }
             '\r' => match chars.next() {
                 Some((_, '\n')) => {}
-                _ => break,
+                _ => return Err(Malformed::new(LexErrorKind::BareCarriageReturn, input, i)),
             },
+            _ if bytes && !ch.is_ascii() => {
+                return Err(Malformed::new(LexErrorKind::InvalidLiteral, input, i));
+            }
             _ => {}
         }
     }
-    Err(Reject)
+    Err(Malformed::new(
+        LexErrorKind::UnterminatedLiteral,
+        input,
+        input.len(),
+    ))
 }
 
 fn byte(input: Cursor) -> Result<Cursor, Reject> {
     let input = input.parse("b'")?;
+    Ok(byte_body(input)?)
+}
+
+fn byte_body(input: Cursor) -> Result<Cursor, Malformed> {
     let mut bytes = input.bytes().enumerate();
     let ok = match bytes.next().map(|(_, b)| b) {
         Some(b'\\') => match bytes.next().map(|(_, b)| b) {
@@ -472,21 +765,34 @@ fn byte(input: Cursor) -> Result<Cursor, Reject> {
             Some(b'n' | b'r' | b't' | b'\\' | b'0' | b'\'' | b'"') => true,
             _ => false,
         },
-        b => b.is_some(),
+        Some(_) => true,
+        None => {
+            let kind = LexErrorKind::UnterminatedLiteral;
+            return Err(Malformed::new(kind, input, 0));
+        }
     };
     if !ok {
-        return Err(Reject);
+        return Err(Malformed::new(LexErrorKind::InvalidEscape, input, 0));
     }
-    let (offset, _) = bytes.next().ok_or(Reject)?;
+    let (offset, _) = bytes.next().ok_or_else(|| {
+        let kind = LexErrorKind::UnterminatedLiteral;
+        Malformed::new(kind, input, input.len())
+    })?;
     if !input.chars().as_str().is_char_boundary(offset) {
-        return Err(Reject);
+        return Err(Malformed::new(LexErrorKind::InvalidLiteral, input, 0));
+    }
+    match input.advance(offset).parse("'") {
+        Ok(input) => Ok(literal_suffix(input)),
+        Err(Reject) => Err(Malformed::new(LexErrorKind::InvalidLiteral, input, offset)),
     }
-    let input = input.advance(offset).parse("'")?;
-    Ok(literal_suffix(input))
 }
 
 fn character(input: Cursor) -> Result<Cursor, Reject> {
     let input = input.parse("'")?;
+    Ok(character_body(input)?)
+}
+
+fn character_body(input: Cursor) -> Result<Cursor, Malformed> {
     let mut chars = input.char_indices();
     let ok = match chars.next().map(|(_, ch)| ch) {
         Some('\\') => match chars.next().map(|(_, ch)| ch) {
@@ -495,14 +801,23 @@ fn character(input: Cursor) -> Result<Cursor, Reject> {
             Some('n' | 'r' | 't' | '\\' | '0' | '\'' | '"') => true,
             _ => false,
         },
-        ch => ch.is_some(),
+        Some(_) => true,
+        None => {
+            let kind = LexErrorKind::UnterminatedLiteral;
+            return Err(Malformed::new(kind, input, 0));
+        }
     };
     if !ok {
-        return Err(Reject);
+        return Err(Malformed::new(LexErrorKind::InvalidEscape, input, 0));
+    }
+    let (idx, _) = chars.next().ok_or_else(|| {
+        let kind = LexErrorKind::UnterminatedLiteral;
+        Malformed::new(kind, input, input.len())
+    })?;
+    match input.advance(idx).parse("'") {
+        Ok(input) => Ok(literal_suffix(input)),
+        Err(Reject) => Err(Malformed::new(LexErrorKind::InvalidLiteral, input, idx)),
     }
-    let (idx, _) = chars.next().ok_or(Reject)?;
-    let input = input.advance(idx).parse("'")?;
-    Ok(literal_suffix(input))
 }
 
 macro_rules! next_ch {
@@ -574,7 +889,7 @@ fn float(input: Cursor) -> Result<Cursor, Reject> {
 fn float_digits(input: Cursor) -> Result<Cursor, Reject> {
     let mut chars = input.chars().peekable();
     match chars.next() {
-        Some(ch) if ('0'..='9').contains(&ch) => {}
+        Some(ch) if ch.is_ascii_digit() => {}
         _ => return Err(Reject),
     }
 
@@ -594,7 +909,7 @@ fn float_digits(input: Cursor) -> Result<Cursor, Reject> {
                 chars.next();
                 if chars
                     .peek()
-                    .map_or(false, |&ch| ch == '.' || is_ident_start(ch))
+                    .is_some_and(|&ch| ch == '.' || is_ident_start(ch))
                 {
                     return Err(Reject);
                 }
@@ -710,7 +1025,7 @@ fn digits(mut input: Cursor) -> Result<Cursor, Reject> {
                 continue;
             }
             _ => break,
-        };
+        }
         len += 1;
         empty = false;
     }
@@ -745,14 +1060,10 @@ fn punct_char(input: Cursor) -> PResult<char> {
     }
 
     let mut chars = input.chars();
-    let first = match chars.next() {
-        Some(ch) => ch,
-        None => {
-            return Err(Reject);
-        }
+    let Some(first) = chars.next() else {
+        return Err(Reject);
     };
-    let recognized = "~!@#$%^&*-=+|;:,<.>/?'";
-    if recognized.contains(first) {
+    if is_punct_char(first) {
         Ok((input.advance(first.len_utf8()), first))
     } else {
         Err(Reject)
@@ -762,17 +1073,18 @@ fn punct_char(input: Cursor) -> PResult<char> {
 fn doc_comment(input: Cursor) -> PResult<Vec<TokenTree>> {
     let lo = input.off;
     let (rest, (comment, inner)) = doc_comment_contents(input)?;
-    let span = crate::Span::_new_stable(Span { lo, hi: rest.off });
+    let span = crate::Span::_new_stable(rest.span_from(lo));
 
-    let mut scan_for_bare_cr = comment;
-    while let Some(cr) = scan_for_bare_cr.find('\r') {
-        let rest = &scan_for_bare_cr[cr + 1..];
-        if !rest.starts_with('\n') {
-            return Err(Reject);
-        }
-        scan_for_bare_cr = rest;
+    if find_bare_cr(comment).is_some() {
+        return Err(Reject);
     }
 
+    Ok((rest, doc_attribute(comment, inner, span)))
+}
+
+/// The `#[doc = "..."]` or `#![doc = "..."]` tokens that a doc comment with
+/// the given text stands for.
+pub(crate) fn doc_attribute(comment: &str, inner: bool, span: crate::Span) -> Vec<TokenTree> {
     let mut trees = Vec::new();
     trees.push(TokenTree::Punct(Punct::new('#', Spacing::Alone)));
     if inner {
@@ -791,10 +1103,32 @@ fn doc_comment(input: Cursor) -> PResult<Vec<TokenTree>> {
     for tt in &mut trees {
         tt.set_span(span);
     }
-    Ok((rest, trees))
+    trees
+}
+
+/// Whether `repr` lexes as exactly one doc comment with the given style and
+/// text.
+pub(crate) fn is_doc_comment(repr: &str, style: AttrStyle, text: &str) -> bool {
+    doc_comment_text(repr) == Some((text, style == AttrStyle::Inner))
 }
 
-fn doc_comment_contents(input: Cursor) -> PResult<(&str, bool)> {
+/// If `repr` lexes as exactly one doc comment, returns its text and whether
+/// it is an inner doc comment.
+pub(crate) fn doc_comment_text(repr: &str) -> Option<(&str, bool)> {
+    let input = Cursor {
+        rest: repr,
+        off: 0,
+        map: 0,
+    };
+    match doc_comment_contents(input) {
+        Ok((rest, (comment, inner))) if rest.is_empty() && find_bare_cr(comment).is_none() => {
+            Some((comment, inner))
+        }
+        _ => None,
+    }
+}
+
+fn doc_comment_contents(input: Cursor<'_>) -> PResult<'_, (&str, bool)> {
     if input.starts_with("//!") {
         let input = input.advance(3);
         let (input, s) = take_until_newline_or_eof(input);
@@ -817,7 +1151,7 @@ fn doc_comment_contents(input: Cursor) -> PResult<(&str, bool)> {
     }
 }
 
-fn take_until_newline_or_eof(input: Cursor) -> (Cursor, &str) {
+fn take_until_newline_or_eof(input: Cursor<'_>) -> (Cursor<'_>, &str) {
     let chars = input.char_indices();
 
     for (i, ch) in chars {
@@ -830,3 +1164,17 @@ fn take_until_newline_or_eof(input: Cursor) -> (Cursor, &str) {
 
     (input.advance(input.len()), input.rest)
 }
+
+/// Returns the offset of the first carriage return in `s` which is not
+/// followed by a newline.
+fn find_bare_cr(s: &str) -> Option<usize> {
+    let mut offset = 0;
+    while let Some(cr) = s[offset..].find('\r') {
+        let cr = offset + cr;
+        if !s[cr + 1..].starts_with('\n') {
+            return Some(cr);
+        }
+        offset = cr + 1;
+    }
+    None
+}
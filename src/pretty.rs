@@ -0,0 +1,355 @@
+//! Multi-line formatting of token streams, for `TokenStream::pretty`.
+//!
+//! The output only ever differs from the plain `Display` output in where it
+//! puts whitespace, and whitespace is only added or removed between tokens
+//! where doing so cannot change how the output lexes, so it parses back into
+//! the same tokens.
+
+use crate::fallback::{can_join, doc_comment_source};
+use crate::{Delimiter, Group, PrettyOptions, Spacing, TokenTree};
+use std::iter;
+
+/// How the tokens of one stream are laid out relative to each other.
+#[derive(Copy, Clone, PartialEq)]
+enum Layout {
+    /// The contents of braces or the top level: statements and items each
+    /// start on a new line.
+    Items,
+    /// The contents of a parenthesized or bracketed group which does not fit
+    /// on the current line: one comma-separated element per line.
+    List,
+    /// Everything on the current line.
+    Inline,
+}
+
+/// What is known about the tokens of a stream before the current one.
+#[derive(Default)]
+struct Context {
+    /// Number of unclosed `<` which look like they start generic arguments.
+    generics: usize,
+    /// Whether the current item has reached its `where` clause, whose commas
+    /// do not start new lines.
+    in_where: bool,
+}
+
+enum Separator {
+    None,
+    Space,
+    Newline,
+}
+
+pub(crate) struct Printer {
+    options: PrettyOptions,
+    out: String,
+    level: usize,
+    column: usize,
+    /// Set by a line doc comment, which nothing may follow on the same line.
+    pending_newline: bool,
+    /// Whether this printer only measures how wide the output is on one line.
+    flat: bool,
+}
+
+impl Printer {
+    pub(crate) fn new(options: PrettyOptions) -> Self {
+        Printer {
+            options,
+            out: String::new(),
+            level: 0,
+            column: 0,
+            pending_newline: false,
+            flat: false,
+        }
+    }
+
+    pub(crate) fn print(mut self, tokens: &[TokenTree]) -> String {
+        self.stream(tokens, Layout::Items);
+        self.out
+    }
+
+    fn write(&mut self, s: &str) {
+        if self.pending_newline {
+            self.newline();
+        }
+        self.out.push_str(s);
+        self.column += s.chars().count();
+    }
+
+    fn newline(&mut self) {
+        self.pending_newline = false;
+        self.out.push('\n');
+        let indent = self.level * self.options.indent;
+        self.out.extend(iter::repeat_n(' ', indent));
+        self.column = indent;
+    }
+
+    fn stream(&mut self, tokens: &[TokenTree], layout: Layout) {
+        let layout = if self.flat { Layout::Inline } else { layout };
+        let mut cx = Context::default();
+        let mut i = 0;
+        while let Some(tt) = tokens.get(i) {
+            let closes_generics = cx.generics > 0 && closes_generics(tokens, i);
+            if i > 0 {
+                match separator(tokens, i, layout, &cx, closes_generics) {
+                    Separator::None => {}
+                    Separator::Space => self.write(" "),
+                    Separator::Newline => self.newline(),
+                }
+            }
+            if let Some((comment, len)) = doc_comment_source(&tokens[i..]) {
+                self.write(comment.trim_end_matches('\r'));
+                self.pending_newline = comment.starts_with("//");
+                i += len;
+                continue;
+            }
+            match tt {
+                TokenTree::Punct(punct) if punct.as_char() == ';' => cx = Context::default(),
+                TokenTree::Punct(_) if opens_generics(tokens, i) => cx.generics += 1,
+                TokenTree::Punct(_) if closes_generics => cx.generics -= 1,
+                TokenTree::Ident(ident) if ident == "where" => cx.in_where = true,
+                TokenTree::Group(group) if group.delimiter() == Delimiter::Brace => {
+                    cx = Context::default();
+                }
+                _ => {}
+            }
+            self.token(tt, layout);
+            i += 1;
+        }
+    }
+
+    fn token(&mut self, tt: &TokenTree, layout: Layout) {
+        match tt {
+            TokenTree::Group(group) => self.group(group, layout),
+            tt => self.write(&tt.to_string()),
+        }
+    }
+
+    fn group(&mut self, group: &Group, layout: Layout) {
+        let tokens = group.inner.stream_tokens();
+        let brace = group.delimiter() == Delimiter::Brace;
+        let (open, close) = match group.delimiter() {
+            Delimiter::Parenthesis => ("(", ")"),
+            Delimiter::Bracket => ("[", "]"),
+            Delimiter::Brace => ("{", "}"),
+            Delimiter::None => return self.stream(tokens, layout),
+        };
+        self.write(open);
+        if !tokens.is_empty() {
+            if self.flat {
+                if brace {
+                    self.write(" ");
+                }
+                self.stream(tokens, Layout::Inline);
+                if brace {
+                    self.write(" ");
+                }
+            } else if brace || !self.fits(tokens) {
+                self.level += 1;
+                self.newline();
+                self.stream(tokens, if brace { Layout::Items } else { Layout::List });
+                self.level -= 1;
+                self.newline();
+            } else {
+                self.stream(tokens, Layout::Inline);
+            }
+        }
+        self.write(close);
+    }
+
+    /// Whether `tokens` fit on the current line inside a pair of delimiters.
+    fn fits(&self, tokens: &[TokenTree]) -> bool {
+        let mut measure = Printer::new(self.options);
+        measure.flat = true;
+        measure.stream(tokens, Layout::Inline);
+        !measure.pending_newline
+            && !measure.out.contains('\n')
+            && self.column + measure.column + 2 <= self.options.max_width
+    }
+}
+
+/// What to print between `tokens[i - 1]` and `tokens[i]`.
+fn separator(
+    tokens: &[TokenTree],
+    i: usize,
+    layout: Layout,
+    cx: &Context,
+    closes_generics: bool,
+) -> Separator {
+    let prev = &tokens[i - 1];
+    let next = &tokens[i];
+    if let TokenTree::Punct(punct) = prev {
+        if punct.spacing() == Spacing::Joint {
+            return Separator::None;
+        }
+    }
+    let list_break = is_punct(prev, ',') && cx.generics == 0;
+    let breaks = match layout {
+        Layout::Items => ends_item(tokens, i) || list_break && !cx.in_where,
+        Layout::List => list_break,
+        Layout::Inline => false,
+    };
+    let tight = is_tight(tokens, i)
+        || closes_generics
+        || opens_generics(tokens, i)
+        || opens_generics(tokens, i - 1);
+    if breaks {
+        Separator::Newline
+    } else if tight && can_join(prev, next) {
+        Separator::None
+    } else {
+        Separator::Space
+    }
+}
+
+/// Whether `tokens[i - 1]` ends a statement or item.
+fn ends_item(tokens: &[TokenTree], i: usize) -> bool {
+    let prev = &tokens[i - 1];
+    let next = &tokens[i];
+    match prev {
+        TokenTree::Punct(punct) => punct.as_char() == ';',
+        TokenTree::Group(group) => match group.delimiter() {
+            Delimiter::Brace => {
+                let continues = match next {
+                    TokenTree::Punct(punct) => ";,.?".contains(punct.as_char()),
+                    TokenTree::Ident(ident) => ident == "else" || ident == "as",
+                    TokenTree::Group(group) => group.delimiter() == Delimiter::Brace,
+                    TokenTree::Literal(_) => false,
+                };
+                !continues
+            }
+            // The end of an attribute.
+            Delimiter::Bracket => {
+                i >= 2 && is_punct(&tokens[i - 2], '#')
+                    || i >= 3 && is_punct(&tokens[i - 2], '!') && is_punct(&tokens[i - 3], '#')
+            }
+            Delimiter::Parenthesis | Delimiter::None => false,
+        },
+        TokenTree::Ident(_) | TokenTree::Literal(_) => false,
+    }
+}
+
+/// Whether `tokens[i - 1]` and `tokens[i]` look better without a space.
+fn is_tight(tokens: &[TokenTree], i: usize) -> bool {
+    let prev = &tokens[i - 1];
+    let next = &tokens[i];
+    match next {
+        TokenTree::Punct(punct) => match punct.as_char() {
+            ',' | ';' | '?' | '.' => return true,
+            ':' => return !matches!(prev, TokenTree::Punct(_)),
+            // A macro invocation.
+            '!' if punct.spacing() == Spacing::Alone => return is_name(prev),
+            _ => {}
+        },
+        TokenTree::Group(group) => match group.delimiter() {
+            Delimiter::Parenthesis | Delimiter::Bracket => match prev {
+                TokenTree::Ident(_) => return is_name(prev),
+                TokenTree::Group(_) => return true,
+                TokenTree::Punct(punct) if "#!".contains(punct.as_char()) => return true,
+                TokenTree::Punct(_) | TokenTree::Literal(_) => {}
+            },
+            Delimiter::Brace | Delimiter::None => {}
+        },
+        TokenTree::Ident(_) | TokenTree::Literal(_) => {}
+    }
+    match prev {
+        TokenTree::Punct(punct) => match punct.as_char() {
+            '.' => true,
+            // The second colon of a path separator.
+            ':' => i >= 2 && is_joint_punct(&tokens[i - 2], ':'),
+            // A prefix operator.
+            '&' | '*' | '-' | '!' => match i.checked_sub(2).map(|j| &tokens[j]) {
+                None => true,
+                Some(TokenTree::Punct(punct)) => punct.spacing() == Spacing::Alone,
+                Some(before) => matches!(before, TokenTree::Ident(_)) && !is_name(before),
+            },
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+/// Whether `tokens[i]` is a `<` which looks like it starts generic
+/// arguments or parameters.
+fn opens_generics(tokens: &[TokenTree], i: usize) -> bool {
+    if !is_punct(&tokens[i], '<') || i >= 1 && is_joint_punct(&tokens[i - 1], '<') {
+        return false;
+    }
+    if is_joint_punct(&tokens[i], '<') {
+        if let Some(TokenTree::Punct(next)) = tokens.get(i + 1) {
+            if "=<-".contains(next.as_char()) {
+                return false;
+            }
+        }
+    }
+    match i.checked_sub(1).map(|j| &tokens[j]) {
+        Some(TokenTree::Ident(ident)) => {
+            let ident = ident.to_string();
+            let declared = i >= 2
+                && matches!(&tokens[i - 2], TokenTree::Ident(keyword)
+                    if ["fn", "struct", "enum", "union", "trait", "type"]
+                        .iter()
+                        .any(|k| keyword == k));
+            ident == "impl" || ident.starts_with(char::is_uppercase) || declared
+        }
+        // A turbofish.
+        Some(TokenTree::Punct(punct)) if punct.as_char() == ':' => {
+            i >= 2 && is_joint_punct(&tokens[i - 2], ':')
+        }
+        _ => false,
+    }
+}
+
+/// Whether `tokens[i]` is a `>` which could close generic arguments.
+fn closes_generics(tokens: &[TokenTree], i: usize) -> bool {
+    if !is_punct(&tokens[i], '>') {
+        return false;
+    }
+    let arrow =
+        i >= 1 && (is_joint_punct(&tokens[i - 1], '-') || is_joint_punct(&tokens[i - 1], '='));
+    let comparison = is_joint_punct(&tokens[i], '>')
+        && matches!(tokens.get(i + 1), Some(next) if is_punct(next, '='));
+    !arrow && !comparison
+}
+
+fn is_punct(tt: &TokenTree, ch: char) -> bool {
+    matches!(tt, TokenTree::Punct(punct) if punct.as_char() == ch)
+}
+
+fn is_joint_punct(tt: &TokenTree, ch: char) -> bool {
+    matches!(tt, TokenTree::Punct(punct) if punct.as_char() == ch && punct.spacing() == Spacing::Joint)
+}
+
+/// Whether `tt` is an identifier other than a keyword which is followed by a
+/// space, such as the name of a function or macro.
+fn is_name(tt: &TokenTree) -> bool {
+    let ident = match tt {
+        TokenTree::Ident(ident) => ident.to_string(),
+        _ => return false,
+    };
+    !matches!(
+        ident.as_str(),
+        "as" | "async"
+            | "await"
+            | "box"
+            | "break"
+            | "const"
+            | "continue"
+            | "dyn"
+            | "else"
+            | "for"
+            | "if"
+            | "impl"
+            | "in"
+            | "let"
+            | "loop"
+            | "match"
+            | "move"
+            | "mut"
+            | "ref"
+            | "return"
+            | "static"
+            | "unsafe"
+            | "where"
+            | "while"
+            | "yield"
+    )
+}
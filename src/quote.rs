@@ -0,0 +1,505 @@
+//! The `quote!` macro and the `ToTokens` implementations it interpolates
+//! with.
+//!
+//! The macro walks its input one token at a time, looking at the three tokens
+//! on either side to recognize `#var` interpolations and `#(...)*`
+//! repetitions, so that long inputs do not run into the recursion limit.
+//! Groups are quoted recursively. Every other token is stringified and lexed
+//! without being recorded in a source map, so quoting in a loop does not grow
+//! the process-wide source map.
+//!
+//! Repetitions bind each variable inside them to an iterator, through the
+//! `ext` traits below, and then step all of the iterators together until one
+//! of them runs out.
+
+use crate::{
+    imp, Delimiter, DocComment, Group, Ident, Literal, Punct, Span, ToTokens, TokenStream,
+    TokenTree,
+};
+use std::collections::BTreeSet;
+use std::iter;
+use std::ops::BitOr;
+
+/// Quotes Rust syntax into a `TokenStream`.
+///
+/// `#var` interpolates any `var` implementing [`ToTokens`], and
+/// `#(...)*` or `#(...),*` repeats its contents once for each element of the
+/// variables interpolated inside it, with an optional separator between
+/// repetitions. Every variable interpolated inside a repetition must be an
+/// iterator or a collection such as a `Vec`, a slice or an `Option`; they are
+/// iterated in lockstep until the shortest runs out.
+///
+/// Quoted tokens have `Span::call_site()` spans.
+///
+/// # Panics
+///
+/// Tokens other than groups are lexed when the macro runs, which panics on a
+/// literal that this crate does not lex, such as a C string literal.
+///
+/// ```
+/// use proc_macro0::{quote, Ident, Span};
+///
+/// let name = Ident::new("Point", Span::call_site());
+/// let fields = vec![
+///     Ident::new("x", Span::call_site()),
+///     Ident::new("y", Span::call_site()),
+/// ];
+/// let tokens = quote! {
+///     struct #name {
+///         #(#fields: f64),*
+///     }
+/// };
+/// assert_eq!(tokens.to_string(), "struct Point { x : f64 , y : f64 }");
+/// ```
+#[macro_export]
+macro_rules! quote {
+    () => {
+        $crate::TokenStream::new()
+    };
+    ($($tt:tt)*) => {{
+        let mut _s = $crate::TokenStream::new();
+        $crate::__quote_each_token!{_s $($tt)*}
+        _s
+    }};
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __quote_each_token {
+    ($tokens:ident $($tts:tt)*) => {
+        $crate::__quote_tokens_with_context!{$tokens
+            (@ @ @ @ @ @ $($tts)*)
+            (@ @ @ @ @ $($tts)* @)
+            (@ @ @ @ $($tts)* @ @)
+            (@ @ @ $(($tts))* @ @ @)
+            (@ @ $($tts)* @ @ @ @)
+            (@ $($tts)* @ @ @ @ @)
+            ($($tts)* @ @ @ @ @ @)
+        }
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __quote_tokens_with_context {
+    ($tokens:ident
+        ($($b3:tt)*) ($($b2:tt)*) ($($b1:tt)*)
+        ($($curr:tt)*)
+        ($($a1:tt)*) ($($a2:tt)*) ($($a3:tt)*)
+    ) => {
+        $(
+            $crate::__quote_token_with_context!{$tokens $b3 $b2 $b1 $curr $a1 $a2 $a3}
+        )*
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __quote_token_with_context {
+    // Padding around the input.
+    ($tokens:ident $b3:tt $b2:tt $b1:tt @ $a1:tt $a2:tt $a3:tt) => {};
+
+    // A repetition without a separator, `#(...)*`.
+    ($tokens:ident $b3:tt $b2:tt $b1:tt (#) ( $($inner:tt)* ) * $a3:tt) => {{
+        #[allow(unused_imports)]
+        use $crate::__private::ext::*;
+        let has_iter = $crate::__private::ThereIsNoIteratorInRepetition;
+        $crate::__quote_pounded_vars!{__quote_bind_into_iter!(has_iter) $($inner)*}
+        let _: $crate::__private::HasIterator = has_iter;
+        loop {
+            $crate::__quote_pounded_vars!{__quote_bind_next_or_break!() $($inner)*}
+            $crate::__quote_each_token!{$tokens $($inner)*}
+        }
+    }};
+    ($tokens:ident $b3:tt $b2:tt # (( $($inner:tt)* )) * $a2:tt $a3:tt) => {};
+    ($tokens:ident $b3:tt # ( $($inner:tt)* ) (*) $a1:tt $a2:tt $a3:tt) => {};
+
+    // A repetition with a separator, `#(...),*`.
+    ($tokens:ident $b3:tt $b2:tt $b1:tt (#) ( $($inner:tt)* ) $sep:tt *) => {{
+        #[allow(unused_imports)]
+        use $crate::__private::ext::*;
+        let mut _first = true;
+        let has_iter = $crate::__private::ThereIsNoIteratorInRepetition;
+        $crate::__quote_pounded_vars!{__quote_bind_into_iter!(has_iter) $($inner)*}
+        let _: $crate::__private::HasIterator = has_iter;
+        loop {
+            $crate::__quote_pounded_vars!{__quote_bind_next_or_break!() $($inner)*}
+            if !_first {
+                $crate::__quote_token!{$tokens $sep}
+            }
+            _first = false;
+            $crate::__quote_each_token!{$tokens $($inner)*}
+        }
+    }};
+    ($tokens:ident $b3:tt $b2:tt # (( $($inner:tt)* )) $sep:tt * $a3:tt) => {};
+    ($tokens:ident $b3:tt # ( $($inner:tt)* ) ($sep:tt) * $a2:tt $a3:tt) => {};
+    ($tokens:ident # ( $($inner:tt)* ) $sep:tt (*) $a1:tt $a2:tt $a3:tt) => {};
+
+    // An interpolation, `#var`.
+    ($tokens:ident $b3:tt $b2:tt $b1:tt (#) $var:ident $a2:tt $a3:tt) => {
+        $crate::ToTokens::to_tokens(&$var, &mut $tokens);
+    };
+    ($tokens:ident $b3:tt $b2:tt # ($var:ident) $a1:tt $a2:tt $a3:tt) => {};
+
+    // Anything else.
+    ($tokens:ident $b3:tt $b2:tt $b1:tt ($curr:tt) $a1:tt $a2:tt $a3:tt) => {
+        $crate::__quote_token!{$tokens $curr}
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __quote_token {
+    ($tokens:ident ( $($inner:tt)* )) => {
+        $crate::__private::push_group(
+            &mut $tokens,
+            $crate::Delimiter::Parenthesis,
+            $crate::quote!($($inner)*),
+        );
+    };
+    ($tokens:ident [ $($inner:tt)* ]) => {
+        $crate::__private::push_group(
+            &mut $tokens,
+            $crate::Delimiter::Bracket,
+            $crate::quote!($($inner)*),
+        );
+    };
+    ($tokens:ident { $($inner:tt)* }) => {
+        $crate::__private::push_group(
+            &mut $tokens,
+            $crate::Delimiter::Brace,
+            $crate::quote!($($inner)*),
+        );
+    };
+    ($tokens:ident $other:tt) => {
+        $crate::__private::parse(&mut $tokens, stringify!($other));
+    };
+}
+
+/// Invokes `$call` with each variable interpolated in the input, including
+/// inside groups and nested repetitions.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __quote_pounded_vars {
+    ($call:ident! $extra:tt $($tts:tt)*) => {
+        $crate::__quote_pounded_vars_with_context!{$call! $extra
+            (@ $($tts)*)
+            ($($tts)* @)
+        }
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __quote_pounded_vars_with_context {
+    ($call:ident! $extra:tt ($($b1:tt)*) ($($curr:tt)*)) => {
+        $(
+            $crate::__quote_pounded_var_with_context!{$call! $extra $b1 $curr}
+        )*
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __quote_pounded_var_with_context {
+    ($call:ident! $extra:tt $b1:tt ( $($inner:tt)* )) => {
+        $crate::__quote_pounded_vars!{$call! $extra $($inner)*}
+    };
+    ($call:ident! $extra:tt $b1:tt [ $($inner:tt)* ]) => {
+        $crate::__quote_pounded_vars!{$call! $extra $($inner)*}
+    };
+    ($call:ident! $extra:tt $b1:tt { $($inner:tt)* }) => {
+        $crate::__quote_pounded_vars!{$call! $extra $($inner)*}
+    };
+    ($call:ident! ($($extra:tt)*) # $var:ident) => {
+        $crate::$call!{$($extra)* $var}
+    };
+    ($call:ident! $extra:tt $b1:tt $curr:tt) => {};
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __quote_bind_into_iter {
+    ($has_iter:ident $var:ident) => {
+        #[allow(unused_mut)]
+        let (mut $var, i) = $var.quote_into_iter();
+        let $has_iter = $has_iter | i;
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __quote_bind_next_or_break {
+    ($var:ident) => {
+        let $var = match $var.next() {
+            Some(x) => $crate::__private::RepInterp(x),
+            None => break,
+        };
+    };
+}
+
+pub fn push_group(tokens: &mut TokenStream, delimiter: Delimiter, inner: TokenStream) {
+    tokens.extend(iter::once(TokenTree::Group(Group::new(delimiter, inner))));
+}
+
+/// Appends the tokens of `src`, one token stringified by `quote!`.
+///
+/// # Panics
+///
+/// Panics if `src` does not lex, like `quote!`.
+pub fn parse(tokens: &mut TokenStream, src: &str) {
+    match imp::TokenStream::parse_unrecorded(src) {
+        Some(stream) => tokens.extend(iter::once(TokenStream::_new(stream))),
+        None => panic!("quote! could not lex {:?}", src),
+    }
+}
+
+/// Marks a repetition which interpolates at least one iterator.
+#[derive(Copy, Clone)]
+pub struct HasIterator;
+
+/// Marks a repetition which so far interpolates no iterators. Repeating it
+/// would never end, so this is a type error.
+#[derive(Copy, Clone)]
+pub struct ThereIsNoIteratorInRepetition;
+
+impl BitOr<ThereIsNoIteratorInRepetition> for ThereIsNoIteratorInRepetition {
+    type Output = ThereIsNoIteratorInRepetition;
+
+    fn bitor(self, _rhs: ThereIsNoIteratorInRepetition) -> Self::Output {
+        ThereIsNoIteratorInRepetition
+    }
+}
+
+impl BitOr<HasIterator> for ThereIsNoIteratorInRepetition {
+    type Output = HasIterator;
+
+    fn bitor(self, _rhs: HasIterator) -> Self::Output {
+        HasIterator
+    }
+}
+
+impl BitOr<ThereIsNoIteratorInRepetition> for HasIterator {
+    type Output = HasIterator;
+
+    fn bitor(self, _rhs: ThereIsNoIteratorInRepetition) -> Self::Output {
+        HasIterator
+    }
+}
+
+impl BitOr<HasIterator> for HasIterator {
+    type Output = HasIterator;
+
+    fn bitor(self, _rhs: HasIterator) -> Self::Output {
+        HasIterator
+    }
+}
+
+/// One element of a repetition. Interpolating the same variable twice in a
+/// repetition steps it twice, so `next` on an element returns the element.
+pub struct RepInterp<T>(pub T);
+
+impl<T> RepInterp<T> {
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(self) -> Option<T> {
+        Some(self.0)
+    }
+}
+
+impl<T: ToTokens> ToTokens for RepInterp<T> {
+    fn to_tokens(&self, tokens: &mut TokenStream) {
+        self.0.to_tokens(tokens);
+    }
+}
+
+/// Traits turning the variables in a repetition into iterators. Iterators are
+/// taken by value and collections by reference, through autoref method
+/// resolution.
+pub mod ext {
+    use super::{BTreeSet, HasIterator, RepInterp};
+    use std::{collections::btree_set, option, slice};
+
+    pub trait RepIteratorExt: Iterator + Sized {
+        fn quote_into_iter(self) -> (Self, HasIterator) {
+            (self, HasIterator)
+        }
+    }
+
+    impl<T: Iterator> RepIteratorExt for T {}
+
+    pub trait RepAsIteratorExt<'q> {
+        type Iter: Iterator;
+
+        fn quote_into_iter(&'q self) -> (Self::Iter, HasIterator);
+    }
+
+    impl<'q, T: RepAsIteratorExt<'q> + ?Sized> RepAsIteratorExt<'q> for &T {
+        type Iter = T::Iter;
+
+        fn quote_into_iter(&'q self) -> (Self::Iter, HasIterator) {
+            <T as RepAsIteratorExt>::quote_into_iter(*self)
+        }
+    }
+
+    impl<'q, T: RepAsIteratorExt<'q> + ?Sized> RepAsIteratorExt<'q> for &mut T {
+        type Iter = T::Iter;
+
+        fn quote_into_iter(&'q self) -> (Self::Iter, HasIterator) {
+            <T as RepAsIteratorExt>::quote_into_iter(*self)
+        }
+    }
+
+    impl<'q, T: 'q> RepAsIteratorExt<'q> for [T] {
+        type Iter = slice::Iter<'q, T>;
+
+        fn quote_into_iter(&'q self) -> (Self::Iter, HasIterator) {
+            (self.iter(), HasIterator)
+        }
+    }
+
+    impl<'q, T: 'q> RepAsIteratorExt<'q> for Vec<T> {
+        type Iter = slice::Iter<'q, T>;
+
+        fn quote_into_iter(&'q self) -> (Self::Iter, HasIterator) {
+            (self.iter(), HasIterator)
+        }
+    }
+
+    impl<'q, T: 'q> RepAsIteratorExt<'q> for Option<T> {
+        type Iter = option::Iter<'q, T>;
+
+        fn quote_into_iter(&'q self) -> (Self::Iter, HasIterator) {
+            (self.iter(), HasIterator)
+        }
+    }
+
+    impl<'q, T: 'q> RepAsIteratorExt<'q> for BTreeSet<T> {
+        type Iter = btree_set::Iter<'q, T>;
+
+        fn quote_into_iter(&'q self) -> (Self::Iter, HasIterator) {
+            (self.iter(), HasIterator)
+        }
+    }
+
+    impl<'q, T: RepAsIteratorExt<'q>> RepAsIteratorExt<'q> for RepInterp<T> {
+        type Iter = T::Iter;
+
+        fn quote_into_iter(&'q self) -> (Self::Iter, HasIterator) {
+            self.0.quote_into_iter()
+        }
+    }
+}
+
+impl<T: ?Sized + ToTokens> ToTokens for &T {
+    fn to_tokens(&self, tokens: &mut TokenStream) {
+        (**self).to_tokens(tokens);
+    }
+}
+
+impl<T: ?Sized + ToTokens> ToTokens for &mut T {
+    fn to_tokens(&self, tokens: &mut TokenStream) {
+        (**self).to_tokens(tokens);
+    }
+}
+
+impl<T: ?Sized + ToTokens> ToTokens for Box<T> {
+    fn to_tokens(&self, tokens: &mut TokenStream) {
+        (**self).to_tokens(tokens);
+    }
+}
+
+impl<T: ToTokens> ToTokens for Option<T> {
+    fn to_tokens(&self, tokens: &mut TokenStream) {
+        if let Some(t) = self {
+            t.to_tokens(tokens);
+        }
+    }
+}
+
+impl<T: ToTokens> ToTokens for Vec<T> {
+    fn to_tokens(&self, tokens: &mut TokenStream) {
+        for t in self {
+            t.to_tokens(tokens);
+        }
+    }
+}
+
+impl ToTokens for TokenStream {
+    fn to_tokens(&self, tokens: &mut TokenStream) {
+        tokens.extend(iter::once(self.clone()));
+    }
+
+    fn into_token_stream(self) -> TokenStream {
+        self
+    }
+}
+
+macro_rules! tree_to_tokens {
+    ($($ty:ident)*) => {$(
+        impl ToTokens for $ty {
+            fn to_tokens(&self, tokens: &mut TokenStream) {
+                tokens.extend(iter::once(TokenTree::from(self.clone())));
+            }
+        }
+    )*};
+}
+
+tree_to_tokens!(TokenTree Group Ident Punct Literal);
+
+impl ToTokens for DocComment {
+    fn to_tokens(&self, tokens: &mut TokenStream) {
+        tokens.extend(TokenStream::from(self.clone()));
+    }
+}
+
+impl ToTokens for str {
+    fn to_tokens(&self, tokens: &mut TokenStream) {
+        Literal::string(self).to_tokens(tokens);
+    }
+}
+
+impl ToTokens for String {
+    fn to_tokens(&self, tokens: &mut TokenStream) {
+        self.as_str().to_tokens(tokens);
+    }
+}
+
+impl ToTokens for char {
+    fn to_tokens(&self, tokens: &mut TokenStream) {
+        Literal::character(*self).to_tokens(tokens);
+    }
+}
+
+impl ToTokens for bool {
+    fn to_tokens(&self, tokens: &mut TokenStream) {
+        let word = if *self { "true" } else { "false" };
+        Ident::new(word, Span::call_site()).to_tokens(tokens);
+    }
+}
+
+macro_rules! primitive_to_tokens {
+    ($($ty:ident => $name:ident,)*) => {$(
+        impl ToTokens for $ty {
+            fn to_tokens(&self, tokens: &mut TokenStream) {
+                Literal::$name(*self).to_tokens(tokens);
+            }
+        }
+    )*};
+}
+
+primitive_to_tokens! {
+    i8 => i8_suffixed,
+    i16 => i16_suffixed,
+    i32 => i32_suffixed,
+    i64 => i64_suffixed,
+    i128 => i128_suffixed,
+    isize => isize_suffixed,
+    u8 => u8_suffixed,
+    u16 => u16_suffixed,
+    u32 => u32_suffixed,
+    u64 => u64_suffixed,
+    u128 => u128_suffixed,
+    usize => usize_suffixed,
+    f32 => f32_suffixed,
+    f64 => f64_suffixed,
+}
@@ -0,0 +1,265 @@
+//! Token patterns, for `TokenStream::find_all`.
+//!
+//! A pattern is compiled into a tree of nodes mirroring its groups. Matching
+//! is a backtracking search over the token trees of one stream at a time,
+//! looking through `Delimiter::None` groups like `PartialEq` does, where each
+//! sequence wildcard first tries to match as few tokens as possible. The
+//! search remembers where the rest of the pattern is known not to match, so
+//! that backtracking never tries the same node at the same token twice.
+
+use crate::fallback::{self, Transparent};
+use crate::{Cursor, Delimiter, Match, ParseError, Punct, Spacing, TokenStream, TokenTree};
+use std::collections::HashSet;
+
+#[derive(Clone, Debug)]
+pub(crate) enum Node {
+    /// A token which must be equal to the one it matches.
+    Token(TokenTree),
+    /// A group with the given delimiter, whose whole contents must match.
+    Group(Delimiter, Vec<Node>),
+    /// `$_` or `$name`.
+    One(Option<String>),
+    /// `$..` or `$..name`.
+    Many(Option<String>),
+}
+
+/// Compiles the pattern at `input`, up to the end of the group it is in.
+pub(crate) fn compile(input: &mut Cursor) -> Result<Vec<Node>, ParseError> {
+    let mut names = Vec::new();
+    nodes(input, &mut names)
+}
+
+fn nodes(input: &mut Cursor, names: &mut Vec<String>) -> Result<Vec<Node>, ParseError> {
+    let mut nodes = Vec::new();
+    while let Some(tt) = input.peek() {
+        match tt {
+            TokenTree::Punct(punct) if punct.as_char() == '$' => {
+                input.advance();
+                nodes.push(variable(input, names)?);
+            }
+            TokenTree::Group(group) => {
+                input.enter();
+                let inner = self::nodes(input, names)?;
+                input.exit();
+                nodes.push(Node::Group(group.delimiter(), inner));
+            }
+            tt => {
+                input.advance();
+                let tt = match (tt, input.peek()) {
+                    // Joint only because a `$` follows, as in `!$args`.
+                    (TokenTree::Punct(punct), Some(TokenTree::Punct(next)))
+                        if next.as_char() == '$' =>
+                    {
+                        TokenTree::Punct(Punct::new(punct.as_char(), Spacing::Alone))
+                    }
+                    (tt, _) => tt.clone(),
+                };
+                nodes.push(Node::Token(tt));
+            }
+        }
+    }
+    Ok(nodes)
+}
+
+/// Compiles what follows a `$`.
+fn variable(input: &mut Cursor, names: &mut Vec<String>) -> Result<Node, ParseError> {
+    if input.punct('$').is_some() {
+        let dollar = Punct::new('$', Spacing::Alone);
+        return Ok(Node::Token(TokenTree::Punct(dollar)));
+    }
+
+    let mut fork = input.clone();
+    let many = match (fork.punct('.'), fork.punct('.')) {
+        (Some(first), Some(_)) if first.spacing() == Spacing::Joint => {
+            *input = fork;
+            true
+        }
+        _ => false,
+    };
+
+    let name = match input.ident() {
+        Some(ident) if ident == "_" => None,
+        Some(ident) => {
+            let name = ident.to_string();
+            if names.contains(&name) {
+                let message = format!("`${name}` is captured more than once");
+                return Err(ParseError::new(ident.span(), message));
+            }
+            names.push(name.clone());
+            Some(name)
+        }
+        None if many => None,
+        None => return Err(input.error("expected `$$`, `$..`, `$_` or `$name`")),
+    };
+
+    Ok(if many {
+        Node::Many(name)
+    } else {
+        Node::One(name)
+    })
+}
+
+pub(crate) fn find_all(nodes: &[Node], tokens: &[TokenTree]) -> Vec<Match> {
+    let mut matches = Vec::new();
+    search(nodes, tokens, &mut Vec::new(), &mut matches);
+    matches
+}
+
+/// Finds the matches starting in `tokens` or in any group inside it, in order
+/// of where they start. Matches in the same stream do not overlap.
+fn search(nodes: &[Node], tokens: &[TokenTree], path: &mut Vec<usize>, out: &mut Vec<Match>) {
+    let tokens: Vec<&TokenTree> = Transparent::new(tokens).collect();
+    let mut matcher = Matcher::new(nodes, &tokens, false);
+    let mut next = 0;
+    for (i, tt) in tokens.iter().enumerate() {
+        if i >= next {
+            let mut captures = Vec::new();
+            match matcher.at(0, i, &mut captures) {
+                Some(end) if end > i => {
+                    path.push(i);
+                    out.push(new_match(&tokens[i..end], path, captures));
+                    path.pop();
+                    next = end;
+                }
+                _ => {}
+            }
+        }
+        if let TokenTree::Group(group) = tt {
+            path.push(i);
+            search(nodes, group.inner.stream_tokens(), path, out);
+            path.pop();
+        }
+    }
+}
+
+fn new_match(tokens: &[&TokenTree], path: &[usize], captures: Captures) -> Match {
+    let first = tokens[0].span();
+    let last = tokens[tokens.len() - 1].span();
+    Match {
+        tokens: stream(tokens),
+        span: first.join(last).unwrap_or(first),
+        path: path.to_vec(),
+        captures: captures
+            .into_iter()
+            .map(|(name, tokens)| (name.to_owned(), stream(&tokens)))
+            .collect(),
+    }
+}
+
+fn stream(tokens: &[&TokenTree]) -> TokenStream {
+    tokens.iter().map(|&tt| tt.clone()).collect()
+}
+
+/// The tokens captured by each named wildcard matched so far.
+type Captures<'n, 'a> = Vec<(&'n str, Vec<&'a TokenTree>)>;
+
+/// Matches a sequence of nodes against a sequence of tokens, at any
+/// position in either.
+struct Matcher<'n, 't, 'a> {
+    nodes: &'n [Node],
+    tokens: &'t [&'a TokenTree],
+    /// Whether matches must reach the end of `tokens`.
+    anchored: bool,
+    /// The positions `(k, j)` where `nodes[k..]` is known not to match at
+    /// `tokens[j..]`. Only positions that were tried are stored, which for
+    /// most patterns is far fewer than all of them.
+    failed: HashSet<(usize, usize)>,
+    /// For each `Node::Many`, the first token it is known not to match at.
+    /// It cannot match at any later token either, since it could only match
+    /// fewer tokens there.
+    many_failed: Vec<usize>,
+}
+
+impl<'n, 't, 'a> Matcher<'n, 't, 'a> {
+    fn new(nodes: &'n [Node], tokens: &'t [&'a TokenTree], anchored: bool) -> Self {
+        Matcher {
+            nodes,
+            tokens,
+            anchored,
+            failed: HashSet::new(),
+            many_failed: vec![usize::MAX; nodes.len()],
+        }
+    }
+
+    /// Matches `nodes[k..]` against `tokens[j..]`, returning the end of the
+    /// tokens matched.
+    fn at(&mut self, k: usize, j: usize, captures: &mut Captures<'n, 'a>) -> Option<usize> {
+        if self.failed.contains(&(k, j)) {
+            return None;
+        }
+        let end = self.match_node(k, j, captures);
+        if end.is_none() {
+            self.failed.insert((k, j));
+        }
+        end
+    }
+
+    fn match_node(&mut self, k: usize, j: usize, captures: &mut Captures<'n, 'a>) -> Option<usize> {
+        let node = match self.nodes.get(k) {
+            Some(node) => node,
+            None if self.anchored && j < self.tokens.len() => return None,
+            None => return Some(j),
+        };
+
+        if let Node::Many(name) = node {
+            // From where it failed before, the rest of the pattern is known
+            // not to match anywhere.
+            let stop = self.many_failed[k].min(self.tokens.len() + 1);
+            for end in j..stop {
+                let saved = captures.len();
+                if let Some(name) = name {
+                    captures.push((name, self.tokens[j..end].to_vec()));
+                }
+                if let Some(end) = self.at(k + 1, end, captures) {
+                    return Some(end);
+                }
+                captures.truncate(saved);
+            }
+            self.many_failed[k] = self.many_failed[k].min(j);
+            return None;
+        }
+
+        let token = *self.tokens.get(j)?;
+        let saved = captures.len();
+        let matched = match node {
+            Node::Token(expected) => token_matches(expected, token),
+            Node::Group(delimiter, inner) => match token {
+                TokenTree::Group(group) if group.delimiter() == *delimiter => {
+                    let contents: Vec<&TokenTree> =
+                        Transparent::new(group.inner.stream_tokens()).collect();
+                    Matcher::new(inner, &contents, true)
+                        .at(0, 0, captures)
+                        .is_some()
+                }
+                _ => false,
+            },
+            Node::One(name) => {
+                if let Some(name) = name {
+                    captures.push((name, vec![token]));
+                }
+                true
+            }
+            Node::Many(_) => unreachable!(),
+        };
+        if matched {
+            if let Some(end) = self.at(k + 1, j + 1, captures) {
+                return Some(end);
+            }
+        }
+        captures.truncate(saved);
+        None
+    }
+}
+
+/// Whether a token in a pattern matches a token in the stream. Punctuation
+/// which is alone in the pattern also matches joint punctuation, so that a
+/// pattern ending in `<` matches the `<` of `<-`.
+fn token_matches(expected: &TokenTree, token: &TokenTree) -> bool {
+    match (expected, token) {
+        (TokenTree::Punct(expected), TokenTree::Punct(token)) => {
+            expected.as_char() == token.as_char()
+                && (expected.spacing() == Spacing::Alone || token.spacing() == Spacing::Joint)
+        }
+        _ => fallback::token_eq(expected, token),
+    }
+}
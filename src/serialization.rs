@@ -0,0 +1,439 @@
+//! Serde implementations for token streams, behind the `serde` feature.
+//!
+//! Tokens serialize through the private `*Repr` types below, which mirror the
+//! public ones. Whether spans are written is decided by `SerializeOptions`,
+//! which `Serializable` installs in a thread local for the duration of the
+//! call, so that it also applies to token streams nested inside other
+//! serializable values.
+
+use crate::{
+    imp, AttrStyle, CommentKind, Delimiter, DocComment, Group, Ident, Literal, Punct, Serializable,
+    Spacing, Span, TokenStream, TokenTree,
+};
+use serde::de::{Deserializer, Error};
+use serde::ser::Serializer;
+use serde::{Deserialize, Serialize};
+use std::cell::Cell;
+use std::iter::FromIterator;
+use std::sync::Arc;
+
+thread_local! {
+    static SPANS: Cell<bool> = const { Cell::new(false) };
+}
+
+impl<T: Serialize + ?Sized> Serialize for Serializable<'_, T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        struct Restore(bool);
+
+        impl Drop for Restore {
+            fn drop(&mut self) {
+                SPANS.with(|spans| spans.set(self.0));
+            }
+        }
+
+        let _restore = Restore(SPANS.with(|spans| spans.replace(self.options.spans)));
+        self.value.serialize(serializer)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "Delimiter")]
+enum DelimiterRepr {
+    Parenthesis,
+    Brace,
+    Bracket,
+    None,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "Spacing")]
+enum SpacingRepr {
+    Alone,
+    Joint,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "AttrStyle")]
+enum AttrStyleRepr {
+    Outer,
+    Inner,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "CommentKind")]
+enum CommentKindRepr {
+    Line,
+    Block,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename = "Span")]
+struct SpanRepr {
+    file: String,
+    start: LineColumnRepr,
+    end: LineColumnRepr,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename = "LineColumn")]
+struct LineColumnRepr {
+    line: usize,
+    column: usize,
+}
+
+impl SpanRepr {
+    fn new(span: Span) -> Option<Self> {
+        if !SPANS.with(Cell::get) {
+            return None;
+        }
+        let start = span.start();
+        let end = span.end();
+        Some(SpanRepr {
+            file: span.source_file().path().to_string_lossy().into_owned(),
+            start: LineColumnRepr {
+                line: start.line,
+                column: start.column,
+            },
+            end: LineColumnRepr {
+                line: end.line,
+                column: end.column,
+            },
+        })
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename = "TokenTree")]
+enum TokenTreeRepr<'a> {
+    Group(GroupRepr<'a>),
+    Ident(IdentRepr),
+    Punct(PunctRepr),
+    Literal(LiteralRepr),
+}
+
+#[derive(Serialize)]
+#[serde(rename = "Group")]
+struct GroupRepr<'a> {
+    #[serde(with = "DelimiterRepr")]
+    delimiter: Delimiter,
+    stream: StreamRepr<'a>,
+    span: Option<SpanRepr>,
+    /// The doc comment that a `[doc = "..."]` group prints as.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    doc: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+#[serde(rename = "Ident")]
+struct IdentRepr {
+    name: String,
+    span: Option<SpanRepr>,
+}
+
+#[derive(Serialize)]
+#[serde(rename = "Punct")]
+struct PunctRepr {
+    ch: char,
+    #[serde(with = "SpacingRepr")]
+    spacing: Spacing,
+    span: Option<SpanRepr>,
+}
+
+#[derive(Serialize)]
+#[serde(rename = "Literal")]
+struct LiteralRepr {
+    repr: String,
+    span: Option<SpanRepr>,
+}
+
+#[derive(Serialize)]
+#[serde(rename = "DocComment")]
+struct DocCommentRepr<'a> {
+    #[serde(with = "AttrStyleRepr")]
+    style: AttrStyle,
+    #[serde(with = "CommentKindRepr")]
+    kind: CommentKind,
+    text: &'a str,
+    span: Option<SpanRepr>,
+}
+
+struct StreamRepr<'a>(&'a [TokenTree]);
+
+impl Serialize for StreamRepr<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.0)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename = "TokenTree")]
+enum TokenTreeDe {
+    Group(GroupDe),
+    Ident(IdentDe),
+    Punct(PunctDe),
+    Literal(LiteralDe),
+}
+
+#[derive(Deserialize)]
+#[serde(rename = "Group")]
+struct GroupDe {
+    #[serde(with = "DelimiterRepr")]
+    delimiter: Delimiter,
+    stream: TokenStream,
+    #[allow(dead_code)]
+    span: Option<SpanRepr>,
+    #[serde(default)]
+    doc: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename = "Ident")]
+struct IdentDe {
+    name: String,
+    #[allow(dead_code)]
+    span: Option<SpanRepr>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename = "Punct")]
+struct PunctDe {
+    ch: char,
+    #[serde(with = "SpacingRepr")]
+    spacing: Spacing,
+    #[allow(dead_code)]
+    span: Option<SpanRepr>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename = "Literal")]
+struct LiteralDe {
+    repr: String,
+    #[allow(dead_code)]
+    span: Option<SpanRepr>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename = "DocComment")]
+struct DocCommentDe {
+    #[serde(with = "AttrStyleRepr")]
+    style: AttrStyle,
+    #[serde(with = "CommentKindRepr")]
+    kind: CommentKind,
+    text: String,
+    #[allow(dead_code)]
+    span: Option<SpanRepr>,
+}
+
+impl Serialize for TokenStream {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        StreamRepr(&self.inner.inner).serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for TokenStream {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Vec::<TokenTree>::deserialize(deserializer).map(TokenStream::from_iter)
+    }
+}
+
+impl Serialize for TokenTree {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let repr = match self {
+            TokenTree::Group(t) => TokenTreeRepr::Group(GroupRepr::new(t)),
+            TokenTree::Ident(t) => TokenTreeRepr::Ident(IdentRepr::new(t)),
+            TokenTree::Punct(t) => TokenTreeRepr::Punct(PunctRepr::new(t)),
+            TokenTree::Literal(t) => TokenTreeRepr::Literal(LiteralRepr::new(t)),
+        };
+        repr.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for TokenTree {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(match TokenTreeDe::deserialize(deserializer)? {
+            TokenTreeDe::Group(t) => TokenTree::Group(t.into_group()),
+            TokenTreeDe::Ident(t) => TokenTree::Ident(t.into_ident()?),
+            TokenTreeDe::Punct(t) => TokenTree::Punct(t.into_punct()?),
+            TokenTreeDe::Literal(t) => TokenTree::Literal(t.into_literal()?),
+        })
+    }
+}
+
+impl<'a> GroupRepr<'a> {
+    fn new(group: &'a Group) -> Self {
+        GroupRepr {
+            delimiter: group.delimiter(),
+            stream: StreamRepr(group.inner.stream_tokens()),
+            span: SpanRepr::new(group.span()),
+            doc: group
+                .inner
+                .doc_comment()
+                .map(|(source, _text, _inner)| source),
+        }
+    }
+}
+
+impl GroupDe {
+    fn into_group(self) -> Group {
+        let mut group = Group::new(self.delimiter, self.stream);
+        group.inner.doc = self.doc.map(Arc::from);
+        group
+    }
+}
+
+impl Serialize for Group {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        GroupRepr::new(self).serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Group {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        GroupDe::deserialize(deserializer).map(GroupDe::into_group)
+    }
+}
+
+impl IdentRepr {
+    fn new(ident: &Ident) -> Self {
+        IdentRepr {
+            name: ident.to_string(),
+            span: SpanRepr::new(ident.span()),
+        }
+    }
+}
+
+impl IdentDe {
+    fn into_ident<E: Error>(self) -> Result<Ident, E> {
+        let span = Span::call_site();
+        match self.name.strip_prefix("r#") {
+            Some(name) => Ident::try_new_raw(name, span),
+            None => Ident::try_new(&self.name, span),
+        }
+        .map_err(E::custom)
+    }
+}
+
+impl Serialize for Ident {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        IdentRepr::new(self).serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Ident {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        IdentDe::deserialize(deserializer)?.into_ident()
+    }
+}
+
+impl PunctRepr {
+    fn new(punct: &Punct) -> Self {
+        PunctRepr {
+            ch: punct.as_char(),
+            spacing: punct.spacing(),
+            span: SpanRepr::new(punct.span()),
+        }
+    }
+}
+
+impl PunctDe {
+    fn into_punct<E: Error>(self) -> Result<Punct, E> {
+        Punct::try_new(self.ch, self.spacing).map_err(E::custom)
+    }
+}
+
+impl Serialize for Punct {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        PunctRepr::new(self).serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Punct {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        PunctDe::deserialize(deserializer)?.into_punct()
+    }
+}
+
+impl LiteralRepr {
+    fn new(literal: &Literal) -> Self {
+        LiteralRepr {
+            repr: literal.to_string(),
+            span: SpanRepr::new(literal.span()),
+        }
+    }
+}
+
+impl LiteralDe {
+    fn into_literal<E: Error>(self) -> Result<Literal, E> {
+        // Not `Literal::from_str`, which would record every literal as a file
+        // in the global source map.
+        match imp::Literal::parse_unrecorded(&self.repr) {
+            Some(literal) => Ok(Literal::_new(literal)),
+            None => Err(E::custom(format_args!("invalid literal {:?}", self.repr))),
+        }
+    }
+}
+
+impl Serialize for Literal {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        LiteralRepr::new(self).serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Literal {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        LiteralDe::deserialize(deserializer)?.into_literal()
+    }
+}
+
+impl<'a> DocCommentRepr<'a> {
+    fn new(comment: &'a DocComment) -> Self {
+        DocCommentRepr {
+            style: comment.style(),
+            kind: comment.kind(),
+            text: comment.text(),
+            span: SpanRepr::new(comment.span()),
+        }
+    }
+}
+
+impl DocCommentDe {
+    fn into_doc_comment<E: Error>(self) -> Result<DocComment, E> {
+        DocComment::try_new(self.style, self.kind, &self.text).map_err(E::custom)
+    }
+}
+
+impl Serialize for DocComment {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        DocCommentRepr::new(self).serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for DocComment {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        DocCommentDe::deserialize(deserializer)?.into_doc_comment()
+    }
+}
+
+impl Serialize for Delimiter {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        DelimiterRepr::serialize(self, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Delimiter {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        DelimiterRepr::deserialize(deserializer)
+    }
+}
+
+impl Serialize for Spacing {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        SpacingRepr::serialize(self, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Spacing {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        SpacingRepr::deserialize(deserializer)
+    }
+}
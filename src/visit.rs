@@ -0,0 +1,248 @@
+//! Traits for walking token streams and rewriting them in place.
+//!
+//! `Visit` walks a stream by reference, `VisitMut` walks it by mutable
+//! reference and `Fold` walks it by value, each descending into groups and
+//! calling a method for every token tree, token and span on the way. Every
+//! method has a default implementation which just continues the walk, and
+//! which is also available as a free function of the same name in this module
+//! so that an overriding method can still continue the walk.
+//!
+//! Groups are walked without cloning their streams, through
+//! `Group::stream_mut` and `Group::map_stream`, and keep their delimiter,
+//! spans and trivia unless a method changes them.
+//!
+//! ```
+//! use proc_macro0::visit::{self, VisitMut};
+//! use proc_macro0::{Ident, TokenStream};
+//!
+//! // Renames every `x` to `x_1`.
+//! struct Rename;
+//!
+//! impl VisitMut for Rename {
+//!     fn visit_ident_mut(&mut self, ident: &mut Ident) {
+//!         if ident == "x" {
+//!             *ident = Ident::new("x_1", ident.span());
+//!         }
+//!     }
+//! }
+//!
+//! let mut tokens: TokenStream = "let x = { f(x) };".parse().unwrap();
+//! Rename.visit_token_stream_mut(&mut tokens);
+//! assert_eq!(tokens.to_string(), "let x_1 = { f (x_1) } ;");
+//! ```
+
+use crate::{Group, Ident, Literal, Punct, Span, TokenStream, TokenTree};
+use std::mem;
+
+/// Walks a token stream by reference.
+pub trait Visit {
+    fn visit_token_stream(&mut self, stream: &TokenStream) {
+        visit_token_stream(self, stream);
+    }
+
+    fn visit_token_tree(&mut self, tt: &TokenTree) {
+        visit_token_tree(self, tt);
+    }
+
+    fn visit_group(&mut self, group: &Group) {
+        visit_group(self, group);
+    }
+
+    fn visit_ident(&mut self, ident: &Ident) {
+        visit_ident(self, ident);
+    }
+
+    fn visit_punct(&mut self, punct: &Punct) {
+        visit_punct(self, punct);
+    }
+
+    fn visit_literal(&mut self, literal: &Literal) {
+        visit_literal(self, literal);
+    }
+
+    /// Called by the default methods with the span of every token tree. For a
+    /// group this is the span of its delimiters, visited before its contents.
+    fn visit_span(&mut self, span: Span) {
+        let _ = span;
+    }
+}
+
+pub fn visit_token_stream<V: Visit + ?Sized>(v: &mut V, stream: &TokenStream) {
+    for tt in &stream.inner.inner {
+        v.visit_token_tree(tt);
+    }
+}
+
+pub fn visit_token_tree<V: Visit + ?Sized>(v: &mut V, tt: &TokenTree) {
+    match tt {
+        TokenTree::Group(t) => v.visit_group(t),
+        TokenTree::Ident(t) => v.visit_ident(t),
+        TokenTree::Punct(t) => v.visit_punct(t),
+        TokenTree::Literal(t) => v.visit_literal(t),
+    }
+}
+
+pub fn visit_group<V: Visit + ?Sized>(v: &mut V, group: &Group) {
+    v.visit_span(group.span());
+    v.visit_token_stream(&group.inner.stream);
+}
+
+pub fn visit_ident<V: Visit + ?Sized>(v: &mut V, ident: &Ident) {
+    v.visit_span(ident.span());
+}
+
+pub fn visit_punct<V: Visit + ?Sized>(v: &mut V, punct: &Punct) {
+    v.visit_span(punct.span());
+}
+
+pub fn visit_literal<V: Visit + ?Sized>(v: &mut V, literal: &Literal) {
+    v.visit_span(literal.span());
+}
+
+/// Walks a token stream by mutable reference, rewriting it in place.
+pub trait VisitMut {
+    fn visit_token_stream_mut(&mut self, stream: &mut TokenStream) {
+        visit_token_stream_mut(self, stream);
+    }
+
+    fn visit_token_tree_mut(&mut self, tt: &mut TokenTree) {
+        visit_token_tree_mut(self, tt);
+    }
+
+    fn visit_group_mut(&mut self, group: &mut Group) {
+        visit_group_mut(self, group);
+    }
+
+    fn visit_ident_mut(&mut self, ident: &mut Ident) {
+        visit_ident_mut(self, ident);
+    }
+
+    fn visit_punct_mut(&mut self, punct: &mut Punct) {
+        visit_punct_mut(self, punct);
+    }
+
+    fn visit_literal_mut(&mut self, literal: &mut Literal) {
+        visit_literal_mut(self, literal);
+    }
+
+    /// Called with the span of every token tree, like `Visit::visit_span`.
+    /// Changing the span of a group moves both of its delimiters.
+    fn visit_span_mut(&mut self, span: &mut Span) {
+        let _ = span;
+    }
+}
+
+pub fn visit_token_stream_mut<V: VisitMut + ?Sized>(v: &mut V, stream: &mut TokenStream) {
+    for tt in &mut stream.inner.inner {
+        v.visit_token_tree_mut(tt);
+    }
+}
+
+pub fn visit_token_tree_mut<V: VisitMut + ?Sized>(v: &mut V, tt: &mut TokenTree) {
+    match tt {
+        TokenTree::Group(t) => v.visit_group_mut(t),
+        TokenTree::Ident(t) => v.visit_ident_mut(t),
+        TokenTree::Punct(t) => v.visit_punct_mut(t),
+        TokenTree::Literal(t) => v.visit_literal_mut(t),
+    }
+}
+
+pub fn visit_group_mut<V: VisitMut + ?Sized>(v: &mut V, group: &mut Group) {
+    let mut span = group.span();
+    v.visit_span_mut(&mut span);
+    group.set_span(span);
+    v.visit_token_stream_mut(group.stream_mut());
+}
+
+pub fn visit_ident_mut<V: VisitMut + ?Sized>(v: &mut V, ident: &mut Ident) {
+    let mut span = ident.span();
+    v.visit_span_mut(&mut span);
+    ident.set_span(span);
+}
+
+pub fn visit_punct_mut<V: VisitMut + ?Sized>(v: &mut V, punct: &mut Punct) {
+    let mut span = punct.span();
+    v.visit_span_mut(&mut span);
+    punct.set_span(span);
+}
+
+pub fn visit_literal_mut<V: VisitMut + ?Sized>(v: &mut V, literal: &mut Literal) {
+    let mut span = literal.span();
+    v.visit_span_mut(&mut span);
+    literal.set_span(span);
+}
+
+/// Walks a token stream by value, building a rewritten one.
+///
+/// The default `fold_token_stream` folds each token tree into exactly one
+/// other; override it to add or remove token trees.
+pub trait Fold {
+    fn fold_token_stream(&mut self, stream: TokenStream) -> TokenStream {
+        fold_token_stream(self, stream)
+    }
+
+    fn fold_token_tree(&mut self, tt: TokenTree) -> TokenTree {
+        fold_token_tree(self, tt)
+    }
+
+    fn fold_group(&mut self, group: Group) -> Group {
+        fold_group(self, group)
+    }
+
+    fn fold_ident(&mut self, ident: Ident) -> Ident {
+        fold_ident(self, ident)
+    }
+
+    fn fold_punct(&mut self, punct: Punct) -> Punct {
+        fold_punct(self, punct)
+    }
+
+    fn fold_literal(&mut self, literal: Literal) -> Literal {
+        fold_literal(self, literal)
+    }
+
+    /// Called with the span of every token tree, like `Visit::visit_span`.
+    fn fold_span(&mut self, span: Span) -> Span {
+        span
+    }
+}
+
+/// Folds each token tree of `stream`, keeping its trailing trivia.
+pub fn fold_token_stream<F: Fold + ?Sized>(f: &mut F, mut stream: TokenStream) -> TokenStream {
+    let tokens = mem::take(&mut stream.inner.inner);
+    stream.inner.inner = tokens.into_iter().map(|tt| f.fold_token_tree(tt)).collect();
+    stream
+}
+
+pub fn fold_token_tree<F: Fold + ?Sized>(f: &mut F, tt: TokenTree) -> TokenTree {
+    match tt {
+        TokenTree::Group(t) => TokenTree::Group(f.fold_group(t)),
+        TokenTree::Ident(t) => TokenTree::Ident(f.fold_ident(t)),
+        TokenTree::Punct(t) => TokenTree::Punct(f.fold_punct(t)),
+        TokenTree::Literal(t) => TokenTree::Literal(f.fold_literal(t)),
+    }
+}
+
+pub fn fold_group<F: Fold + ?Sized>(f: &mut F, mut group: Group) -> Group {
+    let span = f.fold_span(group.span());
+    group.set_span(span);
+    group.map_stream(|stream| f.fold_token_stream(stream))
+}
+
+pub fn fold_ident<F: Fold + ?Sized>(f: &mut F, mut ident: Ident) -> Ident {
+    let span = f.fold_span(ident.span());
+    ident.set_span(span);
+    ident
+}
+
+pub fn fold_punct<F: Fold + ?Sized>(f: &mut F, mut punct: Punct) -> Punct {
+    let span = f.fold_span(punct.span());
+    punct.set_span(span);
+    punct
+}
+
+pub fn fold_literal<F: Fold + ?Sized>(f: &mut F, mut literal: Literal) -> Literal {
+    let span = f.fold_span(literal.span());
+    literal.set_span(span);
+    literal
+}
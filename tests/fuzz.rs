@@ -0,0 +1,267 @@
+//! Lexes pseudo-random inputs built from fragments of Rust syntax, checking
+//! that malformed input is always reported as a `LexError` rather than a
+//! panic.
+
+// Random numbers are truncated on purpose.
+#![allow(clippy::cast_possible_truncation)]
+
+use proc_macro0::{
+    EncodeOptions, LexOptions, Literal, PrettyOptions, SourceMap, TokenStream, TokenTree,
+};
+
+const FRAGMENTS: &[&str] = &[
+    "a",
+    "_",
+    "r#",
+    "b",
+    "br",
+    "r",
+    "#",
+    "##",
+    "'",
+    "\"",
+    "\\",
+    "\\x",
+    "\\u{",
+    "\\n",
+    "\\\n",
+    "\\\r",
+    "x",
+    "u",
+    "{",
+    "}",
+    "(",
+    ")",
+    "[",
+    "]",
+    "/",
+    "*",
+    "//",
+    "///",
+    "//!",
+    "/*",
+    "*/",
+    "/**",
+    "/*!",
+    "\r",
+    "\n",
+    " ",
+    "\t",
+    "0",
+    "1",
+    "9",
+    "0x",
+    "0b",
+    "0o",
+    "e",
+    "E",
+    "-",
+    "+",
+    ".",
+    "..",
+    "f32",
+    "u8",
+    "self",
+    "super",
+    "Self",
+    "crate",
+    "é",
+    "\u{85}",
+    "\u{200e}",
+    "ж",
+    "\u{10ffff}",
+    "\0",
+    "~",
+    "!",
+    "$",
+    "?",
+    ";",
+    ",",
+    "=",
+    "<",
+    ">",
+];
+
+/// xorshift64, to avoid depending on a random number generator.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+fn check(input: &str) {
+    // A source map per input keeps span lookups from slowing down as the
+    // number of parsed inputs grows.
+    let map = SourceMap::new();
+    if let Ok(tokens) = map.parse_token_stream(input) {
+        visit(tokens);
+    }
+    let options = LexOptions::new().preserve_trivia(true);
+    if let Ok(tokens) = map.parse_token_stream_with(input, &options) {
+        assert_eq!(tokens.to_string(), input);
+        let mut bytes = Vec::new();
+        let encode = EncodeOptions::new().spans(true);
+        tokens.encode_with(&mut bytes, &encode).unwrap();
+        let decoded = map.decode_token_stream(&mut &bytes[..]).unwrap();
+        assert_eq!(decoded.to_string(), input);
+        assert_eq!(decoded, tokens);
+    }
+    let options = LexOptions::new().keep_doc_comments(true);
+    if let Ok(tokens) = map.parse_token_stream_with(input, &options) {
+        let printed = tokens.to_string();
+        let reparsed = map.parse_token_stream_with(&printed, &options).unwrap();
+        assert_eq!(reparsed.to_string(), printed);
+        assert_eq!(reparsed, tokens);
+        let pretty = tokens
+            .pretty(&PrettyOptions::new().max_width(20))
+            .to_string();
+        let reparsed = map.parse_token_stream_with(&pretty, &options).unwrap();
+        assert_eq!(reparsed.to_string(), printed);
+    }
+    if let Ok(literal) = map.parse_literal(input) {
+        let _ = literal.span().start();
+        let _ = literal.to_string();
+        values(&literal);
+    }
+}
+
+fn values(literal: &Literal) {
+    let _ = (literal.kind(), literal.suffix());
+    let _ = (literal.as_str_value(), literal.as_bytes_value());
+    let _ = (literal.as_char_value(), literal.as_byte_value());
+    let _ = (literal.as_int::<u64>(), literal.as_float());
+    let _ = (literal.subspan(1..), literal.value_subspan(1..));
+}
+
+fn visit(tokens: TokenStream) {
+    for token in tokens {
+        let span = token.span();
+        let _ = (span.start(), span.end(), span.source_file());
+        let _ = token.to_string();
+        match token {
+            TokenTree::Group(group) => visit(group.stream()),
+            TokenTree::Literal(literal) => values(&literal),
+            TokenTree::Ident(_) | TokenTree::Punct(_) => {}
+        }
+    }
+}
+
+#[test]
+fn fragments() {
+    let iterations = if cfg!(miri) { 50 } else { 20_000 };
+    let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+    for _ in 0..iterations {
+        let mut input = String::new();
+        for _ in 0..rng.below(12) {
+            input.push_str(FRAGMENTS[rng.below(FRAGMENTS.len())]);
+        }
+        check(&input);
+    }
+}
+
+#[test]
+fn arbitrary_bytes() {
+    let iterations = if cfg!(miri) { 50 } else { 20_000 };
+    let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+    for _ in 0..iterations {
+        let bytes = (0..rng.below(24))
+            .map(|_| rng.next() as u8)
+            .collect::<Vec<u8>>();
+        check(&String::from_utf8_lossy(&bytes));
+    }
+}
+
+#[test]
+fn diffs() {
+    let iterations = if cfg!(miri) { 50 } else { 5_000 };
+    let mut rng = Rng(0xbb67_ae85_84ca_a73b);
+    let mut prev = TokenStream::new();
+    for _ in 0..iterations {
+        let tokens = balanced(&mut rng).parse::<TokenStream>().unwrap();
+        let diff = prev.diff(&tokens);
+        assert_eq!(diff.is_empty(), prev == tokens);
+        for change in diff.changes() {
+            if let Some(token) = change.old_token() {
+                assert_eq!(at_path(&prev, change.old_path()), *token);
+            }
+            if let Some(token) = change.new_token() {
+                assert_eq!(at_path(&tokens, change.new_path()), *token);
+            }
+        }
+        assert!(tokens.diff(&tokens).is_empty());
+        prev = tokens;
+    }
+}
+
+/// Builds an input from a small alphabet with balanced delimiters, so that
+/// consecutive inputs often share tokens.
+fn balanced(rng: &mut Rng) -> String {
+    const TOKENS: &[&str] = &["a", "b", "1", "2", "+", ",", " "];
+    const DELIMITERS: &[(char, char)] = &[('(', ')'), ('[', ']'), ('{', '}')];
+    let mut input = String::new();
+    let mut open = Vec::new();
+    for _ in 0..rng.below(30) {
+        match rng.below(10) {
+            0 => {
+                let (start, end) = DELIMITERS[rng.below(DELIMITERS.len())];
+                input.push(start);
+                open.push(end);
+            }
+            1 if !open.is_empty() => input.push(open.pop().unwrap()),
+            _ => input.push_str(TOKENS[rng.below(TOKENS.len())]),
+        }
+    }
+    input.extend(open.into_iter().rev());
+    input
+}
+
+fn at_path(tokens: &TokenStream, path: &[usize]) -> TokenTree {
+    let token = tokens.clone().into_iter().nth(path[0]).unwrap();
+    match (token, path.len()) {
+        (token, 1) => token,
+        (TokenTree::Group(group), _) => at_path(&group.stream(), &path[1..]),
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn corrupted_encoding() {
+    let src = "/// doc\nfn f<'a>(x: &'a str) -> u8 { r#try!(x); 1u8 } // end";
+    let options = LexOptions::new().preserve_trivia(true);
+    let tokens = TokenStream::from_str_with(src, &options).unwrap();
+    let mut bytes = Vec::new();
+    let encode = EncodeOptions::new().spans(true);
+    tokens.encode_with(&mut bytes, &encode).unwrap();
+
+    let iterations = if cfg!(miri) { 50 } else { 20_000 };
+    let mut rng = Rng(0x6a09_e667_f3bc_c908);
+    for _ in 0..iterations {
+        let mut corrupted = bytes.clone();
+        for _ in 0..=rng.below(3) {
+            let i = rng.below(corrupted.len());
+            corrupted[i] = rng.next() as u8;
+        }
+        corrupted.truncate(corrupted.len() - rng.below(2) * rng.below(corrupted.len()));
+        let map = SourceMap::new();
+        if let Ok(tokens) = map.decode_token_stream(&mut &corrupted[..]) {
+            visit(tokens);
+        }
+    }
+}
+
+#[test]
+fn reserved_raw_identifiers() {
+    for keyword in ["_", "self", "super", "Self", "crate"] {
+        assert!(format!("r#{keyword}").parse::<TokenStream>().is_err());
+        assert!(format!("'r#{keyword}").parse::<TokenStream>().is_err());
+    }
+}
@@ -1,6 +1,7 @@
 use proc_macro0::{
-    Delimiter, Group, Ident, LexError, LineColumn, Literal, Punct, SourceFile, Spacing, Span,
-    TokenStream, TokenTree,
+    AttrStyle, Change, ColumnEncoding, CommentKind, Cursor, Delimiter, Diff, DocComment,
+    DocCommentError, EncodeOptions, Group, Ident, LexError, LexOptions, LineColumn, Literal, Match,
+    ParseError, Pattern, Punct, SourceFile, SourceMap, Spacing, Span, TokenStream, TokenTree,
 };
 
 macro_rules! assert_impl {
@@ -42,8 +43,20 @@ macro_rules! assert_impl {
 
 assert_impl!(Delimiter is Send and Sync);
 assert_impl!(Spacing is Send and Sync);
+assert_impl!(AttrStyle is Send and Sync);
+assert_impl!(CommentKind is Send and Sync);
+assert_impl!(LexOptions is Send and Sync);
+assert_impl!(EncodeOptions is Send and Sync);
+assert_impl!(Diff is Send and Sync);
+assert_impl!(Change is Send and Sync);
+assert_impl!(Cursor is Send and Sync);
+assert_impl!(ParseError is Send and Sync);
+assert_impl!(Pattern is Send and Sync);
+assert_impl!(Match is Send and Sync);
 
 assert_impl!(Group is Send and Sync);
+assert_impl!(DocComment is Send and Sync);
+assert_impl!(DocCommentError is Send and Sync);
 assert_impl!(Ident is Send and Sync);
 assert_impl!(LexError is Send and Sync);
 assert_impl!(Literal is Send and Sync);
@@ -53,8 +66,10 @@ assert_impl!(TokenStream is Send and Sync);
 assert_impl!(TokenTree is Send and Sync);
 
 assert_impl!(LineColumn is Send and Sync);
+assert_impl!(ColumnEncoding is Send and Sync);
 
 assert_impl!(SourceFile is Send and Sync);
+assert_impl!(SourceMap is Send and Sync);
 
 mod unwind_safe {
     use proc_macro0::{
@@ -1,8 +1,13 @@
 #![allow(clippy::non_ascii_literal)]
 
-use proc_macro0::{Ident, Literal, Punct, Spacing, Span, TokenStream, TokenTree};
-use std::panic;
+use proc_macro0::{
+    AttrStyle, ChangeKind, ColumnEncoding, CommentKind, Delimiter, DocComment, Group, Ident,
+    IdentErrorKind, LexError, LexErrorKind, LexOptions, LineColumn, Literal, LiteralKind, Punct,
+    SourceMap, Spacing, Span, TokenStream, TokenTree,
+};
+use std::path::Path;
 use std::str::{self, FromStr};
+use std::{env, fs, io, panic};
 
 #[test]
 fn idents() {
@@ -104,6 +109,56 @@ fn lifetime_invalid() {
     }
 }
 
+#[test]
+fn ident_try_new() {
+    fn kind(string: &str, raw: bool) -> IdentErrorKind {
+        let result = if raw {
+            Ident::try_new_raw(string, Span::call_site())
+        } else {
+            Ident::try_new(string, Span::call_site())
+        };
+        result.unwrap_err().kind()
+    }
+
+    assert_eq!(
+        Ident::try_new("fn", Span::call_site()).unwrap().to_string(),
+        "fn"
+    );
+    assert_eq!(
+        Ident::try_new_raw("fn", Span::call_site())
+            .unwrap()
+            .to_string(),
+        "r#fn"
+    );
+    assert_eq!(kind("", false), IdentErrorKind::Empty);
+    assert_eq!(kind("255", false), IdentErrorKind::Number);
+    assert_eq!(kind("a#", false), IdentErrorKind::InvalidChar('#'));
+    assert_eq!(kind("1a", false), IdentErrorKind::InvalidChar('1'));
+    assert_eq!(kind("r#a", false), IdentErrorKind::InvalidChar('#'));
+    assert_eq!(kind("self", true), IdentErrorKind::ReservedRaw);
+
+    let err = Ident::try_new_raw("super", Span::call_site()).unwrap_err();
+    assert_eq!(err.as_str(), "super");
+    assert_eq!(err.to_string(), "`r#super` cannot be a raw identifier");
+}
+
+#[test]
+fn punct_try_new() {
+    let punct = Punct::try_new('+', Spacing::Joint).unwrap();
+    assert_eq!(punct.as_char(), '+');
+    assert_eq!(punct.spacing(), Spacing::Joint);
+
+    let err = Punct::try_new('a', Spacing::Alone).unwrap_err();
+    assert_eq!(err.as_char(), 'a');
+    assert_eq!(err.to_string(), "unsupported character 'a'");
+}
+
+#[test]
+#[should_panic(expected = "unsupported character 'a'")]
+fn punct_invalid() {
+    Punct::new('a', Spacing::Alone);
+}
+
 #[test]
 fn literal_string() {
     assert_eq!(Literal::string("foo").to_string(), "\"foo\"");
@@ -198,6 +253,127 @@ fn literal_suffix() {
     assert_eq!(token_count("0.0ECMA"), 1);
 }
 
+#[test]
+fn literal_values() {
+    fn lit(repr: &str) -> Literal {
+        repr.parse().unwrap()
+    }
+
+    assert_eq!(lit("\"a\\n\\u{e9}\\x41\"").kind(), LiteralKind::Str);
+    assert_eq!(
+        lit("\"a\\n\\u{e9}\\x41\"").as_str_value().as_deref(),
+        Some("a\n\u{e9}A"),
+    );
+    assert_eq!(
+        lit("\"line \\\n   continued\"").as_str_value().as_deref(),
+        Some("line continued"),
+    );
+    assert_eq!(lit("r#\"\\n\"#").kind(), LiteralKind::RawStr);
+    assert_eq!(lit("r#\"\\n\"#").as_str_value().as_deref(), Some("\\n"));
+    assert_eq!(lit("\"s\"suffix").suffix(), "suffix");
+
+    assert_eq!(lit("b\"a\\xff\"").kind(), LiteralKind::ByteStr);
+    assert_eq!(lit("b\"a\\xff\"").as_bytes_value(), Some(b"a\xff".to_vec()));
+    assert_eq!(lit("br##\"\"#\"##").kind(), LiteralKind::RawByteStr);
+    assert_eq!(lit("br##\"\"#\"##").as_bytes_value(), Some(b"\"#".to_vec()));
+    assert_eq!(lit("\"a\"").as_bytes_value(), None);
+
+    assert_eq!(lit("'\\''").kind(), LiteralKind::Char);
+    assert_eq!(lit("'\\''").as_char_value(), Some('\''));
+    assert_eq!(lit("'\\u{1F600}'").as_char_value(), Some('\u{1F600}'));
+    assert_eq!(lit("b'\\n'").kind(), LiteralKind::Byte);
+    assert_eq!(lit("b'\\n'").as_byte_value(), Some(b'\n'));
+    assert_eq!(lit("b'\\n'").as_char_value(), None);
+
+    assert_eq!(lit("1_000u32").kind(), LiteralKind::Int);
+    assert_eq!(lit("1_000u32").suffix(), "u32");
+    assert_eq!(lit("1_000u32").as_int::<u32>(), Some(1000));
+    assert_eq!(lit("0xffu8").as_int::<u8>(), Some(255));
+    assert_eq!(lit("0x1ff").as_int::<u8>(), None);
+    assert_eq!(lit("0o17").as_int::<i32>(), Some(15));
+    assert_eq!(lit("0b1010_1010").as_int::<u64>(), Some(170));
+    assert_eq!(lit("-128").as_int::<i8>(), Some(-128));
+    assert_eq!(lit("-1").as_int::<u8>(), None);
+    assert_eq!(lit("1.5").as_int::<i32>(), None);
+    assert_eq!(lit("0b1f32").kind(), LiteralKind::Int);
+    assert_eq!(lit("0b1f32").suffix(), "f32");
+    assert_eq!(lit("0o7f64").kind(), LiteralKind::Int);
+
+    assert_eq!(lit("1.5").kind(), LiteralKind::Float);
+    assert_eq!(lit("1_0.2_5e1").as_float(), Some(102.5));
+    assert_eq!(lit("-2.5f32").as_float(), Some(-2.5));
+    assert_eq!(lit("-2.5f32").suffix(), "f32");
+    assert_eq!(lit("1f64").kind(), LiteralKind::Float);
+    assert_eq!(lit("1f64").as_float(), Some(1.0));
+    assert_eq!(lit("1").as_float(), None);
+
+    assert_eq!(
+        Literal::string("a\tb").as_str_value().as_deref(),
+        Some("a\tb")
+    );
+    assert_eq!(Literal::i16_suffixed(-7).as_int::<i64>(), Some(-7));
+    assert_eq!(
+        Literal::i128_suffixed(i128::MIN).as_int::<i128>(),
+        Some(i128::MIN),
+    );
+    assert_eq!(
+        Literal::i64_unsuffixed(i64::MIN).as_int::<i64>(),
+        Some(i64::MIN)
+    );
+    assert_eq!(Literal::u128_unsuffixed(1 << 127).as_int::<i128>(), None,);
+    assert_eq!(
+        lit("-170141183460469231731687303715884105729").as_int::<i128>(),
+        None
+    );
+}
+
+#[test]
+fn literal_subspan() {
+    fn columns(span: Option<Span>) -> Option<(usize, usize)> {
+        span.map(|span| (span.start().column, span.end().column))
+    }
+
+    let tokens = TokenStream::from_str(r##"f("aé\tb", r#"{x}"#, b"\x41{}", 1u8)"##).unwrap();
+    let Some(TokenTree::Group(group)) = tokens.into_iter().nth(1) else {
+        unreachable!();
+    };
+    let literals: Vec<Literal> = group
+        .stream()
+        .into_iter()
+        .filter_map(|tt| match tt {
+            TokenTree::Literal(literal) => Some(literal),
+            _ => None,
+        })
+        .collect();
+
+    // Source byte ranges, mapped to char columns.
+    assert_eq!(columns(literals[0].subspan(..)), Some((2, 9)));
+    assert_eq!(columns(literals[0].subspan(1..4)), Some((3, 5)));
+    assert_eq!(columns(literals[0].subspan(1..3)), None);
+    assert_eq!(columns(literals[0].subspan(..=10)), None);
+    assert_eq!(columns(literals[3].subspan(1..)), Some((33, 35)));
+
+    // Value byte ranges, mapped through escapes.
+    assert_eq!(columns(literals[0].value_subspan(3..4)), Some((5, 7)));
+    assert_eq!(columns(literals[0].value_subspan(4..4)), Some((7, 7)));
+    assert_eq!(columns(literals[0].value_subspan(2..3)), None);
+    assert_eq!(columns(literals[1].value_subspan(0..3)), Some((14, 17)));
+    assert_eq!(columns(literals[2].value_subspan(0..1)), Some((23, 27)));
+    assert_eq!(columns(literals[2].value_subspan(1..)), Some((27, 29)));
+    assert_eq!(columns(literals[3].value_subspan(..)), None);
+
+    // Literals which were not parsed from source have no subspans.
+    assert_eq!(columns(Literal::string("a").subspan(0..1)), None);
+    let mut literal = Literal::u8_suffixed(1);
+    literal.set_span(literals[0].span());
+    assert_eq!(columns(literal.subspan(0..1)), None);
+
+    let negative = Literal::from_str("-12").unwrap();
+    let start = negative.span().start().column;
+    assert_eq!(columns(negative.subspan(1..2)), Some((start, start + 1)));
+    assert_eq!(columns(negative.subspan(0..2)), None);
+}
+
 #[test]
 fn literal_iter_negative() {
     let negative_literal = Literal::i32_suffixed(-3);
@@ -243,9 +419,9 @@ fn literal_parse() {
 #[test]
 fn roundtrip() {
     fn roundtrip(p: &str) {
-        println!("parse: {}", p);
+        println!("parse: {p}");
         let s = p.parse::<TokenStream>().unwrap().to_string();
-        println!("first: {}", s);
+        println!("first: {s}");
         let s2 = s.parse::<TokenStream>().unwrap().to_string();
         assert_eq!(s, s2);
     }
@@ -301,6 +477,629 @@ fn fail() {
     fail("\"\\\n\u{85}\r\"");
 }
 
+#[test]
+fn lex_error_kind() {
+    fn kind(p: &str) -> LexErrorKind {
+        p.parse::<TokenStream>().unwrap_err().kind()
+    }
+
+    assert_eq!(
+        kind("("),
+        LexErrorKind::UnclosedDelimiter(Delimiter::Parenthesis)
+    );
+    assert_eq!(
+        kind("a }"),
+        LexErrorKind::UnexpectedClosingDelimiter(Delimiter::Brace)
+    );
+    assert_eq!(
+        kind("(]"),
+        LexErrorKind::MismatchedDelimiter {
+            open: Delimiter::Parenthesis,
+            close: Delimiter::Bracket,
+        }
+    );
+    assert_eq!(kind("\"abc"), LexErrorKind::UnterminatedLiteral);
+    assert_eq!(kind("br#\"abc\""), LexErrorKind::UnterminatedLiteral);
+    assert_eq!(kind("b'"), LexErrorKind::UnterminatedLiteral);
+    assert_eq!(kind("/* a /* b */"), LexErrorKind::UnterminatedBlockComment);
+    assert_eq!(kind("\"\\q\""), LexErrorKind::InvalidEscape);
+    assert_eq!(kind("'\\u{110000}'"), LexErrorKind::InvalidEscape);
+    assert_eq!(kind("b\"\\xZZ\""), LexErrorKind::InvalidEscape);
+    assert_eq!(kind("r\"\r\""), LexErrorKind::BareCarriageReturn);
+    assert_eq!(kind("///\r"), LexErrorKind::BareCarriageReturn);
+    assert_eq!(kind("0b2"), LexErrorKind::InvalidLiteral);
+    assert_eq!(kind("'aa'aa"), LexErrorKind::InvalidLiteral);
+    assert_eq!(kind("b\"é\""), LexErrorKind::InvalidLiteral);
+    assert_eq!(kind("r#_"), LexErrorKind::InvalidIdent);
+    assert_eq!(kind("a \\ b"), LexErrorKind::UnexpectedChar('\\'));
+
+    assert_eq!(
+        "1 2".parse::<Literal>().unwrap_err().kind(),
+        LexErrorKind::InvalidLiteral
+    );
+    assert_eq!(
+        "\"abc".parse::<Literal>().unwrap_err().kind(),
+        LexErrorKind::UnterminatedLiteral
+    );
+}
+
+#[test]
+fn lex_error_spans() {
+    let err = "a\n  ( b ]".parse::<TokenStream>().unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "mismatched closing delimiter `]`, expected `)`"
+    );
+    assert_eq!(err.start_span().start(), LineColumn { line: 2, column: 2 });
+    assert_eq!(err.start_span().end(), LineColumn { line: 2, column: 3 });
+    assert_eq!(err.end_span().start(), LineColumn { line: 2, column: 6 });
+    assert_eq!(err.span().start(), LineColumn { line: 2, column: 2 });
+    assert_eq!(err.span().end(), LineColumn { line: 2, column: 7 });
+
+    let err = "{ \"ab\\qc\" }".parse::<TokenStream>().unwrap_err();
+    assert_eq!(err.to_string(), "invalid escape sequence in literal");
+    assert_eq!(err.start_span().start().column, 2);
+    assert_eq!(err.end_span().start().column, 5);
+
+    let err = "[\n".parse::<TokenStream>().unwrap_err();
+    assert_eq!(err.to_string(), "unclosed delimiter `[`");
+    assert_eq!(err.start_span().start(), LineColumn { line: 1, column: 0 });
+    assert_eq!(err.end_span().start(), LineColumn { line: 2, column: 0 });
+}
+
+#[test]
+fn lossless_roundtrip() {
+    let options = LexOptions::new().preserve_trivia(true);
+    let sources = [
+        "",
+        "  \n",
+        "a+=b",
+        "/* a */ f ( x ,y )\t// b\n",
+        "/// doc\r\n//! inner\n/** block */ /*! inner block */ struct S { }",
+        "#[doc = \"attr\"] //// not doc\n/*** not doc */ fn f() -> u8 { 'a: loop {} }",
+        "x = [ r#\"raw\"# , b'y' ,-1i8 ] ;",
+    ];
+    for src in &sources {
+        let tokens = TokenStream::from_str_with(src, &options).unwrap();
+        assert_eq!(tokens.to_string(), *src);
+    }
+
+    for entry in fs::read_dir(Path::new(env!("CARGO_MANIFEST_DIR")).join("src")).unwrap() {
+        let src = fs::read_to_string(entry.unwrap().path()).unwrap();
+        let tokens = TokenStream::from_str_with(&src, &options).unwrap();
+        assert_eq!(tokens.to_string(), src);
+    }
+
+    // Without the option, trivia is dropped as usual.
+    let tokens = TokenStream::from_str("/* a */ { x }").unwrap();
+    assert_eq!(tokens.trailing_trivia(), None);
+    assert_eq!(tokens.to_string(), "{ x }");
+}
+
+#[test]
+fn lossless_trivia() {
+    let options = LexOptions::new().preserve_trivia(true);
+    let tokens = TokenStream::from_str_with("// a\nf( x /* b */ )\n", &options).unwrap();
+    assert_eq!(tokens.trailing_trivia(), Some("\n"));
+    let tokens: Vec<TokenTree> = tokens.into_iter().collect();
+    assert_eq!(tokens[0].leading_trivia(), Some("// a\n"));
+    assert_eq!(tokens[1].leading_trivia(), Some(""));
+    let group = match &tokens[1] {
+        TokenTree::Group(group) => group.stream(),
+        _ => unreachable!(),
+    };
+    assert_eq!(group.trailing_trivia(), Some(" /* b */ "));
+    assert_eq!(
+        group.into_iter().next().unwrap().leading_trivia(),
+        Some(" ")
+    );
+
+    let doc = TokenStream::from_str_with("/// doc\nfn", &options).unwrap();
+    let doc: Vec<TokenTree> = doc.into_iter().collect();
+    assert_eq!(doc.len(), 3);
+    assert_eq!(doc[0].leading_trivia(), Some(""));
+    assert_eq!(doc[2].leading_trivia(), Some("\n"));
+}
+
+#[test]
+fn lossless_edit() {
+    fn rename(stream: TokenStream) -> TokenStream {
+        let trailing = stream.trailing_trivia().map(str::to_owned);
+        let mut renamed: TokenStream = stream
+            .into_iter()
+            .map(|tt| match tt {
+                TokenTree::Ident(ident) if ident == "old" => {
+                    let mut new = Ident::new("new_name", ident.span());
+                    new.set_leading_trivia(ident.leading_trivia());
+                    TokenTree::Ident(new)
+                }
+                TokenTree::Group(group) => {
+                    let mut new = Group::new(group.delimiter(), rename(group.stream()));
+                    new.set_span(group.span());
+                    new.set_leading_trivia(group.leading_trivia());
+                    TokenTree::Group(new)
+                }
+                tt => tt,
+            })
+            .collect();
+        renamed.set_trailing_trivia(trailing.as_deref());
+        renamed
+    }
+
+    let options = LexOptions::new().preserve_trivia(true);
+    let src = "fn f() {\n    // keep\n    old(1);\n}\n";
+    let tokens = TokenStream::from_str_with(src, &options).unwrap();
+    assert_eq!(
+        rename(tokens.clone()).to_string(),
+        "fn f() {\n    // keep\n    new_name(1);\n}\n",
+    );
+
+    // Inserted tokens have no trivia and get the usual spacing, and the
+    // trailing trivia moves in front of them.
+    let mut extended = tokens;
+    extended.extend(TokenStream::from_str("struct S;").unwrap());
+    assert_eq!(
+        extended.to_string(),
+        "fn f() {\n    // keep\n    old(1);\n}\nstruct S ;",
+    );
+
+    // A doc comment whose tokens were changed is printed as an attribute.
+    let doc = TokenStream::from_str_with("/// a\n#[doc = \"b\"]", &options).unwrap();
+    let mut doc: Vec<TokenTree> = doc.into_iter().collect();
+    doc.remove(0);
+    let doc: TokenStream = doc.into_iter().collect();
+    assert_eq!(doc.to_string(), "[doc = \" a\"]\n#[doc = \"b\"]");
+}
+
+#[test]
+fn lossless_edit_keeps_tokens_apart() {
+    let options = LexOptions::new().preserve_trivia(true);
+    let lex = |src| TokenStream::from_str_with(src, &options).unwrap();
+
+    let filtered: TokenStream = lex("a+b")
+        .into_iter()
+        .filter(|tt| !matches!(tt, TokenTree::Punct(_)))
+        .collect();
+    assert_eq!(filtered.to_string(), "a b");
+
+    let mut extended = lex("y");
+    extended.extend(lex("x"));
+    assert_eq!(extended.to_string(), "y x");
+
+    let mut extended = lex("x // c");
+    extended.extend(TokenStream::from_str("z").unwrap());
+    assert_eq!(extended.to_string(), "x // c\nz");
+
+    let group = Group::new(Delimiter::Parenthesis, lex("a // hi"));
+    let printed = TokenStream::from(TokenTree::Group(group)).to_string();
+    assert_eq!(printed, "(a // hi\n)");
+    assert_eq!(lex(&printed).to_string(), printed);
+
+    let mut ident = Ident::new("a", Span::call_site());
+    ident.set_leading_trivia(Some("// a"));
+    let tokens: TokenStream = vec![TokenTree::Ident(ident)].into_iter().collect();
+    assert_eq!(tokens.to_string(), "// a\na");
+}
+
+#[test]
+fn set_trivia_rejects_tokens() {
+    for trivia in [" x ", "/// doc\n", "/*! doc */", "/* open"] {
+        let mut tokens = TokenStream::new();
+        let result = panic::catch_unwind(move || tokens.set_trailing_trivia(Some(trivia)));
+        assert!(result.is_err(), "{:?}", trivia);
+    }
+    let mut punct = Punct::new('+', Spacing::Alone);
+    punct.set_leading_trivia(Some(" /* a */ // b\n\t"));
+    assert_eq!(punct.leading_trivia(), Some(" /* a */ // b\n\t"));
+}
+
+#[test]
+fn group_stream_mut() {
+    let options = LexOptions::new().preserve_trivia(true);
+    let map = SourceMap::new();
+    let tokens = map.parse_token_stream_with("f( a, b )", &options).unwrap();
+    let Some(TokenTree::Group(mut group)) = tokens.into_iter().nth(1) else {
+        panic!("expected a group");
+    };
+    let (open, close) = (group.span_open(), group.span_close());
+
+    let stream = group.stream_mut();
+    stream.extend(TokenStream::from_str(", c").unwrap());
+    assert_eq!(stream.trailing_trivia(), None);
+    assert_eq!(group.to_string(), "( a, b , c)");
+    assert_eq!(group.delimiter(), Delimiter::Parenthesis);
+    assert_eq!(group.span_open().start(), open.start());
+    assert_eq!(group.span_close().end(), close.end());
+
+    let group = group.map_stream(|stream| stream.into_iter().take(1).collect());
+    assert_eq!(group.to_string(), "( a)");
+    assert_eq!(group.span_open().start(), open.start());
+    assert_eq!(group.span_close().end(), close.end());
+
+    let stream = group.into_stream();
+    assert_eq!(stream.to_string(), " a");
+
+    // Editing the contents of a doc comment's group prints it as an
+    // attribute.
+    let doc = TokenStream::from_str_with("/// a\nfn f() {}", &options).unwrap();
+    let doc: TokenStream = doc
+        .into_iter()
+        .map(|tt| match tt {
+            TokenTree::Group(group) if group.delimiter() == Delimiter::Bracket => TokenTree::Group(
+                group.map_stream(|_| TokenStream::from_str("doc = \"b\"").unwrap()),
+            ),
+            tt => tt,
+        })
+        .collect();
+    assert_eq!(doc.to_string(), "# [doc = \"b\"]\nfn f() {}");
+
+    // Contents which are left as they were still print as the comment.
+    let doc = TokenStream::from_str_with("/// a\nfn f() {}", &options).unwrap();
+    let doc: TokenStream = doc
+        .into_iter()
+        .map(|tt| match tt {
+            TokenTree::Group(mut group) => {
+                group.stream_mut();
+                TokenTree::Group(group.map_stream(|stream| stream))
+            }
+            tt => tt,
+        })
+        .collect();
+    assert_eq!(doc.to_string(), "/// a\nfn f() {}");
+}
+
+#[test]
+fn doc_comment_tokens() {
+    fn doc_comments(tokens: TokenStream) -> Vec<DocComment> {
+        let mut comments = Vec::new();
+        for tt in tokens {
+            if let TokenTree::Group(group) = tt {
+                comments.extend(group.doc_comment());
+                comments.extend(doc_comments(group.stream()));
+            }
+        }
+        comments
+    }
+
+    let options = LexOptions::new().keep_doc_comments(true);
+    let src = "//! inner\n/// outer\n/** block */ /*! inner block */ fn f() {\n/// last\n}";
+    let tokens = TokenStream::from_str_with(src, &options).unwrap();
+    let comments = doc_comments(tokens.clone());
+    assert_eq!(comments.len(), 5);
+    assert_eq!(comments[0].style(), AttrStyle::Inner);
+    assert_eq!(comments[0].kind(), CommentKind::Line);
+    assert_eq!(comments[0].text(), " inner");
+    assert_eq!(comments[1].style(), AttrStyle::Outer);
+    assert_eq!(comments[2].kind(), CommentKind::Block);
+    assert_eq!(comments[2].text(), " block ");
+    assert_eq!(comments[3].style(), AttrStyle::Inner);
+    assert_eq!(comments[4].text(), " last");
+    assert_eq!(
+        comments[0].span().start(),
+        LineColumn { line: 1, column: 0 }
+    );
+    assert_eq!(comments[0].span().end(), LineColumn { line: 1, column: 9 });
+
+    // The tokens are the usual attributes, but print as the comments.
+    let attribute = TokenStream::from_str(src).unwrap();
+    assert_eq!(tokens, attribute);
+    assert_eq!(tokens.clone().into_iter().count(), 14);
+    let printed = tokens.to_string();
+    assert_eq!(
+        printed,
+        "//! inner\n/// outer\n/** block */ /*! inner block */ fn f () { /// last\n }",
+    );
+    let reparsed = TokenStream::from_str_with(&printed, &options).unwrap();
+    assert_eq!(reparsed.to_string(), printed);
+
+    assert_eq!(
+        comments[1].to_attribute().to_string(),
+        "# [doc = \" outer\"]",
+    );
+    assert_eq!(
+        comments[3].to_attribute().to_string(),
+        "# ! [doc = \" inner block \"]",
+    );
+
+    // Combined with trivia, the source is reproduced exactly.
+    let lossless = options.preserve_trivia(true);
+    let tokens = TokenStream::from_str_with(src, &lossless).unwrap();
+    assert_eq!(tokens.to_string(), src);
+
+    // Without either option, the comments are forgotten.
+    assert!(doc_comments(attribute).is_empty());
+
+    let comment = DocComment::new(AttrStyle::Outer, CommentKind::Line, " new");
+    let mut tokens = TokenStream::from(comment);
+    tokens.extend(Some(TokenTree::from(Ident::new("x", Span::call_site()))));
+    assert_eq!(tokens.to_string(), "/// new\nx");
+}
+
+#[test]
+fn doc_comment_new() {
+    for (style, kind, text) in [
+        (AttrStyle::Outer, CommentKind::Line, " a"),
+        (AttrStyle::Inner, CommentKind::Line, ""),
+        (AttrStyle::Outer, CommentKind::Block, " /* nested */ "),
+        (AttrStyle::Inner, CommentKind::Block, ""),
+    ] {
+        assert_eq!(DocComment::new(style, kind, text).text(), text);
+        assert_eq!(DocComment::try_new(style, kind, text).unwrap().text(), text);
+    }
+    for (style, kind, text) in [
+        (AttrStyle::Outer, CommentKind::Line, "/ not doc"),
+        (AttrStyle::Outer, CommentKind::Line, "a\nb"),
+        (AttrStyle::Inner, CommentKind::Line, "bare\rcr"),
+        (AttrStyle::Outer, CommentKind::Block, ""),
+        (AttrStyle::Outer, CommentKind::Block, "* not doc"),
+        (AttrStyle::Inner, CommentKind::Block, "a */ b"),
+        (AttrStyle::Inner, CommentKind::Block, "/* unbalanced"),
+    ] {
+        let err = DocComment::try_new(style, kind, text).unwrap_err();
+        assert_eq!(err.text(), text);
+        let result = panic::catch_unwind(|| DocComment::new(style, kind, text));
+        assert!(result.is_err(), "{:?}", text);
+    }
+    let err = DocComment::try_new(AttrStyle::Outer, CommentKind::Line, "a\nb").unwrap_err();
+    assert_eq!(err.to_string(), "\"a\\nb\" is not a valid Line doc comment");
+}
+
+#[test]
+fn token_eq() {
+    let tokens = TokenStream::from_str("fn f ( x : u8 ) { x + 1 }").unwrap();
+    let map = SourceMap::new();
+    let formatted = map
+        .parse_token_stream("fn f(x: u8) {\n    x + 1\n}")
+        .unwrap();
+    assert_eq!(tokens, formatted);
+    assert_ne!(
+        tokens,
+        TokenStream::from_str("fn f(x: u8) { x + 2 }").unwrap()
+    );
+    assert_ne!(
+        tokens,
+        TokenStream::from_str("fn f[x: u8] { x + 1 }").unwrap()
+    );
+
+    // Spacing is part of a punct, and literals compare by how they are written.
+    let plus_eq = TokenStream::from_str("a += 1").unwrap();
+    assert_ne!(plus_eq, TokenStream::from_str("a + = 1").unwrap());
+    assert_ne!(plus_eq, TokenStream::from_str("a += 0x1").unwrap());
+    assert_eq!(Literal::u8_suffixed(1), Literal::from_str("1u8").unwrap());
+    assert_ne!(Literal::string("a"), Literal::from_str("r\"a\"").unwrap());
+
+    // Trivia is ignored.
+    let options = LexOptions::new().preserve_trivia(true);
+    let commented =
+        TokenStream::from_str_with("fn f(x: u8) { /* one */ x + 1 }", &options).unwrap();
+    assert_eq!(tokens, commented);
+
+    // None-delimited groups are transparent.
+    let inner = TokenStream::from_str("x + 1").unwrap();
+    let mut nested = TokenStream::from_str("fn f(x: u8)").unwrap();
+    let none = Group::new(Delimiter::None, inner.clone());
+    let brace = Group::new(Delimiter::Brace, TokenStream::from(TokenTree::Group(none)));
+    nested.extend(std::iter::once(TokenTree::Group(brace)));
+    assert_eq!(tokens, nested);
+    let none = TokenTree::Group(Group::new(Delimiter::None, inner.clone()));
+    assert_ne!(
+        none,
+        TokenTree::Group(Group::new(Delimiter::Parenthesis, inner))
+    );
+    let x = TokenTree::Ident(Ident::new("x", Span::call_site()));
+    assert_eq!(
+        TokenTree::Group(Group::new(Delimiter::None, x.clone().into())),
+        x
+    );
+}
+
+#[test]
+fn token_hash() {
+    use std::collections::hash_map::DefaultHasher;
+    use std::collections::HashMap;
+    use std::hash::{Hash, Hasher};
+
+    fn hash<T: Hash>(t: &T) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        t.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    let a = TokenStream::from_str("f(x)").unwrap();
+    let b = TokenStream::from_str("f ( x )").unwrap();
+    assert_eq!(hash(&a), hash(&b));
+    let x = TokenStream::from_str("x").unwrap();
+    let none = TokenTree::Group(Group::new(Delimiter::None, x.clone()));
+    let wrapped = TokenStream::from_str("f")
+        .unwrap()
+        .into_iter()
+        .chain(std::iter::once(TokenTree::Group(Group::new(
+            Delimiter::Parenthesis,
+            none.into(),
+        ))));
+    assert_eq!(hash(&a), hash(&wrapped.collect::<TokenStream>()));
+
+    let mut memo = HashMap::new();
+    memo.insert(a, 1);
+    assert_eq!(memo.get(&b), Some(&1));
+    assert_eq!(memo.get(&x), None);
+}
+
+fn diff(old: &str, new: &str) -> proc_macro0::Diff {
+    let old = TokenStream::from_str(old).unwrap();
+    let new = TokenStream::from_str(new).unwrap();
+    old.diff(&new)
+}
+
+type ChangeSummary = (
+    ChangeKind,
+    Vec<usize>,
+    Vec<usize>,
+    Option<String>,
+    Option<String>,
+);
+
+fn summary(diff: &proc_macro0::Diff) -> Vec<ChangeSummary> {
+    diff.changes()
+        .iter()
+        .map(|change| {
+            let token = |token: Option<&TokenTree>| token.map(ToString::to_string);
+            (
+                change.kind(),
+                change.old_path().to_vec(),
+                change.new_path().to_vec(),
+                token(change.old_token()),
+                token(change.new_token()),
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn token_diff() {
+    assert!(diff("a + b", "a  +  b").is_empty());
+
+    let d = diff(
+        "struct S { x: u8, y: u16 }",
+        "struct S { x: u8, z: u16, w: u32 }",
+    );
+    assert_eq!(
+        summary(&d),
+        [
+            (
+                ChangeKind::Changed,
+                vec![2, 4],
+                vec![2, 4],
+                Some("y".to_owned()),
+                Some("z".to_owned()),
+            ),
+            (
+                ChangeKind::Inserted,
+                vec![2, 7],
+                vec![2, 7],
+                None,
+                Some(",".to_owned())
+            ),
+            (
+                ChangeKind::Inserted,
+                vec![2, 7],
+                vec![2, 8],
+                None,
+                Some("w".to_owned())
+            ),
+            (
+                ChangeKind::Inserted,
+                vec![2, 7],
+                vec![2, 9],
+                None,
+                Some(":".to_owned())
+            ),
+            (
+                ChangeKind::Inserted,
+                vec![2, 7],
+                vec![2, 10],
+                None,
+                Some("u32".to_owned())
+            ),
+        ],
+    );
+
+    let d = diff("f(a, 1)", "f[a, \"1\"]");
+    assert_eq!(
+        summary(&d),
+        [
+            (
+                ChangeKind::DelimiterChanged,
+                vec![1],
+                vec![1],
+                Some("(a , 1)".to_owned()),
+                Some("[a , \"1\"]".to_owned()),
+            ),
+            (
+                ChangeKind::Changed,
+                vec![1, 2],
+                vec![1, 2],
+                Some("1".to_owned()),
+                Some("\"1\"".to_owned()),
+            ),
+        ],
+    );
+
+    let d = diff("a b c", "a c");
+    assert_eq!(
+        summary(&d),
+        [(
+            ChangeKind::Deleted,
+            vec![1],
+            vec![1],
+            Some("b".to_owned()),
+            None
+        )],
+    );
+    let d = diff("a b", "a 1");
+    assert_eq!(d.changes()[0].kind(), ChangeKind::Deleted);
+    assert_eq!(d.changes()[1].kind(), ChangeKind::Inserted);
+}
+
+#[test]
+fn token_diff_spans() {
+    // Spans of each side.
+    let map = SourceMap::new();
+    let old = map.parse_token_stream("x\n  + 1").unwrap();
+    let new = map.parse_token_stream("x + 2").unwrap();
+    let d = old.diff(&new);
+    let change = &d.changes()[0];
+    assert_eq!(
+        change.old_span().unwrap().start(),
+        LineColumn { line: 2, column: 4 }
+    );
+    assert_eq!(
+        change.new_span().unwrap().start(),
+        LineColumn { line: 1, column: 4 }
+    );
+}
+
+#[test]
+fn token_diff_none_groups() {
+    let mut grouped = TokenStream::from_str("a").unwrap();
+    let b_c = TokenStream::from_str("b c").unwrap();
+    grouped.extend(std::iter::once(TokenTree::Group(Group::new(
+        Delimiter::None,
+        b_c,
+    ))));
+    assert!(TokenStream::from_str("a b c")
+        .unwrap()
+        .diff(&grouped)
+        .is_empty());
+    let d = TokenStream::from_str("a b d").unwrap().diff(&grouped);
+    assert_eq!(summary(&d)[0].1, [2]);
+}
+
+#[test]
+fn token_diff_large() {
+    let idents = |prefix: &str, len: usize| {
+        (0..len)
+            .map(|i| TokenTree::Ident(Ident::new(&format!("{prefix}{i}"), Span::call_site())))
+            .collect::<TokenStream>()
+    };
+
+    // Nothing in common, which is the worst case for the alignment.
+    let d = idents("a", 4000).diff(&idents("b", 4000));
+    assert_eq!(d.changes().len(), 4000);
+    assert!(d
+        .changes()
+        .iter()
+        .all(|change| change.kind() == ChangeKind::Changed));
+
+    // One change in the middle of long runs of equal tokens.
+    let old = idents("a", 100_000);
+    let mut new = old.clone().into_iter().collect::<Vec<_>>();
+    new[50_000] = TokenTree::Ident(Ident::new("b", Span::call_site()));
+    let d = old.diff(&new.into_iter().collect());
+    assert_eq!(d.changes().len(), 1);
+    assert_eq!(d.changes()[0].old_path(), [50_000]);
+}
+
 #[test]
 fn span_test() {
     check_spans(
@@ -351,7 +1150,10 @@ fn span_join() {
         .into_iter()
         .collect::<Vec<_>>();
 
-    assert!(source1[0].span().source_file() != source2[0].span().source_file());
+    assert_ne!(
+        source1[0].span().source_file(),
+        source2[0].span().source_file()
+    );
     assert_eq!(
         source1[0].span().source_file(),
         source1[1].span().source_file()
@@ -375,6 +1177,255 @@ fn span_join() {
     );
 }
 
+#[test]
+fn span_source_text() {
+    let map = SourceMap::new();
+    let src = "// é\nlet café = \"ü\";\nf(a, /* ö */\n  b)";
+    let tokens: Vec<TokenTree> = map.parse_token_stream(src).unwrap().into_iter().collect();
+
+    let cafe = tokens[1].span();
+    assert_eq!(cafe.source_text().unwrap(), "café");
+    assert_eq!(cafe.byte_range(), 10..15);
+    assert_eq!(&src[cafe.byte_range()], "café");
+    assert_eq!(tokens[3].span().source_text().unwrap(), "\"ü\"");
+
+    let group = tokens[6].span();
+    assert_eq!(group.source_text().unwrap(), "(a, /* ö */\n  b)");
+    assert_eq!(&src[group.byte_range()], "(a, /* ö */\n  b)");
+
+    let joined = tokens[1].span().join(tokens[4].span()).unwrap();
+    assert_eq!(joined.source_text().unwrap(), "café = \"ü\";");
+    let joined = tokens[0].span().join(group).unwrap();
+    assert_eq!(joined.source_text().unwrap(), &src[6..]);
+
+    assert_eq!(Span::call_site().source_text(), None);
+    assert_eq!(Span::call_site().byte_range(), 0..0);
+
+    drop(map);
+    assert_eq!(cafe.source_text(), None);
+    assert_eq!(cafe.byte_range(), 0..0);
+}
+
+#[test]
+fn span_column_encodings() {
+    let map = SourceMap::new();
+    let src = "a\n/* 🦀é */ x\r\n\"ü\" y";
+    let tokens: Vec<TokenTree> = map.parse_token_stream(src).unwrap().into_iter().collect();
+    let position = |line, column| LineColumn { line, column };
+
+    let x = tokens[1].span();
+    assert_eq!(x.start(), position(2, 9));
+    assert_eq!(x.start_in(ColumnEncoding::Chars), Some(position(2, 9)));
+    assert_eq!(x.start_in(ColumnEncoding::Utf8), Some(position(2, 13)));
+    assert_eq!(x.end_in(ColumnEncoding::Utf8), Some(position(2, 14)));
+    assert_eq!(x.start_in(ColumnEncoding::Utf16), Some(position(2, 10)));
+    assert_eq!(x.end_in(ColumnEncoding::Utf16), Some(position(2, 11)));
+
+    let literal = tokens[2].span();
+    assert_eq!(literal.end(), position(3, 3));
+    assert_eq!(literal.end_in(ColumnEncoding::Utf8), Some(position(3, 4)));
+    assert_eq!(literal.end_in(ColumnEncoding::Utf16), Some(position(3, 3)));
+
+    for encoding in [
+        ColumnEncoding::Chars,
+        ColumnEncoding::Utf8,
+        ColumnEncoding::Utf16,
+    ] {
+        for tt in &tokens {
+            for lc in [tt.span().start_in(encoding), tt.span().end_in(encoding)] {
+                let lc = lc.unwrap();
+                let found = tokens[0].span().at_line_column(lc, encoding).unwrap();
+                assert_eq!(found.start_in(encoding), Some(lc));
+                assert_eq!(found.end_in(encoding), Some(lc));
+                assert_eq!(found.source_text().as_deref(), Some(""));
+            }
+        }
+    }
+
+    let a = tokens[0].span();
+    let found = a
+        .at_line_column(position(2, 13), ColumnEncoding::Utf8)
+        .unwrap();
+    assert_eq!(found.join(x).unwrap().source_text().as_deref(), Some("x"));
+    // Within the crab.
+    assert_eq!(a.at_line_column(position(2, 5), ColumnEncoding::Utf8), None);
+    assert_eq!(
+        a.at_line_column(position(2, 4), ColumnEncoding::Utf16),
+        None
+    );
+    // The end of a line is before its newline, but after a carriage return.
+    assert!(a
+        .at_line_column(position(2, 11), ColumnEncoding::Chars)
+        .is_some());
+    assert_eq!(
+        a.at_line_column(position(2, 12), ColumnEncoding::Chars),
+        None
+    );
+    assert!(a
+        .at_line_column(position(3, 5), ColumnEncoding::Chars)
+        .is_some());
+    assert_eq!(
+        a.at_line_column(position(3, 6), ColumnEncoding::Chars),
+        None
+    );
+    assert_eq!(
+        a.at_line_column(position(4, 0), ColumnEncoding::Chars),
+        None
+    );
+    assert_eq!(
+        a.at_line_column(position(0, 0), ColumnEncoding::Chars),
+        None
+    );
+
+    let call_site = Span::call_site();
+    assert_eq!(
+        call_site.start_in(ColumnEncoding::Utf16),
+        Some(position(1, 0))
+    );
+    assert_eq!(
+        call_site.at_line_column(position(1, 0), ColumnEncoding::Chars),
+        None
+    );
+}
+
+#[test]
+fn named_source_file() {
+    let tokens = TokenStream::from_str_named("src/lib.rs", "fn f() {}").unwrap();
+    let source_file = tokens.into_iter().next().unwrap().span().source_file();
+    assert_eq!(source_file.path(), Path::new("src/lib.rs"));
+    assert!(source_file.is_real());
+
+    let path = env::temp_dir().join("proc_macro0_named_source_file.rs");
+    fs::write(&path, "struct S;\n{").unwrap();
+    let err = TokenStream::from_file(&path).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert!(err.get_ref().unwrap().is::<LexError>());
+
+    fs::write(&path, "struct S;\nstruct T;").unwrap();
+    let map = SourceMap::new();
+    let tokens = map
+        .parse_file(&path)
+        .unwrap()
+        .into_iter()
+        .collect::<Vec<_>>();
+    fs::remove_file(&path).unwrap();
+    let span = tokens[4].span();
+    assert_eq!(span.source_file().path(), path);
+    assert!(span.source_file().is_real());
+    assert_eq!(span.start().line, 2);
+}
+
+#[test]
+fn scoped_source_map() {
+    let map = SourceMap::new();
+    let tokens = map
+        .parse_token_stream("aaa\nbbb")
+        .unwrap()
+        .into_iter()
+        .collect::<Vec<_>>();
+    let other = "aaa"
+        .parse::<TokenStream>()
+        .unwrap()
+        .into_iter()
+        .next()
+        .unwrap();
+
+    let joined = tokens[0].span().join(tokens[1].span()).unwrap();
+    assert_eq!(joined.end().line, 2);
+    assert_eq!(joined.end().column, 3);
+    assert!(tokens[0].span().join(other.span()).is_none());
+    assert_eq!(
+        tokens[0].span().source_file().path().to_string_lossy(),
+        "<parsed string 1>"
+    );
+
+    let literal = map.parse_literal("\"lit\"").unwrap();
+    assert_eq!(literal.to_string(), "\"lit\"");
+
+    drop(map);
+    let start = tokens[1].span().start();
+    assert_eq!(start.line, 1);
+    assert_eq!(start.column, 0);
+    assert_eq!(
+        tokens[1].span().source_file(),
+        Span::call_site().source_file()
+    );
+    assert!(tokens[0].span().join(tokens[1].span()).is_none());
+}
+
+#[test]
+fn many_source_files() {
+    let map = SourceMap::new();
+    let mut spans = Vec::new();
+    for i in 0..1000 {
+        // Empty files start and end at the same position.
+        map.parse_token_stream("").unwrap();
+        let src = format!("{}x{}", "\n".repeat(i % 7), i);
+        let name = format!("file{i}.rs");
+        let tokens = map.parse_token_stream_named(&name, &src).unwrap();
+        spans.push((name, i % 7, tokens.into_iter().next().unwrap().span()));
+    }
+    for (i, (name, line, span)) in spans.iter().enumerate() {
+        assert_eq!(span.source_file().path(), Path::new(name));
+        assert_eq!(
+            span.start(),
+            LineColumn {
+                line: line + 1,
+                column: 0
+            }
+        );
+        assert_eq!(span.source_text().unwrap(), format!("x{i}"));
+        if let Some((_, _, next)) = spans.get(i + 1) {
+            assert!(span.join(*next).is_none());
+        }
+    }
+}
+
+#[test]
+fn parallel_source_files() {
+    let map = SourceMap::new();
+    let spans = std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..8)
+            .map(|t| {
+                let map = &map;
+                scope.spawn(move || {
+                    let mut spans = Vec::new();
+                    for i in 0..200 {
+                        let src = format!("{}t{}_{}", "\n".repeat(i % 3), t, i);
+                        let tokens = if i % 2 == 0 {
+                            TokenStream::from_str(&src).unwrap()
+                        } else {
+                            map.parse_token_stream(&src).unwrap()
+                        };
+                        let span = tokens.into_iter().next().unwrap().span();
+                        spans.push((format!("t{t}_{i}"), i % 3, i % 2 == 1, span));
+                    }
+                    spans
+                })
+            })
+            .collect();
+        let spans: Vec<_> = handles
+            .into_iter()
+            .flat_map(|h| h.join().unwrap())
+            .collect();
+        spans
+    });
+
+    // Files in the same source map are all numbered differently.
+    let mut files = Vec::new();
+    for (text, line, scoped, span) in &spans {
+        assert_eq!(span.source_text().as_ref(), Some(text));
+        assert_eq!(span.start().line, line + 1);
+        if *scoped {
+            files.push(span.source_file());
+        }
+    }
+    for (i, file) in files.iter().enumerate() {
+        assert!(!files[i + 1..].contains(file));
+    }
+    assert_eq!(format!("{map:?}"), "SourceMap { files: 800, .. }");
+}
+
 #[test]
 fn no_panic() {
     let s = str::from_utf8(b"b\'\xc2\x86  \x00\x00\x00^\"").unwrap();
@@ -402,9 +1453,8 @@ fn joint_last_token() {
 
     let joint_punct = Punct::new(':', Spacing::Joint);
     let stream = TokenStream::from(TokenTree::Punct(joint_punct));
-    let punct = match stream.into_iter().next().unwrap() {
-        TokenTree::Punct(punct) => punct,
-        _ => unreachable!(),
+    let Some(TokenTree::Punct(punct)) = stream.into_iter().next() else {
+        unreachable!();
     };
     assert_eq!(punct.spacing(), Spacing::Joint);
 }
@@ -425,7 +1475,7 @@ fn test_debug_ident() {
 
     let expected = "Ident { sym: proc_macro }";
 
-    assert_eq!(expected, format!("{:?}", ident));
+    assert_eq!(expected, format!("{ident:?}"));
 }
 
 #[test]
@@ -480,7 +1530,7 @@ TokenStream [
 ]\
     ";
 
-    let actual = format!("{:#?}", tts);
+    let actual = format!("{tts:#?}");
     if actual.ends_with(",\n]") {
         assert_eq!(expected, actual);
     } else {
@@ -519,8 +1569,8 @@ fn non_ascii_tokens() {
     check_spans("/*** ábc */ x", &[(1, 12, 1, 13)]);
     check_spans(r#""abc""#, &[(1, 0, 1, 5)]);
     check_spans(r#""ábc""#, &[(1, 0, 1, 5)]);
-    check_spans(r###"r#"abc"#"###, &[(1, 0, 1, 8)]);
-    check_spans(r###"r#"ábc"#"###, &[(1, 0, 1, 8)]);
+    check_spans(r##"r#"abc"#"##, &[(1, 0, 1, 8)]);
+    check_spans(r##"r#"ábc"#"##, &[(1, 0, 1, 8)]);
     check_spans("r#\"a\nc\"#", &[(1, 0, 2, 3)]);
     check_spans("r#\"á\nc\"#", &[(1, 0, 2, 3)]);
     check_spans("'a'", &[(1, 0, 1, 3)]);
@@ -561,12 +1611,12 @@ fn check_spans_internal(ts: TokenStream, lines: &mut &[(usize, usize, usize, usi
             *lines = rest;
 
             let start = i.span().start();
-            assert_eq!(start.line, sline, "sline did not match for {}", i);
-            assert_eq!(start.column, scol, "scol did not match for {}", i);
+            assert_eq!(start.line, sline, "sline did not match for {i}");
+            assert_eq!(start.column, scol, "scol did not match for {i}");
 
             let end = i.span().end();
-            assert_eq!(end.line, eline, "eline did not match for {}", i);
-            assert_eq!(end.column, ecol, "ecol did not match for {}", i);
+            assert_eq!(end.line, eline, "eline did not match for {i}");
+            assert_eq!(end.column, ecol, "ecol did not match for {i}");
 
             if let TokenTree::Group(g) = i {
                 check_spans_internal(g.stream().clone(), lines);
@@ -0,0 +1,278 @@
+use proc_macro0::{
+    ColumnEncoding, EncodeOptions, LexError, LexErrorKind, LexOptions, LineColumn,
+    SourceMap, Span, TokenStream, TokenTree,
+};
+use std::io;
+use std::str::FromStr;
+
+fn encode(tokens: &TokenStream, options: EncodeOptions) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    tokens.encode_with(&mut bytes, &options).unwrap();
+    bytes
+}
+
+fn assert_same_spans(a: TokenStream, b: TokenStream) {
+    let (a, b): (Vec<_>, Vec<_>) = (a.into_iter().collect(), b.into_iter().collect());
+    assert_eq!(a.len(), b.len());
+    for (a, b) in a.into_iter().zip(b) {
+        let (sa, sb) = (a.span(), b.span());
+        assert_eq!(sa.start(), sb.start(), "{a}");
+        assert_eq!(sa.end(), sb.end(), "{a}");
+        assert_eq!(sa.source_file(), sb.source_file(), "{a}");
+        if let (TokenTree::Group(a), TokenTree::Group(b)) = (a, b) {
+            assert_same_spans(a.stream(), b.stream());
+        }
+    }
+}
+
+fn group(tt: Option<TokenTree>) -> TokenStream {
+    match tt {
+        Some(TokenTree::Group(group)) => group.stream(),
+        _ => panic!("expected a group"),
+    }
+}
+
+#[test]
+fn roundtrip() {
+    let src = "#[derive(Debug)] pub struct S<'a> { r#type: &'a [u8; 2], x: (f32, char) } \
+               const C: &str = r#\"\"#; 'x'; b\"\\xff\" 1e3f64 -1i8 a+=b; ...";
+    let tokens = TokenStream::from_str(src).unwrap();
+    let bytes = encode(&tokens, EncodeOptions::new());
+    let decoded = TokenStream::decode(&mut &bytes[..]).unwrap();
+    assert_eq!(decoded.to_string(), tokens.to_string());
+    for tt in decoded {
+        assert_eq!(tt.span().start(), Span::call_site().start());
+    }
+}
+
+#[test]
+fn roundtrip_spans() {
+    let map = SourceMap::new();
+    let src = "fn main() {\n    println!(\"é {}\", 1);\n}\n";
+    let tokens = map.parse_token_stream_named("src/main.rs", src).unwrap();
+    let bytes = encode(&tokens, EncodeOptions::new().spans(true));
+
+    let decoded = TokenStream::decode(&mut &bytes[..]).unwrap();
+    assert_same_spans(decoded.clone(), tokens.clone());
+    let file = decoded
+        .clone()
+        .into_iter()
+        .next()
+        .unwrap()
+        .span()
+        .source_file();
+    assert_eq!(file.path().to_str(), Some("src/main.rs"));
+    assert!(file.is_real());
+
+    // Decoded files have no source text, so only columns in chars are known.
+    let literal = |tokens: TokenStream| {
+        let body = group(tokens.into_iter().nth(3));
+        let args = group(body.into_iter().nth(2));
+        args.into_iter().nth(2).unwrap().span()
+    };
+    let span = literal(tokens.clone());
+    assert_eq!(span.source_text().as_deref(), Some("1"));
+    assert_eq!(span.byte_range(), 34..35);
+    let mut utf8 = span.start();
+    utf8.column += 1;
+    assert_eq!(span.start_in(ColumnEncoding::Utf8), Some(utf8));
+    let span = literal(decoded.clone());
+    assert_eq!(span.source_text(), None);
+    assert_eq!(span.byte_range(), 0..0);
+    let chars = span.start();
+    assert_eq!(span.start_in(ColumnEncoding::Chars), Some(chars));
+    assert_eq!(span.start_in(ColumnEncoding::Utf8), None);
+    let found = span.at_line_column(chars, ColumnEncoding::Chars).unwrap();
+    assert_eq!(found.start(), chars);
+    assert_eq!(span.at_line_column(chars, ColumnEncoding::Utf8), None);
+
+    let other = SourceMap::new();
+    let decoded = other.decode_token_stream(&mut &bytes[..]).unwrap();
+    assert_same_spans(decoded.clone(), tokens);
+    drop(other);
+    let span = decoded.into_iter().next().unwrap().span();
+    assert_eq!(span.source_file(), Span::call_site().source_file());
+}
+
+#[test]
+fn roundtrip_trivia() {
+    let src = "/// doc\n//! inner\nfn f() { /* a */ g( 1 ); }\n// end\n";
+    for options in &[
+        LexOptions::new().preserve_trivia(true),
+        LexOptions::new()
+            .preserve_trivia(true)
+            .keep_doc_comments(true),
+        LexOptions::new().keep_doc_comments(true),
+    ] {
+        let tokens = TokenStream::from_str_with(src, options).unwrap();
+        let bytes = encode(&tokens, EncodeOptions::new());
+        let decoded = TokenStream::decode(&mut &bytes[..]).unwrap();
+        assert_eq!(decoded.to_string(), tokens.to_string());
+    }
+}
+
+#[test]
+fn interned_strings() {
+    let one = TokenStream::from_str("identifier").unwrap();
+    let many = TokenStream::from_str(&"identifier ".repeat(100)).unwrap();
+    let one = encode(&one, EncodeOptions::new());
+    let many = encode(&many, EncodeOptions::new());
+    assert!(many.len() - one.len() <= 99 * 3);
+}
+
+#[test]
+fn invalid() {
+    let tokens = TokenStream::from_str("f(x)").unwrap();
+    let bytes = encode(&tokens, EncodeOptions::new().spans(true));
+
+    let err = TokenStream::decode(&mut &b"nope"[..]).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+    let mut version = bytes.clone();
+    version[4] = 2;
+    let err = TokenStream::decode(&mut &version[..]).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert_eq!(
+        err.to_string(),
+        "unsupported token stream encoding version 2"
+    );
+
+    for len in 0..bytes.len() {
+        assert!(TokenStream::decode(&mut &bytes[..len]).is_err());
+    }
+
+    // Trivia has to be only whitespace and comments.
+    let options = LexOptions::new().preserve_trivia(true);
+    let tokens = TokenStream::from_str_with("a b", &options).unwrap();
+    let mut bytes = encode(&tokens, EncodeOptions::new());
+    let space = bytes.iter().position(|&b| b == b' ').unwrap();
+    bytes[space] = b'x';
+    let err = TokenStream::decode(&mut &bytes[..]).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert_eq!(err.to_string(), "invalid trivia");
+}
+
+#[test]
+fn decode_one_stream() {
+    let mut bytes = encode(&TokenStream::from_str("a").unwrap(), EncodeOptions::new());
+    bytes.extend(encode(
+        &TokenStream::from_str("b c").unwrap(),
+        EncodeOptions::new(),
+    ));
+    bytes.push(0xff);
+
+    let mut reader = &bytes[..];
+    assert_eq!(TokenStream::decode(&mut reader).unwrap().to_string(), "a");
+    assert_eq!(TokenStream::decode(&mut reader).unwrap().to_string(), "b c");
+    assert_eq!(reader, [0xff]);
+}
+
+/// Uses up `n` positions of `map`, as if a file of `n - 1` chars had been
+/// lexed into it, by decoding an ident which spans the last char of a file
+/// with no source text. Returns `false` if fewer than `n` positions are left.
+fn fill(map: &SourceMap, n: u32) -> bool {
+    fn varint(bytes: &mut Vec<u8>, mut value: u32) {
+        while value >= 0x80 {
+            bytes.push(value.to_le_bytes()[0] | 0x80);
+            value >>= 7;
+        }
+        bytes.push(value.to_le_bytes()[0]);
+    }
+    let mut bytes = b"PM0T\x01\x01\x02\x04a.rs\x01x\x01\x00\x00".to_vec();
+    varint(&mut bytes, n - 1);
+    bytes.extend_from_slice(b"\x01\x00\x01\x01\x00\x01");
+    varint(&mut bytes, n - 2);
+    bytes.extend_from_slice(b"\x01\x01\x00");
+    map.decode_token_stream(&mut &bytes[..]).is_ok()
+}
+
+#[test]
+fn source_map_full() {
+    let map = SourceMap::new();
+    // Positions start at 1, so this leaves 10 positions free, from
+    // `u32::MAX - 9`. Each file takes one more than its length.
+    assert!(fill(&map, u32::MAX - 10));
+    assert!(!fill(&map, 11));
+
+    let abc = map.parse_token_stream("abc").unwrap();
+    let span = abc.into_iter().next().unwrap().span();
+    assert_eq!(span.start(), LineColumn { line: 1, column: 0 });
+    assert_eq!(span.end(), LineColumn { line: 1, column: 3 });
+    assert_eq!(span.byte_range(), 0..3);
+
+    let err = map.parse_token_stream("xxxxx").unwrap_err();
+    assert_eq!(err.kind(), LexErrorKind::SourceMapFull);
+    assert_eq!(err.to_string(), "source map is full");
+    let last = map.parse_token_stream("xxxx").unwrap();
+    let last = last.into_iter().next().unwrap().span();
+    assert_eq!(last.source_text().as_deref(), Some("xxxx"));
+
+    // Nothing else fits, not even an empty file, and earlier spans are left
+    // intact.
+    let err = map.parse_token_stream("").unwrap_err();
+    assert_eq!(err.kind(), LexErrorKind::SourceMapFull);
+    let err = map.parse_literal("1").unwrap_err();
+    assert_eq!(err.kind(), LexErrorKind::SourceMapFull);
+    let other = SourceMap::new();
+    let x = other.parse_token_stream("x").unwrap();
+    let bytes = encode(&x, EncodeOptions::new().spans(true));
+    let err = map.decode_token_stream(&mut &bytes[..]).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    let lex_err = err.get_ref().unwrap().downcast_ref::<LexError>().unwrap();
+    assert_eq!(lex_err.kind(), LexErrorKind::SourceMapFull);
+    assert_eq!(span.source_text().as_deref(), Some("abc"));
+    // Refused files are not numbered, so `xxxx` comes right after the file
+    // that filled the map and `abc`.
+    let path = last.source_file().path();
+    assert_eq!(path.to_str(), Some("<parsed string 3>"));
+    assert!(span.join(last).is_none());
+
+    // Other source maps are unaffected, so lexing can go on in a new one.
+    let fresh = SourceMap::new();
+    let tokens = fresh.parse_token_stream("xxxxx").unwrap();
+    let span = tokens.into_iter().next().unwrap().span();
+    assert_eq!(span.source_text().as_deref(), Some("xxxxx"));
+}
+
+#[test]
+fn untrusted_file_lengths() {
+    // One file claiming to be `u32::MAX` chars long, and one ident `x`
+    // pointing at its first char.
+    let mut bytes = b"PM0T\x01\x01\x02\x04a.rs\x01x\x01\x00\x00".to_vec();
+    bytes.extend_from_slice(b"\xff\xff\xff\xff\x0f\x01\x00");
+    bytes.extend_from_slice(b"\x01\x01\x00\x01\x00\x01\x01\x00");
+
+    let map = SourceMap::new();
+    let tokens = map.decode_token_stream(&mut &bytes[..]).unwrap();
+    assert_eq!(tokens.to_string(), "x");
+    let span = tokens.into_iter().next().unwrap().span();
+    assert_eq!(span.source_file().path().to_str(), Some("a.rs"));
+    assert_eq!(span.end(), LineColumn { line: 1, column: 1 });
+
+    // Only the chars up to the end of the span were recorded.
+    assert!(fill(&map, u32::MAX - 10));
+    assert!(map.parse_token_stream("abc").is_ok());
+}
+
+/// Encodes `depth` empty parenthesized groups, each inside the one before.
+fn nested(depth: usize) -> Vec<u8> {
+    let mut bytes = b"PM0T\x01\x00\x00".to_vec();
+    for _ in 0..depth {
+        bytes.extend_from_slice(b"\x01\x00\x00\x00\x00");
+    }
+    bytes.extend_from_slice(b"\x00\x00");
+    bytes.resize(bytes.len() + depth, 0);
+    bytes
+}
+
+#[test]
+fn nesting_limit() {
+    let tokens = TokenStream::decode(&mut &nested(512)[..]).unwrap();
+    let printed = tokens.to_string();
+    assert_eq!(printed.matches('(').count(), 512);
+
+    for depth in [513, 1_000_000] {
+        let err = TokenStream::decode(&mut &nested(depth)[..]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -1,7 +1,12 @@
 #![allow(clippy::from_iter_instead_of_collect)]
 
-use proc_macro0::{Delimiter, Group, Ident, Span, TokenStream, TokenTree};
+use proc_macro0::{
+    Delimiter, Group, Ident, LexOptions, PrettyOptions, Span, TokenStream, TokenTree,
+};
+use std::fs;
 use std::iter::{self, FromIterator};
+use std::path::Path;
+use std::str::FromStr;
 
 #[test]
 fn test_fmt_group() {
@@ -26,3 +31,70 @@ fn test_fmt_group() {
     assert_eq!("", none_empty.to_string());
     assert_eq!("x", none_nonempty.to_string());
 }
+
+#[test]
+fn test_fmt_pretty() {
+    let tokens = TokenStream::from_str(
+        "#[derive(Debug)] struct S<T> { a: Vec<T>, b: u8 } \
+         impl<T> S<T> where T: Clone, { fn f(&self, x: &mut u8) -> Option<u8> { \
+         let y = self.a.len() as u8 + *x; if y > 1 { return None; } else { vec![1, 2]; } \
+         Some(-y) } }",
+    )
+    .unwrap();
+    let expected = "\
+#[derive(Debug)]
+struct S<T> {
+    a: Vec<T>,
+    b: u8
+}
+impl<T> S<T> where T: Clone, {
+    fn f(&self, x: &mut u8) -> Option<u8> {
+        let y = self.a.len() as u8 + *x;
+        if y > 1 {
+            return None;
+        } else {
+            vec![1, 2];
+        }
+        Some(-y)
+    }
+}";
+    assert_eq!(tokens.pretty(&PrettyOptions::new()).to_string(), expected);
+    assert_eq!(format!("{tokens:#}"), expected);
+
+    let narrow = PrettyOptions::new().indent(2).max_width(20);
+    let tokens = TokenStream::from_str("f(first_argument, [1, 2], g(x))").unwrap();
+    assert_eq!(
+        tokens.pretty(&narrow).to_string(),
+        "f(\n  first_argument,\n  [1, 2],\n  g(x)\n)",
+    );
+}
+
+#[test]
+fn test_fmt_pretty_roundtrip() {
+    let sources = [
+        "a+=b; a + = b; x . 0 . 1; 1 . 0; r#raw; 'a: loop {} & 'a T; - -1; a..b; x::<u8>()",
+        "#![inner] /// doc\nfn f() {} a<b; c>=d; e<<f; x -> y; m!(); !x; a !(b)",
+    ];
+    let options = LexOptions::new().keep_doc_comments(true);
+    for src in &sources {
+        for options in &[LexOptions::new(), options] {
+            check_pretty(&TokenStream::from_str_with(src, options).unwrap(), *options);
+        }
+    }
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("src");
+    for entry in fs::read_dir(dir).unwrap() {
+        let src = fs::read_to_string(entry.unwrap().path()).unwrap();
+        check_pretty(
+            &TokenStream::from_str_with(&src, &options).unwrap(),
+            options,
+        );
+    }
+}
+
+fn check_pretty(tokens: &TokenStream, options: LexOptions) {
+    for pretty in &[PrettyOptions::new(), PrettyOptions::new().max_width(0)] {
+        let printed = tokens.pretty(pretty).to_string();
+        let reparsed = TokenStream::from_str_with(&printed, &options).unwrap();
+        assert_eq!(reparsed.to_string(), tokens.to_string(), "{printed}");
+    }
+}
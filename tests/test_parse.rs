@@ -0,0 +1,253 @@
+use proc_macro0::{
+    Cursor, Delimiter, Group, Ident, Literal, Parse, ParseError, Punct, SourceMap, Span,
+    TokenStream, TokenTree,
+};
+use std::str::FromStr;
+use std::thread;
+
+fn lex(src: &str) -> TokenStream {
+    TokenStream::from_str(src).unwrap()
+}
+
+#[test]
+fn navigate() {
+    let tokens = lex("a (b [c] d) e");
+    let mut cursor = tokens.cursor();
+    assert_eq!(cursor.ident().unwrap(), "a");
+    assert!(cursor.ident().is_none());
+
+    let group = cursor.enter().unwrap();
+    assert_eq!(group.delimiter(), Delimiter::Parenthesis);
+    assert_eq!(cursor.ident().unwrap(), "b");
+    assert_eq!(cursor.enter().unwrap().delimiter(), Delimiter::Bracket);
+    assert_eq!(cursor.ident().unwrap(), "c");
+    assert!(cursor.eof());
+    assert_eq!(cursor.exit().unwrap().delimiter(), Delimiter::Bracket);
+    assert!(!cursor.eof());
+
+    // Exiting skips the rest of the group.
+    assert_eq!(cursor.exit().unwrap().delimiter(), Delimiter::Parenthesis);
+    assert_eq!(cursor.ident().unwrap(), "e");
+    assert!(cursor.eof());
+    assert!(cursor.exit().is_none());
+    assert!(cursor.peek().is_none());
+}
+
+#[test]
+fn step() {
+    let tokens = lex("x + 'c' [y]");
+    let mut cursor = tokens.cursor();
+    assert!(cursor.punct('+').is_none());
+    cursor.advance();
+    assert_eq!(cursor.punct('+').unwrap().as_char(), '+');
+    assert_eq!(cursor.literal().unwrap().to_string(), "'c'");
+    assert!(cursor.group(Delimiter::Brace).is_none());
+    let group = cursor.group(Delimiter::Bracket).unwrap();
+    assert_eq!(group.stream().to_string(), "y");
+    assert!(cursor.eof());
+    cursor.advance();
+    assert!(cursor.eof());
+
+    let strings: Vec<String> = tokens.cursor().map(TokenTree::to_string).collect();
+    assert_eq!(strings, ["x", "+", "'c'", "[y]"]);
+}
+
+#[test]
+fn none_groups() {
+    let inner = Group::new(Delimiter::None, lex("b c"));
+    let empty = Group::new(Delimiter::None, TokenStream::new());
+    let nested = Group::new(
+        Delimiter::None,
+        TokenStream::from(TokenTree::Group(inner.clone())),
+    );
+    let mut stream = lex("a");
+    stream.extend(vec![
+        TokenTree::Group(nested),
+        TokenTree::Group(empty.clone()),
+    ]);
+    stream.extend(lex("d"));
+    stream.extend(vec![TokenTree::Group(empty)]);
+
+    let strings: Vec<String> = stream.cursor().map(TokenTree::to_string).collect();
+    assert_eq!(strings, ["a", "b", "c", "d"]);
+
+    // A None group does not count as a group to exit.
+    let mut wrapped = TokenStream::new();
+    wrapped.extend(vec![TokenTree::Group(inner)]);
+    let mut cursor = wrapped.cursor();
+    cursor.advance();
+    assert!(cursor.exit().is_none());
+    assert_eq!(cursor.ident().unwrap(), "c");
+    assert!(cursor.eof());
+}
+
+#[test]
+fn parse_none_groups() {
+    // `2 * $e` with `$e` substituted by `a + b`.
+    let sum = Group::new(Delimiter::None, lex("a + b"));
+    let mut tokens = lex("2 *");
+    tokens.extend(vec![TokenTree::Group(sum)]);
+    let none_group = |tt: Option<TokenTree>| match tt {
+        Some(TokenTree::Group(group)) if group.delimiter() == Delimiter::None => {
+            group.stream().to_string()
+        }
+        tt => panic!("expected a None group, got {:?}", tt),
+    };
+
+    let mut cursor = tokens.cursor();
+    let rest: TokenStream = cursor.parse().unwrap();
+    assert!(cursor.eof());
+    assert_eq!(rest.clone().into_iter().count(), 3);
+    assert_eq!(none_group(rest.into_iter().nth(2)), "a + b");
+
+    let mut cursor = tokens.cursor();
+    cursor.advance();
+    cursor.advance();
+    assert_eq!(none_group(cursor.parse().ok()), "a + b");
+    assert!(cursor.eof());
+
+    // So is a group.
+    let mut cursor = tokens.cursor();
+    cursor.advance();
+    cursor.advance();
+    let group: Group = cursor.parse().unwrap();
+    assert_eq!(group.delimiter(), Delimiter::None);
+    assert_eq!(group.stream().to_string(), "a + b");
+    assert!(cursor.eof());
+
+    // Part way through the group, the rest of it is kept in a group.
+    let mut cursor = tokens.cursor();
+    for _ in 0..3 {
+        cursor.advance();
+    }
+    let mut fork = cursor.clone();
+    let rest: TokenStream = fork.parse().unwrap();
+    assert!(fork.eof());
+    assert_eq!(none_group(rest.into_iter().next()), "+ b");
+    let tt: TokenTree = cursor.parse().unwrap();
+    assert_eq!(tt.to_string(), "+");
+
+    // Nested groups starting at the same token are parsed as the outermost.
+    let inner = Group::new(Delimiter::None, lex("b c"));
+    let outer = Group::new(Delimiter::None, TokenStream::from(TokenTree::Group(inner)));
+    let mut tokens = TokenStream::from(TokenTree::Group(outer));
+    tokens.extend(lex("d"));
+    let mut cursor = tokens.cursor();
+    match cursor.parse().unwrap() {
+        TokenTree::Group(outer) => {
+            assert_eq!(outer.delimiter(), Delimiter::None);
+            assert_eq!(none_group(outer.stream().into_iter().next()), "b c");
+        }
+        tt => panic!("expected a group, got {}", tt),
+    }
+    assert_eq!(cursor.ident().unwrap(), "d");
+}
+
+#[test]
+fn fork() {
+    let tokens = lex("a b c");
+    let mut cursor = tokens.cursor();
+    let mut fork = cursor.clone();
+    fork.advance();
+    fork.advance();
+    assert_eq!(cursor.ident().unwrap(), "a");
+    assert_eq!(fork.ident().unwrap(), "c");
+    cursor = fork;
+    assert!(cursor.eof());
+}
+
+#[test]
+fn parse_tokens() {
+    let tokens = lex("f 'x' + (1) /// doc\n g h");
+    let mut cursor = tokens.cursor();
+    let ident: Ident = cursor.parse().unwrap();
+    assert_eq!(ident, "f");
+    let literal: Literal = cursor.parse().unwrap();
+    assert_eq!(literal.to_string(), "'x'");
+    let punct: Punct = cursor.parse().unwrap();
+    assert_eq!(punct.as_char(), '+');
+    let group: Group = cursor.parse().unwrap();
+    assert_eq!(group.stream().to_string(), "1");
+    let tt: TokenTree = cursor.parse().unwrap();
+    assert_eq!(tt.to_string(), "#");
+    cursor.advance();
+    let rest: Vec<Ident> = cursor.parse().unwrap();
+    assert_eq!(rest, ["g", "h"]);
+    assert!(cursor.parse::<TokenTree>().is_err());
+
+    let tokens = lex("a b c");
+    let mut cursor = tokens.cursor();
+    cursor.advance();
+    let rest: TokenStream = cursor.parse().unwrap();
+    assert_eq!(rest.to_string(), "b c");
+}
+
+#[test]
+fn errors() {
+    let map = SourceMap::new();
+    let tokens = map
+        .parse_token_stream_named("lib.rs", "x\n  (1 2)")
+        .unwrap();
+    let mut cursor = tokens.cursor();
+    let err = cursor.parse::<Literal>().unwrap_err();
+    assert_eq!(err.to_string(), "expected a literal");
+    assert_eq!(err.message(), "expected a literal");
+    assert_eq!(err.span().start().line, 1);
+
+    cursor.advance();
+    cursor.enter();
+    cursor.advance();
+    cursor.advance();
+    let err = cursor.parse::<Ident>().unwrap_err();
+    assert_eq!(err.to_string(), "expected an identifier");
+    let start = err.span().start();
+    assert_eq!((start.line, start.column), (2, 6));
+
+    let err = tokens.parse::<Ident>().unwrap_err();
+    assert_eq!(err.to_string(), "unexpected token");
+    assert_eq!(err.span().start().line, 2);
+
+    let err = TokenStream::new().parse::<Ident>().unwrap_err();
+    assert_eq!(err.span().source_file(), Span::call_site().source_file());
+}
+
+// `key = value, ...`
+#[derive(Debug)]
+struct Fields(Vec<(Ident, Literal)>);
+
+impl Parse for Fields {
+    fn parse(input: &mut Cursor) -> Result<Self, ParseError> {
+        let mut fields = Vec::new();
+        while !input.eof() {
+            let key = input.parse()?;
+            if input.punct('=').is_none() {
+                return Err(input.error("expected `=`"));
+            }
+            fields.push((key, input.parse()?));
+            if !input.eof() && input.punct(',').is_none() {
+                return Err(input.error("expected `,`"));
+            }
+        }
+        Ok(Fields(fields))
+    }
+}
+
+#[test]
+fn threads() {
+    let tokens = lex("a = 1, b = \"x\", c = 'c'");
+    thread::scope(|scope| {
+        let handles: Vec<_> = (0..4)
+            .map(|_| scope.spawn(|| tokens.parse::<Fields>().unwrap()))
+            .collect();
+        for handle in handles {
+            let fields = handle.join().unwrap();
+            assert_eq!(fields.0.len(), 3);
+            assert_eq!(fields.0[2].0, "c");
+        }
+    });
+
+    let err = lex("a = 1 b").parse::<Fields>().unwrap_err();
+    assert_eq!(err.to_string(), "expected `,`");
+    let err = lex("a 1").parse::<Fields>().unwrap_err();
+    assert_eq!(err.to_string(), "expected `=`");
+}
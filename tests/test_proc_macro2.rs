@@ -0,0 +1,139 @@
+#![cfg(feature = "proc-macro2")]
+
+use proc_macro0::{
+    ColumnEncoding, Group, Ident, LexErrorKind, LexOptions, Literal, SourceMap, Span,
+    TokenStream, TokenTree,
+};
+use std::convert::TryFrom;
+use std::str::FromStr;
+
+#[test]
+fn roundtrip() {
+    let src = "#[derive(Debug)] struct S<'a> { r#type: &'a [u8; 2], x: (f32, char) } \
+               const C: &str = r#\"\"#; 'x'; b\"\\xff\" 1e3f64 -1i8 a+=b";
+    let tokens = TokenStream::from_str(src).unwrap();
+    let converted = proc_macro2::TokenStream::from(tokens.clone());
+    assert_eq!(converted.to_string(), tokens.to_string());
+
+    let back = TokenStream::try_from(converted).unwrap();
+    assert_eq!(back, tokens);
+    assert_eq!(back.to_string(), tokens.to_string());
+}
+
+#[test]
+fn doc_comments() {
+    let options = LexOptions::new().keep_doc_comments(true);
+    let tokens = TokenStream::from_str_with("/// doc\nfn f() {}", &options).unwrap();
+    let converted = proc_macro2::TokenStream::from(tokens);
+    assert_eq!(converted.to_string(), "# [doc = \" doc\"] fn f () { }");
+}
+
+#[test]
+fn idents() {
+    let raw = Ident::new_raw("type", Span::call_site());
+    let converted = proc_macro2::Ident::from(raw);
+    assert_eq!(converted.to_string(), "r#type");
+    assert_eq!(Ident::from(converted).to_string(), "r#type");
+
+    let literal = Literal::string("a\"b");
+    let converted = proc_macro2::Literal::from(literal);
+    assert_eq!(converted.to_string(), "\"a\\\"b\"");
+    let back = Literal::try_from(converted).unwrap();
+    assert_eq!(back.to_string(), "\"a\\\"b\"");
+}
+
+#[test]
+fn unsupported_literals() {
+    let tokens = proc_macro2::TokenStream::from_str("f(c\"x\")").unwrap();
+    let err = TokenStream::try_from(tokens.clone()).unwrap_err();
+    assert_eq!(err.kind(), LexErrorKind::InvalidLiteral);
+    let err = SourceMap::new().convert_token_stream(tokens).unwrap_err();
+    assert_eq!(err.kind(), LexErrorKind::InvalidLiteral);
+
+    let literal = proc_macro2::Literal::from_str("c\"x\"").unwrap();
+    let err = Literal::try_from(literal).unwrap_err();
+    assert_eq!(err.kind(), LexErrorKind::InvalidLiteral);
+
+    // Raw byte strings are ASCII only, like in `proc_macro2`.
+    let err = TokenStream::from_str("br\"é'a;c\"").unwrap_err();
+    assert_eq!(err.kind(), LexErrorKind::InvalidLiteral);
+    assert!(proc_macro2::TokenStream::from_str("br\"é'a;c\"").is_err());
+}
+
+#[test]
+fn spans() {
+    let src = "fn f() {\n    x + 1\n}";
+    let tokens = proc_macro2::TokenStream::from_str(src).unwrap();
+    let map = SourceMap::new();
+    let converted = map.convert_token_stream(tokens.clone()).unwrap();
+
+    let trees: Vec<TokenTree> = converted.into_iter().collect();
+    let group = match &trees[3] {
+        TokenTree::Group(group) => group,
+        tt => panic!("expected a group, got {}", tt),
+    };
+    assert_eq!(group.span().start().line, 1);
+    assert_eq!(group.span().start().column, 7);
+    assert_eq!(group.span().end().line, 3);
+    assert_eq!(group.span().end().column, 1);
+
+    let inner: Vec<TokenTree> = group.stream().into_iter().collect();
+    let x = inner[0].span();
+    assert_eq!((x.start().line, x.start().column), (2, 4));
+    assert_eq!((x.end().line, x.end().column), (2, 5));
+    assert_eq!(x.byte_range(), 0..0);
+    assert_eq!(x.start_in(ColumnEncoding::Utf8), None);
+    let one = inner[2].span();
+    assert_eq!((one.start().line, one.start().column), (2, 8));
+    assert_eq!(x.source_file(), one.source_file());
+    assert!(!x.source_file().is_real());
+    assert_ne!(x.source_file(), Span::call_site().source_file());
+
+    // Without a source map, every span becomes the call site.
+    let call_site = Span::call_site().source_file();
+    let converted = TokenStream::try_from(tokens.clone()).unwrap();
+    assert!(converted
+        .into_iter()
+        .all(|tt| tt.span().source_file() == call_site));
+    let group = tokens.into_iter().nth(3).unwrap();
+    let group = match group {
+        proc_macro2::TokenTree::Group(group) => group,
+        tt => panic!("expected a group, got {}", tt),
+    };
+    let ident = match group.stream().into_iter().next().unwrap() {
+        proc_macro2::TokenTree::Ident(ident) => ident,
+        tt => panic!("expected an ident, got {}", tt),
+    };
+    assert_eq!(Ident::from(ident.clone()).span().source_file(), call_site);
+    assert_eq!(Span::from(ident.span()).source_file(), call_site);
+    let group = Group::try_from(group).unwrap();
+    assert_eq!(group.span().source_file(), call_site);
+}
+
+/// Uses up `n` positions of `map`, as if a file of `n - 1` chars had been
+/// lexed into it, by decoding an ident which spans the last char of a file
+/// with no source text. Returns `false` if fewer than `n` positions are left.
+fn fill(map: &SourceMap, n: u32) -> bool {
+    fn varint(bytes: &mut Vec<u8>, mut value: u32) {
+        while value >= 0x80 {
+            bytes.push(value.to_le_bytes()[0] | 0x80);
+            value >>= 7;
+        }
+        bytes.push(value.to_le_bytes()[0]);
+    }
+    let mut bytes = b"PM0T\x01\x01\x02\x04a.rs\x01x\x01\x00\x00".to_vec();
+    varint(&mut bytes, n - 1);
+    bytes.extend_from_slice(b"\x01\x00\x01\x01\x00\x01");
+    varint(&mut bytes, n - 2);
+    bytes.extend_from_slice(b"\x01\x01\x00");
+    map.decode_token_stream(&mut &bytes[..]).is_ok()
+}
+
+#[test]
+fn source_map_full() {
+    let tokens = proc_macro2::TokenStream::from_str("x").unwrap();
+    let map = SourceMap::new();
+    assert!(fill(&map, u32::MAX - 1));
+    let err = map.convert_token_stream(tokens).unwrap_err();
+    assert_eq!(err.kind(), LexErrorKind::SourceMapFull);
+}
@@ -0,0 +1,158 @@
+use proc_macro0::{
+    quote, Delimiter, Group, Ident, Literal, Spacing, Span, ToTokens, TokenStream, TokenTree,
+};
+
+fn ident(name: &str) -> Ident {
+    Ident::new(name, Span::call_site())
+}
+
+#[test]
+fn basic() {
+    let tokens = quote! {
+        impl<'a, T: ?Sized> Trait for &'a T where T: Trait {
+            fn f(&self) -> Vec<u8> { vec![1, 2] }
+        }
+    };
+    let expected = "impl < 'a , T : ? Sized > Trait for & 'a T where T : Trait { \
+                    fn f (& self) -> Vec < u8 > { vec ! [1 , 2] } }";
+    assert_eq!(tokens.to_string(), expected);
+    assert!(quote!().is_empty());
+}
+
+#[test]
+fn spacing() {
+    let tokens = quote!(a += 'b' ::c => x.. 'l);
+    let puncts: Vec<(char, Spacing)> = tokens
+        .into_iter()
+        .filter_map(|tt| match tt {
+            TokenTree::Punct(punct) => Some((punct.as_char(), punct.spacing())),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(
+        puncts,
+        [
+            ('+', Spacing::Joint),
+            ('=', Spacing::Alone),
+            (':', Spacing::Joint),
+            (':', Spacing::Alone),
+            ('=', Spacing::Joint),
+            ('>', Spacing::Alone),
+            ('.', Spacing::Joint),
+            ('.', Spacing::Alone),
+            ('\'', Spacing::Joint),
+        ]
+    );
+}
+
+#[test]
+fn interpolation() {
+    let name = ident("S");
+    let ty = quote!(Vec<u8>);
+    let lit = Literal::string("s");
+    let group = Group::new(Delimiter::Bracket, quote!(derive));
+    let none: Option<Ident> = None;
+    let some = Some(ident("pub"));
+    let tokens = quote! {
+        ##group #some #none struct #name(#ty, #lit);
+    };
+    assert_eq!(
+        tokens.to_string(),
+        "# [derive] pub struct S (Vec < u8 > , \"s\") ;"
+    );
+}
+
+#[test]
+fn primitives() {
+    let (byte, long, float, ch, flag, text, string) =
+        (1u8, -2i64, 1.5f32, 'c', true, "s", String::from("t"));
+    let tokens = quote!(#byte #long #float #ch #flag #text #string);
+    assert_eq!(tokens.to_string(), "1u8 - 2i64 1.5f32 'c' true \"s\" \"t\"");
+    assert_eq!(false.to_token_stream().to_string(), "false");
+    assert_eq!(0usize.into_token_stream().to_string(), "0usize");
+}
+
+#[test]
+fn repetition() {
+    let fields = vec![ident("x"), ident("y")];
+    let types = [quote!(f32), quote!(f64)];
+    let name = ident("P");
+
+    let tokens = quote!(struct #name { #(#fields: #types),* });
+    assert_eq!(tokens.to_string(), "struct P { x : f32 , y : f64 }");
+
+    let tokens = quote!(#(let #fields = 0;)*);
+    assert_eq!(tokens.to_string(), "let x = 0 ; let y = 0 ;");
+
+    let tokens = quote!(#(#fields)+*);
+    assert_eq!(tokens.to_string(), "x + y");
+
+    let empty: Vec<Ident> = Vec::new();
+    assert!(quote!(#(#empty),*).is_empty());
+}
+
+#[test]
+fn repetition_iterators() {
+    let names = ["a", "b", "c"];
+    let idents = names.iter().map(|name| ident(name));
+    let values = 1..;
+    let tokens = quote!(#(#idents = #values;)*);
+    assert_eq!(tokens.to_string(), "a = 1i32 ; b = 2i32 ; c = 3i32 ;");
+
+    let optional = Some(ident("x"));
+    assert_eq!(quote!(#(#optional)*).to_string(), "x");
+}
+
+#[test]
+fn repetition_twice() {
+    let fields = vec![ident("x"), ident("y")];
+    let tokens = quote!(#(#fields: #fields),*);
+    assert_eq!(tokens.to_string(), "x : x , y : y");
+}
+
+#[test]
+fn repetition_nested() {
+    let rows = vec![vec![1u8, 2], vec![], vec![3]];
+    let tokens = quote!(#([#(#rows),*]);*);
+    assert_eq!(tokens.to_string(), "[1u8 , 2u8] ; [] ; [3u8]");
+}
+
+#[test]
+fn long() {
+    let x = ident("x");
+    let tokens = quote! {
+        #x #x #x #x #x #x #x #x #x #x #x #x #x #x #x #x #x #x #x #x #x #x #x #x #x
+        #x #x #x #x #x #x #x #x #x #x #x #x #x #x #x #x #x #x #x #x #x #x #x #x #x
+        #x #x #x #x #x #x #x #x #x #x #x #x #x #x #x #x #x #x #x #x #x #x #x #x #x
+        #x #x #x #x #x #x #x #x #x #x #x #x #x #x #x #x #x #x #x #x #x #x #x #x #x
+        #x #x #x #x #x #x #x #x #x #x #x #x #x #x #x #x #x #x #x #x #x #x #x #x #x
+        #x #x #x #x #x #x #x #x #x #x #x #x #x #x #x #x #x #x #x #x #x #x #x #x #x
+    };
+    assert_eq!(tokens.into_iter().count(), 150);
+}
+
+#[test]
+fn spans() {
+    let tokens = quote!(
+        fn f() {
+            "s"
+        }
+    );
+    let call_site = Span::call_site().source_file();
+    for tt in tokens {
+        assert_eq!(tt.span().source_file(), call_site);
+        if let TokenTree::Group(group) = tt {
+            for tt in group.stream() {
+                assert_eq!(tt.span().source_file(), call_site);
+            }
+        }
+    }
+}
+
+#[test]
+fn equals_parsed() {
+    let name = ident("f");
+    let quoted = quote!(pub fn #name(x: u8) -> Option<u8> { Some(x) });
+    let parsed: TokenStream = "pub fn f(x: u8) -> Option<u8> { Some(x) }".parse().unwrap();
+    assert_eq!(quoted, parsed);
+}
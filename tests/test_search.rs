@@ -0,0 +1,154 @@
+use proc_macro0::{Delimiter, Group, Match, Pattern, SourceMap, TokenStream, TokenTree};
+use std::str::FromStr;
+
+fn lex(src: &str) -> TokenStream {
+    TokenStream::from_str(src).unwrap()
+}
+
+fn pattern(src: &str) -> Pattern {
+    Pattern::from_str(src).unwrap()
+}
+
+fn found(tokens: &TokenStream, pattern: &Pattern) -> Vec<String> {
+    tokens
+        .find_all(pattern)
+        .iter()
+        .map(|found| found.tokens().to_string())
+        .collect()
+}
+
+#[test]
+fn macro_calls() {
+    let map = SourceMap::new();
+    let src = "fn f() {\n    foo!(1);\n    x.foo!();\n    if y { foo![foo!(2)] }\n}";
+    let tokens = map.parse_token_stream_named("lib.rs", src).unwrap();
+    let matches = tokens.find_all(&pattern("foo!$args"));
+    let args: Vec<String> = matches
+        .iter()
+        .map(|found| found.get("args").unwrap().to_string())
+        .collect();
+    assert_eq!(args, ["(1)", "()", "[foo ! (2)]", "(2)"]);
+
+    let paths: Vec<&[usize]> = matches.iter().map(Match::path).collect();
+    assert_eq!(paths, [&[3, 0][..], &[3, 6], &[3, 12, 0], &[3, 12, 2, 0]]);
+
+    let span = matches[2].span();
+    assert_eq!((span.start().line, span.start().column), (4, 11));
+    assert_eq!((span.end().line, span.end().column), (4, 24));
+    assert_eq!(span.source_file().path().to_str(), Some("lib.rs"));
+}
+
+#[test]
+fn derives() {
+    let tokens = lex("#[derive(Debug)] #[cfg(test)] #[derive(Clone, Copy)] struct S;");
+    let matches = tokens.find_all(&pattern("#[derive($..traits)]"));
+    let traits: Vec<String> = matches
+        .iter()
+        .map(|found| found.get("traits").unwrap().to_string())
+        .collect();
+    assert_eq!(traits, ["Debug", "Clone , Copy"]);
+}
+
+#[test]
+fn sequences() {
+    let tokens = lex("let a = 1; let b = 2 + 3; let c;");
+    let matches = tokens.find_all(&pattern("let $name = $..value;"));
+    let captures: Vec<Vec<(String, String)>> = matches
+        .iter()
+        .map(|found| {
+            found
+                .captures()
+                .map(|(name, tokens)| (name.to_owned(), tokens.to_string()))
+                .collect()
+        })
+        .collect();
+    assert_eq!(
+        captures,
+        [
+            [
+                ("name".to_owned(), "a".to_owned()),
+                ("value".to_owned(), "1".to_owned())
+            ],
+            [
+                ("name".to_owned(), "b".to_owned()),
+                ("value".to_owned(), "2 + 3".to_owned())
+            ],
+        ]
+    );
+
+    // Sequences match as little as possible, and never across a group.
+    assert_eq!(
+        found(&tokens, &pattern("let $.. ;")),
+        ["let a = 1 ;", "let b = 2 + 3 ;", "let c ;"]
+    );
+    assert_eq!(found(&tokens, &pattern("let $..")), ["let", "let", "let"]);
+    assert_eq!(
+        found(&lex("f(a, b) g()"), &pattern("$_($..)")),
+        ["f (a , b)", "g ()"]
+    );
+
+    // `$.._` is an uncaptured sequence followed by an identifier.
+    let tokens = lex("fn f() where T: Copy {}");
+    let matches = tokens.find_all(&pattern("fn $.._ where"));
+    assert_eq!(matches.len(), 1);
+    assert_eq!(matches[0].captures().count(), 0);
+}
+
+#[test]
+fn overlapping() {
+    let tokens = lex("a a a a a");
+    assert_eq!(found(&tokens, &pattern("a a")), ["a a", "a a"]);
+
+    let tokens = lex("(x (x))");
+    assert_eq!(found(&tokens, &pattern("($..)")), ["(x (x))", "(x)"]);
+    assert!(found(&tokens, &pattern("$..")).is_empty());
+    assert!(found(&tokens, &pattern("")).is_empty());
+}
+
+#[test]
+fn none_groups() {
+    let mut tokens = lex("a");
+    let group = Group::new(Delimiter::None, lex("+ b"));
+    tokens.extend(vec![TokenTree::Group(group)]);
+    tokens.extend(lex("(c)"));
+    assert_eq!(found(&tokens, &pattern("a + b")), ["a + b"]);
+    let matches = tokens.find_all(&pattern("b $x"));
+    assert_eq!(matches[0].get("x").unwrap().to_string(), "(c)");
+    assert_eq!(matches[0].path(), [2]);
+}
+
+#[test]
+fn tokens() {
+    // Punctuation alone in the pattern also matches joint punctuation.
+    let tokens = lex("a<-b a < b a+=b a+ =b");
+    assert_eq!(found(&tokens, &pattern("a<")), ["a <", "a <"]);
+    assert_eq!(found(&tokens, &pattern("a +=")), ["a +="]);
+
+    let tokens = lex("$x 'a' x 1u8 /// doc\n");
+    assert_eq!(found(&tokens, &pattern("$$ x")), ["$ x"]);
+    assert_eq!(found(&tokens, &pattern("'a'")), ["'a'"]);
+    assert_eq!(found(&tokens, &pattern("1u8")), ["1u8"]);
+    assert!(found(&tokens, &pattern("1")).is_empty());
+    assert_eq!(found(&tokens, &pattern("#[doc = $_]")).len(), 1);
+}
+
+#[test]
+fn invalid() {
+    let err = Pattern::from_str("a $").unwrap_err();
+    assert_eq!(err.to_string(), "expected `$$`, `$..`, `$_` or `$name`");
+    assert!(Pattern::from_str("$1").is_err());
+    let err = Pattern::from_str("$x ($x)").unwrap_err();
+    assert_eq!(err.to_string(), "`$x` is captured more than once");
+    assert!(Pattern::from_str("(").is_err());
+}
+
+#[test]
+fn large_input_without_matches() {
+    // Trying every way of splitting the tokens between the wildcards would
+    // take far too long here.
+    let tokens = lex(&"a (a) ".repeat(10_000));
+    assert!(found(&tokens, &pattern("$.._ a $.._ a $.._ b")).is_empty());
+    assert!(found(&tokens, &pattern("a $.._ ($.._ b) $_")).is_empty());
+    assert_eq!(found(&tokens, &pattern("$.._ (a) $.._ ;")).len(), 0);
+    assert_eq!(found(&tokens, &pattern("a $.._ (a)")).len(), 10_000);
+}
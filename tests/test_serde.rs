@@ -0,0 +1,116 @@
+#![cfg(feature = "serde")]
+
+use proc_macro0::{
+    Delimiter, DocComment, Ident, LexOptions, SerializeOptions, SourceMap, Spacing, Span,
+    TokenStream, TokenTree,
+};
+use serde::Serialize;
+use std::str::FromStr;
+
+#[test]
+fn roundtrip() {
+    let src = "#[derive(Debug)] struct S<'a> { r#type: &'a [u8; 2], x: (f32, char) } \
+               const C: &str = r#\"\"# ; 'x'; b\"\\xff\" 1e3f64 -1i8 a+=b";
+    let tokens = TokenStream::from_str(src).unwrap();
+    for options in &[SerializeOptions::new(), SerializeOptions::new().spans(true)] {
+        let json = serde_json::to_string(&options.serializable(&tokens)).unwrap();
+        let deserialized: TokenStream = serde_json::from_str(&json).unwrap();
+        assert_eq!(deserialized.to_string(), tokens.to_string());
+    }
+}
+
+#[test]
+fn roundtrip_doc_comments() {
+    let src = "//! inner\n/// outer\nfn f() {} /** block */ struct S;";
+    let options = LexOptions::new().keep_doc_comments(true);
+    let tokens = TokenStream::from_str_with(src, &options).unwrap();
+    let json = serde_json::to_string(&tokens).unwrap();
+    let deserialized: TokenStream = serde_json::from_str(&json).unwrap();
+    assert_eq!(deserialized.to_string(), tokens.to_string());
+}
+
+#[test]
+fn format() {
+    let tokens = TokenStream::from_str("f(x, 'a')").unwrap();
+    assert_eq!(
+        serde_json::to_string(&tokens).unwrap(),
+        concat!(
+            r#"[{"Ident":{"name":"f","span":null}},"#,
+            r#"{"Group":{"delimiter":"Parenthesis","stream":["#,
+            r#"{"Ident":{"name":"x","span":null}},"#,
+            r#"{"Punct":{"ch":",","spacing":"Alone","span":null}},"#,
+            r#"{"Literal":{"repr":"'a'","span":null}}"#,
+            r#"],"span":null}}]"#,
+        ),
+    );
+    assert_eq!(
+        serde_json::to_string(&Delimiter::Bracket).unwrap(),
+        r#""Bracket""#
+    );
+    assert_eq!(
+        serde_json::to_string(&Spacing::Joint).unwrap(),
+        r#""Joint""#
+    );
+}
+
+#[test]
+fn spans() {
+    let map = SourceMap::new();
+    let tokens = map
+        .parse_token_stream_named("src/lib.rs", "fn f() {\n    x\n}")
+        .unwrap();
+    let options = SerializeOptions::new().spans(true);
+    let value = serde_json::to_value(options.serializable(&tokens)).unwrap();
+    let ident = &value[3]["Group"]["stream"][0]["Ident"];
+    assert_eq!(ident["name"], "x");
+    assert_eq!(ident["span"]["file"], "src/lib.rs");
+    assert_eq!(ident["span"]["start"]["line"], 2);
+    assert_eq!(ident["span"]["start"]["column"], 4);
+    assert_eq!(ident["span"]["end"]["column"], 5);
+
+    // Deserialized tokens do not point back into the source.
+    let deserialized: TokenStream = serde_json::from_value(value).unwrap();
+    let span = deserialized.into_iter().next().unwrap().span();
+    assert_eq!(
+        span.source_file().path(),
+        Span::call_site().source_file().path()
+    );
+
+    // Serializing without the wrapper drops spans again.
+    let value = serde_json::to_value(&tokens).unwrap();
+    assert!(value[0]["Ident"]["span"].is_null());
+}
+
+#[test]
+fn nested() {
+    #[derive(Serialize)]
+    struct Cache {
+        name: &'static str,
+        tokens: Vec<TokenStream>,
+    }
+
+    let map = SourceMap::new();
+    let cache = Cache {
+        name: "cache",
+        tokens: vec![map.parse_token_stream_named("a.rs", "a").unwrap()],
+    };
+    let options = SerializeOptions::new().spans(true);
+    let value = serde_json::to_value(options.serializable(&cache)).unwrap();
+    assert_eq!(value["tokens"][0][0]["Ident"]["span"]["file"], "a.rs");
+}
+
+#[test]
+fn invalid() {
+    let json = r#"[{"Ident":{"name":"a b","span":null}}]"#;
+    assert!(serde_json::from_str::<TokenStream>(json).is_err());
+    let json = r#"{"Punct":{"ch":"a","spacing":"Alone","span":null}}"#;
+    assert!(serde_json::from_str::<TokenTree>(json).is_err());
+    let json = r#"{"Literal":{"repr":"x","span":null}}"#;
+    assert!(serde_json::from_str::<TokenTree>(json).is_err());
+    let json = r#"{"style":"Outer","kind":"Line","text":"a\nb","span":null}"#;
+    assert!(serde_json::from_str::<DocComment>(json).is_err());
+
+    let json = r#"{"name":"r#raw","span":null}"#;
+    let ident: Ident = serde_json::from_str(json).unwrap();
+    assert_eq!(ident.to_string(), "r#raw");
+}
@@ -0,0 +1,201 @@
+use proc_macro0::visit::{self, Fold, Visit, VisitMut};
+use proc_macro0::{
+    Delimiter, Group, Ident, LexOptions, Literal, SourceMap, Span, TokenStream, TokenTree,
+};
+use std::str::FromStr;
+
+fn lex(src: &str) -> TokenStream {
+    TokenStream::from_str(src).unwrap()
+}
+
+#[derive(Default)]
+struct Count {
+    groups: usize,
+    idents: Vec<String>,
+    spans: Vec<(usize, usize)>,
+}
+
+impl Visit for Count {
+    fn visit_group(&mut self, group: &Group) {
+        self.groups += 1;
+        visit::visit_group(self, group);
+    }
+
+    fn visit_ident(&mut self, ident: &Ident) {
+        self.idents.push(ident.to_string());
+        visit::visit_ident(self, ident);
+    }
+
+    fn visit_span(&mut self, span: Span) {
+        self.spans.push((span.start().line, span.start().column));
+    }
+}
+
+#[test]
+fn visit_nested() {
+    let map = SourceMap::new();
+    let tokens = map
+        .parse_token_stream("fn f(x: u8) {\n    g([x, 1])\n}")
+        .unwrap();
+    let mut count = Count::default();
+    count.visit_token_stream(&tokens);
+    assert_eq!(count.groups, 4);
+    assert_eq!(count.idents, ["fn", "f", "x", "u8", "g", "x"]);
+    assert_eq!(
+        count.spans,
+        [
+            (1, 0),
+            (1, 3),
+            (1, 4),
+            (1, 5),
+            (1, 6),
+            (1, 8),
+            (1, 12),
+            (2, 4),
+            (2, 5),
+            (2, 6),
+            (2, 7),
+            (2, 8),
+            (2, 10),
+        ]
+    );
+}
+
+#[test]
+fn visit_none_groups() {
+    let inner = Group::new(Delimiter::None, lex("a b"));
+    let tokens: TokenStream = vec![
+        TokenTree::Group(inner),
+        Ident::new("c", Span::call_site()).into(),
+    ]
+    .into_iter()
+    .collect();
+    let mut count = Count::default();
+    count.visit_token_stream(&tokens);
+    assert_eq!(count.groups, 1);
+    assert_eq!(count.idents, ["a", "b", "c"]);
+}
+
+struct Rename<'a> {
+    from: &'a str,
+    to: &'a str,
+}
+
+impl VisitMut for Rename<'_> {
+    fn visit_ident_mut(&mut self, ident: &mut Ident) {
+        if ident == self.from {
+            *ident = Ident::new(self.to, ident.span());
+        }
+    }
+}
+
+#[test]
+fn rename() {
+    let mut tokens = lex("let x = { let y = [x]; (x, y) };");
+    let spans: Vec<Span> = tokens.clone().into_iter().map(|tt| tt.span()).collect();
+    Rename { from: "x", to: "z" }.visit_token_stream_mut(&mut tokens);
+    assert_eq!(tokens.to_string(), "let z = { let y = [z] ; (z , y) } ;");
+    let renamed: Vec<Span> = tokens.into_iter().map(|tt| tt.span()).collect();
+    assert_eq!(renamed.len(), spans.len());
+    for (a, b) in spans.iter().zip(&renamed) {
+        assert_eq!(a.start(), b.start());
+        assert_eq!(a.end(), b.end());
+    }
+}
+
+struct CallSite;
+
+impl VisitMut for CallSite {
+    fn visit_span_mut(&mut self, span: &mut Span) {
+        *span = Span::call_site();
+    }
+}
+
+#[test]
+fn rewrite_spans() {
+    let map = SourceMap::new();
+    let options = LexOptions::new().preserve_trivia(true);
+    let mut tokens = map
+        .parse_token_stream_with("f( /* a */ x, [y] ) // end", &options)
+        .unwrap();
+    CallSite.visit_token_stream_mut(&mut tokens);
+
+    let call_site = Span::call_site().source_file();
+    let trees: Vec<TokenTree> = tokens.clone().into_iter().collect();
+    let group = match &trees[1] {
+        TokenTree::Group(group) => group,
+        tt => panic!("expected a group, got {}", tt),
+    };
+    assert_eq!(group.delimiter(), Delimiter::Parenthesis);
+    assert_eq!(group.span_open().source_file(), call_site);
+    assert_eq!(group.span_close().source_file(), call_site);
+    assert_eq!(group.stream().trailing_trivia(), Some(" "));
+    let inner: Vec<TokenTree> = group.stream().into_iter().collect();
+    assert_eq!(inner[0].leading_trivia(), Some(" /* a */ "));
+    for tt in &inner {
+        assert_eq!(tt.span().source_file(), call_site);
+    }
+    match &inner[2] {
+        TokenTree::Group(group) => {
+            assert_eq!(group.delimiter(), Delimiter::Bracket);
+            assert_eq!(group.span().source_file(), call_site);
+        }
+        tt => panic!("expected a group, got {}", tt),
+    }
+    assert_eq!(tokens.trailing_trivia(), Some(" // end"));
+}
+
+struct Double;
+
+impl Fold for Double {
+    fn fold_literal(&mut self, literal: Literal) -> Literal {
+        let value: u32 = literal.to_string().parse().unwrap();
+        let mut doubled = Literal::u32_unsuffixed(value * 2);
+        doubled.set_span(literal.span());
+        doubled
+    }
+}
+
+#[test]
+fn fold_literals() {
+    let tokens = lex("f(1, [2, {3}])");
+    let folded = Double.fold_token_stream(tokens);
+    assert_eq!(folded.to_string(), "f (2 , [4 , { 6 }])");
+}
+
+struct StripCommas;
+
+impl Fold for StripCommas {
+    fn fold_token_stream(&mut self, stream: TokenStream) -> TokenStream {
+        stream
+            .into_iter()
+            .filter(|tt| !matches!(tt, TokenTree::Punct(punct) if punct.as_char() == ','))
+            .map(|tt| self.fold_token_tree(tt))
+            .collect()
+    }
+}
+
+#[test]
+fn fold_streams() {
+    let tokens = lex("(a, b, [c, d]), e");
+    let folded = StripCommas.fold_token_stream(tokens);
+    assert_eq!(folded.to_string(), "(a b [c d]) e");
+}
+
+struct Nothing;
+
+impl VisitMut for Nothing {}
+
+impl Fold for Nothing {}
+
+#[test]
+fn keep_doc_comments() {
+    let options = LexOptions::new().preserve_trivia(true);
+    let src =
+        "//! Crate.\n\n/// Hello\n/** Block */\nfn f() {\n    /// Nested\n    let x = 1;\n}\n";
+    let mut tokens = TokenStream::from_str_with(src, &options).unwrap();
+    Nothing.visit_token_stream_mut(&mut tokens);
+    assert_eq!(tokens.to_string(), src);
+    let tokens = Nothing.fold_token_stream(tokens);
+    assert_eq!(tokens.to_string(), src);
+}